@@ -1,35 +1,444 @@
+use crate::error::RustscriberError;
 use cpal::traits::DeviceTrait;
-use cpal::{Device, SampleFormat, SupportedStreamConfig};
+use cpal::{
+    BufferSize, Device, SampleFormat, StreamConfig, SupportedBufferSize, SupportedStreamConfigRange,
+};
 
-const PREFERRED_RATES: [u32; 3] = [16000, 44100, 48000];
-const MAX_RATE: u32 = 48000;
+/// Rate the model runs at; anything else has to be resampled
+const ASR_RATE: u32 = 16000;
+/// Rates tried within each supported range, besides the range's own bounds
+const CANDIDATE_RATES: [u32; 6] = [16000, 32000, 48000, 96000, 44100, 22050];
+
+const ALL_FORMATS: [SampleFormat; 12] = [
+    SampleFormat::I8,
+    SampleFormat::I16,
+    SampleFormat::I24,
+    SampleFormat::I32,
+    SampleFormat::I64,
+    SampleFormat::U8,
+    SampleFormat::U16,
+    SampleFormat::U24,
+    SampleFormat::U32,
+    SampleFormat::U64,
+    SampleFormat::F32,
+    SampleFormat::F64,
+];
+
+/// Constraints on the input config from the command line. Anything left
+/// unset is decided by scoring.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_format: Option<SampleFormat>,
+    /// Frames per callback; the backend default if unset
+    pub buffer_size: Option<u32>,
+    /// Fewest channels the channel selection reads from, e.g. 3 for
+    /// `--channel 3`
+    pub min_channels: u16,
+    /// Each channel becomes its own pipeline, so more channels are better
+    pub split_channels: bool,
+}
+
+impl ConfigOverrides {
+    fn allows(&self, range: &SupportedStreamConfigRange) -> bool {
+        let buffer_ok = match (self.buffer_size, *range.buffer_size()) {
+            (Some(size), SupportedBufferSize::Range { min, max }) => (min..=max).contains(&size),
+            // Unknown ranges are left for the backend to accept or reject
+            _ => true,
+        };
+        buffer_ok
+            && range.channels() >= self.min_channels
+            && self.channels.is_none_or(|c| c == range.channels())
+            && self
+                .sample_format
+                .is_none_or(|f| f == range.sample_format())
+            && self
+                .sample_rate
+                .is_none_or(|r| (range.min_sample_rate()..=range.max_sample_rate()).contains(&r))
+    }
+
+    fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(rate) = self.sample_rate {
+            parts.push(format!("{} Hz", rate));
+        }
+        if let Some(channels) = self.channels {
+            parts.push(format!("{} channel(s)", channels));
+        } else if self.min_channels > 1 {
+            parts.push(format!("at least {} channels", self.min_channels));
+        }
+        if let Some(format) = self.sample_format {
+            parts.push(format.to_string());
+        }
+        if let Some(size) = self.buffer_size {
+            parts.push(format!("{}-frame buffers", size));
+        }
+        parts.join(", ")
+    }
+}
+
+/// The input config picked for a device, and why it won.
+#[derive(Debug)]
+pub struct SelectedConfig {
+    pub config: StreamConfig,
+    pub sample_format: SampleFormat,
+    pub reason: String,
+}
+
+/// Parse a sample format name as printed by `devices` (e.g. `f32`, `i16`).
+pub fn parse_sample_format(s: &str) -> Result<SampleFormat, String> {
+    ALL_FORMATS
+        .into_iter()
+        .find(|f| f.to_string().eq_ignore_ascii_case(s))
+        .ok_or_else(|| {
+            let names: Vec<String> = ALL_FORMATS.iter().map(|f| f.to_string()).collect();
+            format!(
+                "Unknown sample format: {} (expected one of {})",
+                s,
+                names.join(", ")
+            )
+        })
+}
 
 pub fn select_input_config(
     device: &Device,
-) -> Result<(SupportedStreamConfig, SampleFormat), Box<dyn std::error::Error>> {
-    let supported = device.supported_input_configs()?;
-
-    // Try each config range against our preferred rates
+    overrides: &ConfigOverrides,
+) -> Result<SelectedConfig, RustscriberError> {
     let configs: Vec<_> = device.supported_input_configs()?.collect();
-    drop(supported);
+    choose_config(&configs, overrides).map_err(RustscriberError::ConfigNegotiation)
+}
+
+/// Score every rate worth trying in every range that `overrides` allows, and
+/// pick the best. Ties go to the earlier range, i.e. the backend's order.
+fn choose_config(
+    configs: &[SupportedStreamConfigRange],
+    overrides: &ConfigOverrides,
+) -> Result<SelectedConfig, String> {
+    if configs.is_empty() {
+        return Err("No supported input configs found".to_string());
+    }
 
-    for &rate in &PREFERRED_RATES {
-        for range in &configs {
-            if let Some(config) = range.clone().try_with_sample_rate(rate) {
-                let fmt = config.sample_format();
-                return Ok((config, fmt));
+    let mut best: Option<(Score, &SupportedStreamConfigRange, u32)> = None;
+    for range in configs.iter().filter(|range| overrides.allows(range)) {
+        for rate in candidate_rates(range, overrides) {
+            let score = score(
+                range.channels(),
+                rate,
+                range.sample_format(),
+                overrides.split_channels,
+            );
+            if best.as_ref().is_none_or(|(b, _, _)| score.total > b.total) {
+                best = Some((score, range, rate));
             }
         }
     }
 
-    // Fallback: use first config, capped at MAX_RATE
-    let range = configs
+    let Some((score, range, rate)) = best else {
+        let available: Vec<String> = configs
+            .iter()
+            .map(|c| {
+                format!(
+                    "{}ch {}..{} Hz {}",
+                    c.channels(),
+                    c.min_sample_rate(),
+                    c.max_sample_rate(),
+                    c.sample_format()
+                )
+            })
+            .collect();
+        return Err(format!(
+            "No supported input config matches {}. The device offers: {}",
+            overrides.describe(),
+            available.join("; ")
+        ));
+    };
+
+    let mut reason = score.reasons.join("; ");
+    if !overrides.describe().is_empty() {
+        reason.push_str(&format!(" (requested: {})", overrides.describe()));
+    }
+
+    Ok(SelectedConfig {
+        config: StreamConfig {
+            channels: range.channels(),
+            sample_rate: rate,
+            buffer_size: overrides
+                .buffer_size
+                .map_or(BufferSize::Default, BufferSize::Fixed),
+        },
+        sample_format: range.sample_format(),
+        reason,
+    })
+}
+
+fn candidate_rates(range: &SupportedStreamConfigRange, overrides: &ConfigOverrides) -> Vec<u32> {
+    if let Some(rate) = overrides.sample_rate {
+        return vec![rate];
+    }
+    let (min, max) = (range.min_sample_rate(), range.max_sample_rate());
+    CANDIDATE_RATES
         .into_iter()
-        .next()
-        .ok_or("No supported input configs found")?;
+        .filter(|rate| (min..=max).contains(rate))
+        .chain([min, max])
+        .collect()
+}
+
+struct Score {
+    total: i32,
+    reasons: Vec<String>,
+}
+
+/// Rate matters most (resampling costs CPU and accuracy), then sample format,
+/// then channel count (downmixing is cheap). When splitting, channel count
+/// comes first: a channel left out is a speaker left out.
+fn score(channels: u16, rate: u32, format: SampleFormat, split_channels: bool) -> Score {
+    let (rate_score, rate_reason) = if rate == ASR_RATE {
+        (
+            100,
+            format!("{} Hz is the model's rate, no resampling", rate),
+        )
+    } else if rate > ASR_RATE && rate.is_multiple_of(ASR_RATE) {
+        let factor = (rate / ASR_RATE) as i32;
+        (
+            80 - 5 * (factor - 1),
+            format!("{} Hz resamples to 16 kHz by an integer factor", rate),
+        )
+    } else if rate > ASR_RATE {
+        (
+            40 - (rate / ASR_RATE) as i32,
+            format!("{} Hz needs fractional resampling", rate),
+        )
+    } else {
+        (-50, format!("{} Hz is below 16 kHz and loses detail", rate))
+    };
+
+    let (format_score, format_reason) = match format {
+        SampleFormat::F32 => (30, "no conversion needed"),
+        SampleFormat::I16 => (28, "plenty for speech"),
+        SampleFormat::I24 | SampleFormat::I32 | SampleFormat::F64 => {
+            (20, "more precision than speech needs")
+        }
+        SampleFormat::I8 | SampleFormat::U8 => (0, "8-bit is noisy"),
+        _ => (10, "uncommon sample format"),
+    };
+
+    let (channel_score, channel_reason) = match channels {
+        n if split_channels => (200 * n as i32, format!("{} channels to split", n)),
+        1 => (20, "mono, no downmix".to_string()),
+        n => (20 - 10 * (n as i32 - 1), format!("{} channels", n)),
+    };
+
+    Score {
+        total: rate_score + format_score + channel_score,
+        reasons: vec![
+            rate_reason,
+            format!("{} ({})", format, format_reason),
+            channel_reason,
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(
+        channels: u16,
+        min: u32,
+        max: u32,
+        format: SampleFormat,
+    ) -> SupportedStreamConfigRange {
+        SupportedStreamConfigRange::new(
+            channels,
+            min,
+            max,
+            SupportedBufferSize::Range { min: 64, max: 4096 },
+            format,
+        )
+    }
+
+    fn choose(configs: &[SupportedStreamConfigRange]) -> SelectedConfig {
+        choose_config(configs, &ConfigOverrides::default()).unwrap()
+    }
+
+    #[test]
+    fn prefers_16k_mono() {
+        let selected = choose(&[
+            range(2, 44100, 48000, SampleFormat::F32),
+            range(1, 8000, 96000, SampleFormat::I16),
+        ]);
+        assert_eq!(selected.config.sample_rate, 16000);
+        assert_eq!(selected.config.channels, 1);
+        assert_eq!(selected.sample_format, SampleFormat::I16);
+        assert!(selected.reason.contains("no resampling"));
+    }
+
+    #[test]
+    fn prefers_integer_multiple_of_16k() {
+        let selected = choose(&[
+            range(1, 44100, 44100, SampleFormat::F32),
+            range(1, 48000, 48000, SampleFormat::F32),
+        ]);
+        assert_eq!(selected.config.sample_rate, 48000);
+    }
+
+    #[test]
+    fn prefers_lowest_integer_multiple_in_range() {
+        let selected = choose(&[range(1, 32000, 192000, SampleFormat::F32)]);
+        assert_eq!(selected.config.sample_rate, 32000);
+    }
+
+    #[test]
+    fn prefers_float_and_16_bit_over_8_bit() {
+        let selected = choose(&[
+            range(1, 16000, 16000, SampleFormat::U8),
+            range(1, 16000, 16000, SampleFormat::I16),
+        ]);
+        assert_eq!(selected.sample_format, SampleFormat::I16);
+
+        let selected = choose(&[
+            range(1, 16000, 16000, SampleFormat::I16),
+            range(1, 16000, 16000, SampleFormat::F32),
+        ]);
+        assert_eq!(selected.sample_format, SampleFormat::F32);
+    }
+
+    #[test]
+    fn prefers_fewest_channels() {
+        let selected = choose(&[
+            range(8, 48000, 48000, SampleFormat::F32),
+            range(2, 48000, 48000, SampleFormat::F32),
+            range(4, 48000, 48000, SampleFormat::F32),
+        ]);
+        assert_eq!(selected.config.channels, 2);
+    }
+
+    #[test]
+    fn keeps_the_channels_a_selection_reads() {
+        // Typical ALSA/Pulse ranges: any channel count at any rate
+        let configs = [
+            range(1, 8000, 96000, SampleFormat::F32),
+            range(2, 8000, 96000, SampleFormat::F32),
+            range(4, 8000, 96000, SampleFormat::F32),
+        ];
+        // --channel 2, or --channels 1,2
+        let overrides = ConfigOverrides {
+            min_channels: 2,
+            ..Default::default()
+        };
+        let selected = choose_config(&configs, &overrides).unwrap();
+        assert_eq!(selected.config.channels, 2);
+        assert_eq!(selected.config.sample_rate, 16000);
 
-    let capped_rate = range.max_sample_rate().min(MAX_RATE);
-    let config = range.with_sample_rate(capped_rate);
-    let fmt = config.sample_format();
-    Ok((config, fmt))
+        // --channels 1,3
+        let overrides = ConfigOverrides {
+            min_channels: 3,
+            ..Default::default()
+        };
+        assert_eq!(
+            choose_config(&configs, &overrides).unwrap().config.channels,
+            4
+        );
+
+        let overrides = ConfigOverrides {
+            min_channels: 6,
+            ..Default::default()
+        };
+        let err = choose_config(&configs, &overrides).unwrap_err();
+        assert!(err.contains("at least 6 channels"));
+    }
+
+    #[test]
+    fn splitting_takes_every_channel() {
+        let configs = [
+            range(1, 16000, 16000, SampleFormat::F32),
+            range(2, 16000, 16000, SampleFormat::F32),
+            range(4, 48000, 48000, SampleFormat::I16),
+        ];
+        let overrides = ConfigOverrides {
+            split_channels: true,
+            ..Default::default()
+        };
+        let selected = choose_config(&configs, &overrides).unwrap();
+        assert_eq!(selected.config.channels, 4);
+        assert!(selected.reason.contains("4 channels to split"));
+    }
+
+    #[test]
+    fn avoids_rates_below_16k() {
+        let selected = choose(&[
+            range(1, 8000, 11025, SampleFormat::F32),
+            range(2, 44100, 44100, SampleFormat::I16),
+        ]);
+        assert_eq!(selected.config.sample_rate, 44100);
+    }
+
+    #[test]
+    fn overrides_restrict_candidates() {
+        let configs = [
+            range(1, 16000, 48000, SampleFormat::F32),
+            range(2, 16000, 48000, SampleFormat::I16),
+        ];
+        let overrides = ConfigOverrides {
+            sample_rate: Some(48000),
+            channels: Some(2),
+            sample_format: None,
+            buffer_size: Some(256),
+            ..Default::default()
+        };
+        let selected = choose_config(&configs, &overrides).unwrap();
+        assert_eq!(selected.config.channels, 2);
+        assert_eq!(selected.config.sample_rate, 48000);
+        assert_eq!(selected.config.buffer_size, BufferSize::Fixed(256));
+        assert_eq!(selected.sample_format, SampleFormat::I16);
+        assert!(selected.reason.contains("requested"));
+    }
+
+    #[test]
+    fn unsatisfiable_overrides_are_an_error() {
+        let configs = [range(1, 16000, 48000, SampleFormat::F32)];
+
+        let overrides = ConfigOverrides {
+            sample_rate: Some(96000),
+            ..Default::default()
+        };
+        let err = choose_config(&configs, &overrides).unwrap_err();
+        assert!(err.contains("96000 Hz"));
+        assert!(err.contains("1ch 16000..48000 Hz f32"));
+
+        let overrides = ConfigOverrides {
+            buffer_size: Some(8192),
+            ..Default::default()
+        };
+        assert!(choose_config(&configs, &overrides).is_err());
+    }
+
+    #[test]
+    fn unknown_buffer_range_accepts_any_size() {
+        let configs = [SupportedStreamConfigRange::new(
+            1,
+            16000,
+            16000,
+            SupportedBufferSize::Unknown,
+            SampleFormat::F32,
+        )];
+        let overrides = ConfigOverrides {
+            buffer_size: Some(8192),
+            ..Default::default()
+        };
+        assert!(choose_config(&configs, &overrides).is_ok());
+    }
+
+    #[test]
+    fn no_configs_is_an_error() {
+        assert!(choose_config(&[], &ConfigOverrides::default()).is_err());
+    }
+
+    #[test]
+    fn parses_sample_formats() {
+        assert_eq!(parse_sample_format("f32"), Ok(SampleFormat::F32));
+        assert_eq!(parse_sample_format("I24"), Ok(SampleFormat::I24));
+        assert!(parse_sample_format("f16").is_err());
+    }
 }
@@ -0,0 +1,350 @@
+//! Measuring whether this machine can keep up with live audio: the full file
+//! pipeline, timed chunk by chunk.
+
+use crate::error::RustscriberError;
+use crate::eval::ReportFormat;
+use crate::execution;
+use crate::file_transcriber::{AudioFile, FileTranscriber};
+use crate::pipeline::{ChunkTiming, ChunkTimings};
+use serde::Serialize;
+use std::f64::consts::TAU;
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often the thread count is sampled
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+/// Linux reports CPU time in these ticks per second (USER_HZ)
+const CLOCK_TICKS: f64 = 100.0;
+
+/// A stand-in for speech on every channel: a voiced buzz with some breath
+/// noise, shaped into syllables at 4 Hz, with a pause every few seconds.
+/// Deterministic, so runs compare.
+pub fn synthetic(secs: f64, sample_rate: u32, channels: u16) -> AudioFile {
+    let frames = (secs * sample_rate as f64) as usize;
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut samples = Vec::with_capacity(frames * channels as usize);
+    for frame in 0..frames {
+        let t = frame as f64 / sample_rate as f64;
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let noise = (state >> 40) as f64 / (1u64 << 24) as f64 * 2.0 - 1.0;
+        let envelope = if t % 3.0 > 2.3 {
+            0.0
+        } else {
+            0.5 * (1.0 - (TAU * 4.0 * t).cos())
+        };
+        let voice = 0.3 * (TAU * 140.0 * t).sin() + 0.15 * (TAU * 280.0 * t).sin();
+        let sample = (envelope * (voice + 0.1 * noise) * 0.5) as f32;
+        samples.extend(std::iter::repeat_n(sample, channels as usize));
+    }
+    AudioFile {
+        name: "synthetic signal".to_string(),
+        samples,
+        channels,
+        sample_rate,
+    }
+}
+
+/// Summary of a set of durations, in milliseconds.
+#[derive(Default, Serialize)]
+pub struct Percentiles {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    /// Nearest-rank percentiles of `secs`, converted to milliseconds.
+    fn of(mut secs: Vec<f64>) -> Self {
+        if secs.is_empty() {
+            return Self::default();
+        }
+        secs.sort_by(f64::total_cmp);
+        let rank = |p: f64| secs[((p * secs.len() as f64).ceil() as usize).max(1) - 1] * 1000.0;
+        Self {
+            mean: secs.iter().sum::<f64>() / secs.len() as f64 * 1000.0,
+            p50: rank(0.5),
+            p90: rank(0.9),
+            p99: rank(0.99),
+            max: secs[secs.len() - 1] * 1000.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "mean {:.0} ms, p50 {:.0} ms, p90 {:.0} ms, p99 {:.0} ms, max {:.0} ms",
+            self.mean, self.p50, self.p90, self.p99, self.max
+        )
+    }
+}
+
+/// How long after its first sample each chunk's text would appear if the
+/// audio had arrived live: a chunk can't start before its last sample has
+/// been captured or before the previous chunk is done, and then takes as
+/// long as it did here. The cost of a chunk is the time since the one before
+/// it finished, so resampling and the other stages are included. Flush
+/// padding at the end of the file isn't counted.
+fn live_latencies(timings: &[ChunkTiming], started: Instant) -> Vec<f64> {
+    let mut sources: Vec<usize> = timings.iter().map(|t| t.source).collect();
+    sources.sort_unstable();
+    sources.dedup();
+
+    let mut latencies = Vec::with_capacity(timings.len());
+    for source in sources {
+        let mut previous = started;
+        let mut done = 0.0;
+        for timing in timings.iter().filter(|t| t.source == source) {
+            let cost = timing.finished.duration_since(previous).as_secs_f64();
+            previous = timing.finished;
+            done = f64::max(done, timing.end) + cost;
+            if timing.end > timing.start {
+                latencies.push(done - timing.start);
+            }
+        }
+    }
+    latencies
+}
+
+/// A line of /proc/self/status, e.g. `Threads` or `VmHWM` (in kB).
+fn proc_status(field: &str) -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| {
+        line.strip_prefix(field)
+            .is_some_and(|rest| rest.starts_with(':'))
+    })?;
+    line[field.len() + 1..]
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// User and system CPU time the process has used, in seconds.
+fn cpu_secs() -> Option<f64> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // Fields after the parenthesised command name start at the state (3rd)
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let utime: f64 = fields.get(11)?.parse().ok()?;
+    let stime: f64 = fields.get(12)?.parse().ok()?;
+    Some((utime + stime) / CLOCK_TICKS)
+}
+
+/// Watches the process's threads and CPU time while a benchmark runs. Only
+/// Linux exposes these through /proc; elsewhere nothing is measured.
+struct ResourceMonitor {
+    stop: Arc<AtomicBool>,
+    sampler: JoinHandle<Option<u64>>,
+    cpu_start: Option<f64>,
+}
+
+struct Resources {
+    peak_memory_mb: Option<f64>,
+    peak_threads: Option<u64>,
+    cpu_cores: Option<f64>,
+}
+
+impl ResourceMonitor {
+    fn start() -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let sampler = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut peak = proc_status("Threads");
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(SAMPLE_INTERVAL);
+                    peak = peak.max(proc_status("Threads"));
+                }
+                // Not counting this sampling thread
+                peak.map(|peak| peak - 1)
+            })
+        };
+        Self {
+            stop,
+            sampler,
+            cpu_start: cpu_secs(),
+        }
+    }
+
+    fn finish(self, wall: Duration) -> Resources {
+        self.stop.store(true, Ordering::Relaxed);
+        let peak_threads = self.sampler.join().ok().flatten();
+        let cpu = cpu_secs()
+            .zip(self.cpu_start)
+            .map(|(end, start)| end - start);
+        Resources {
+            peak_memory_mb: proc_status("VmHWM").map(|kb| kb as f64 / 1024.0),
+            peak_threads,
+            cpu_cores: cpu.map(|cpu| cpu / wall.as_secs_f64().max(f64::EPSILON)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BenchReport {
+    pub source: String,
+    pub audio_secs: f64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub pipelines: usize,
+    /// The ONNX Runtime execution provider the model ran on
+    pub execution_provider: &'static str,
+    /// 0 when ONNX Runtime chose
+    pub intra_threads: usize,
+    pub inter_threads: usize,
+    pub graph_optimization: &'static str,
+    pub model_load_secs: f64,
+    /// Time to run the audio through, not counting loading the model
+    pub wall_secs: f64,
+    pub real_time_factor: f64,
+    pub keeps_up: bool,
+    pub chunks: usize,
+    /// Time spent in the model on each chunk
+    pub inference_ms: Percentiles,
+    /// From a chunk's first sample being captured to its text being
+    /// emitted, as if the audio had arrived live
+    pub latency_ms: Percentiles,
+    /// Peak resident memory of the whole process, models included
+    pub peak_memory_mb: Option<f64>,
+    pub peak_threads: Option<u64>,
+    /// Average number of cores busy while the audio ran through
+    pub cpu_cores: Option<f64>,
+}
+
+/// Load the models `audio` needs, then run it through `transcriber`, timing
+/// every chunk.
+pub fn measure(
+    transcriber: &mut FileTranscriber,
+    audio: &AudioFile,
+) -> Result<BenchReport, RustscriberError> {
+    let pipelines = transcriber.pipelines(audio);
+    let loading = Instant::now();
+    transcriber.load_models(pipelines)?;
+    let model_load = loading.elapsed();
+
+    let timings = ChunkTimings::default();
+    transcriber.timed(timings.clone());
+    let monitor = ResourceMonitor::start();
+    let started = Instant::now();
+    let result = transcriber.transcribe(audio);
+    let wall = started.elapsed();
+    let resources = monitor.finish(wall);
+    result?;
+
+    let timings = std::mem::take(&mut *timings.lock().unwrap());
+    let audio_secs = audio.duration_secs();
+    let real_time_factor = wall.as_secs_f64() / audio_secs.max(f64::EPSILON);
+    Ok(BenchReport {
+        source: audio.name.clone(),
+        audio_secs,
+        sample_rate: audio.sample_rate,
+        channels: audio.channels,
+        pipelines,
+        execution_provider: transcriber.provider(),
+        intra_threads: transcriber.runtime().intra_threads,
+        inter_threads: transcriber.runtime().inter_threads,
+        graph_optimization: transcriber.runtime().optimization.name(),
+        model_load_secs: model_load.as_secs_f64(),
+        wall_secs: wall.as_secs_f64(),
+        real_time_factor,
+        keeps_up: real_time_factor < 1.0,
+        chunks: timings.len(),
+        inference_ms: Percentiles::of(timings.iter().map(|t| t.inference.as_secs_f64()).collect()),
+        latency_ms: Percentiles::of(live_latencies(&timings, started)),
+        peak_memory_mb: resources.peak_memory_mb,
+        peak_threads: resources.peak_threads,
+        cpu_cores: resources.cpu_cores,
+    })
+}
+
+impl BenchReport {
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Json => {
+                let mut json = serde_json::to_string_pretty(self).unwrap_or_default();
+                json.push('\n');
+                json
+            }
+            ReportFormat::Text => {
+                let mut out = format!(
+                    "Audio: {:.1} s of {}, {} channel(s) at {} Hz, {} pipeline(s)\n",
+                    self.audio_secs, self.source, self.channels, self.sample_rate, self.pipelines
+                );
+                out.push_str(&format!(
+                    "Model load: {:.2} s, on the {} execution provider with {} intra-op / {} inter-op threads, {} graph optimization\n",
+                    self.model_load_secs,
+                    self.execution_provider,
+                    execution::threads(self.intra_threads),
+                    execution::threads(self.inter_threads),
+                    self.graph_optimization
+                ));
+                out.push_str(&format!(
+                    "Processed in {:.2} s: real-time factor {:.3}, {}\n",
+                    self.wall_secs,
+                    self.real_time_factor,
+                    if self.keeps_up {
+                        "keeps up with live audio"
+                    } else {
+                        "too slow for live audio"
+                    }
+                ));
+                out.push_str(&format!(
+                    "Inference per chunk ({} chunks): {}\n",
+                    self.chunks, self.inference_ms
+                ));
+                out.push_str(&format!(
+                    "End-to-end latency if live: {}\n",
+                    self.latency_ms
+                ));
+                match (self.peak_memory_mb, self.peak_threads, self.cpu_cores) {
+                    (Some(memory), Some(threads), Some(cores)) => out.push_str(&format!(
+                        "Peak memory {:.0} MB, {} threads, {:.1} cores busy on average\n",
+                        memory, threads, cores
+                    )),
+                    _ => out.push_str("Memory, threads and CPU use are only measured on Linux\n"),
+                }
+                out
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank_percentiles() {
+        let p = Percentiles::of((1..=100).map(|ms| ms as f64 / 1000.0).collect());
+        assert_eq!((p.p50, p.p90, p.p99, p.max), (50.0, 90.0, 99.0, 100.0));
+        assert!((p.mean - 50.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn slow_chunks_queue_up_when_live() {
+        let started = Instant::now();
+        let chunk = |i: u64, cost_ms: u64| ChunkTiming {
+            source: 0,
+            start: i as f64 * 0.5,
+            end: (i + 1) as f64 * 0.5,
+            inference: Duration::from_millis(cost_ms),
+            finished: started + Duration::from_millis(cost_ms * (i + 1)),
+        };
+        // Fast enough: each chunk is done 100 ms after its last sample
+        let fast: Vec<f64> = live_latencies(&[chunk(0, 100), chunk(1, 100)], started);
+        assert!(fast.iter().all(|l| (l - 0.6).abs() < 1e-9));
+        // Too slow: every chunk waits longer than the one before
+        let slow = live_latencies(&[chunk(0, 700), chunk(1, 700), chunk(2, 700)], started);
+        assert!((slow[0] - 1.2).abs() < 1e-9);
+        assert!((slow[1] - 1.4).abs() < 1e-9);
+        assert!((slow[2] - 1.6).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,632 @@
+use crate::audio_config;
+use crate::downmix::{self, ChannelMix};
+use crate::execution::{self, Runtime};
+use crate::file_transcriber::{self, TranscriptFormat};
+use crate::pipeline::{self, Overload};
+use cpal::SampleFormat;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Directory name of the model used when neither the config file nor
+/// `--model` names one, looked for under the data directory
+pub const DEFAULT_MODEL_NAME: &str = "nemotron-speech-streaming-en-0.6b";
+/// Speech recognition engines rustscriber can drive
+const ENGINES: [&str; 1] = ["nemotron"];
+const DEFAULT_PRE_ROLL_MS: u64 = 300;
+
+/// Settings that can come from the config file or the command line, named
+/// after the flags. Everything is optional so that layers can be merged:
+/// built-in defaults, then the file's top level, then the chosen profile,
+/// then command-line flags.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub input: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub input_name: Vec<String>,
+    pub default_device: Option<bool>,
+    pub follow_default: Option<bool>,
+
+    pub model: Option<PathBuf>,
+    pub engine: Option<String>,
+    pub execution_provider: Option<String>,
+    pub intra_threads: Option<usize>,
+    pub inter_threads: Option<usize>,
+    pub graph_optimization: Option<String>,
+
+    pub sample_rate: Option<u32>,
+    pub device_channels: Option<u16>,
+    pub sample_format: Option<String>,
+    pub buffer_size: Option<u32>,
+
+    pub channel: Option<String>,
+    pub channels: Option<String>,
+    pub split_channels: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub channel_names: Vec<String>,
+
+    pub high_pass: Option<f32>,
+    pub noise_gate: Option<f32>,
+    pub agc: Option<f32>,
+    pub denoise: Option<f32>,
+    pub denoise_record: Option<PathBuf>,
+
+    pub pause_stream: Option<bool>,
+    pub push_to_talk: Option<bool>,
+    pub pre_roll: Option<u64>,
+    pub ptt_pipe: Option<PathBuf>,
+    pub overload: Option<String>,
+    pub max_backlog: Option<f32>,
+
+    pub meter: Option<bool>,
+    pub tui: Option<bool>,
+    pub control_socket: Option<SocketSetting>,
+
+    pub format: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub recording_dir: Option<PathBuf>,
+}
+
+/// `control-socket = true` for the default path, or a path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SocketSetting {
+    Enabled(bool),
+    Path(PathBuf),
+}
+
+impl Settings {
+    fn defaults() -> Self {
+        Self {
+            model: data_dir().map(|dir| dir.join(DEFAULT_MODEL_NAME)),
+            engine: Some(ENGINES[0].to_string()),
+            pre_roll: Some(DEFAULT_PRE_ROLL_MS),
+            ..Default::default()
+        }
+    }
+
+    /// Layer `over` on top of `self`: whatever `over` sets wins. Options that
+    /// exclude each other (the ways of picking a device, or of mixing
+    /// channels) are taken as a group, so a flag replaces the file's choice
+    /// rather than conflicting with it.
+    fn merge(self, over: Settings) -> Settings {
+        let over_device = !over.input.is_empty()
+            || !over.input_name.is_empty()
+            || over.default_device.is_some()
+            || over.follow_default.is_some();
+        let (input, input_name, default_device, follow_default) = if over_device {
+            (
+                over.input,
+                over.input_name,
+                over.default_device,
+                over.follow_default,
+            )
+        } else {
+            (
+                self.input,
+                self.input_name,
+                self.default_device,
+                self.follow_default,
+            )
+        };
+
+        let over_mix =
+            over.channel.is_some() || over.channels.is_some() || over.split_channels.is_some();
+        let (channel, channels, split_channels) = if over_mix {
+            (over.channel, over.channels, over.split_channels)
+        } else {
+            (self.channel, self.channels, self.split_channels)
+        };
+
+        Settings {
+            host: over.host.or(self.host),
+            input,
+            input_name,
+            default_device,
+            follow_default,
+            model: over.model.or(self.model),
+            engine: over.engine.or(self.engine),
+            execution_provider: over.execution_provider.or(self.execution_provider),
+            intra_threads: over.intra_threads.or(self.intra_threads),
+            inter_threads: over.inter_threads.or(self.inter_threads),
+            graph_optimization: over.graph_optimization.or(self.graph_optimization),
+            sample_rate: over.sample_rate.or(self.sample_rate),
+            device_channels: over.device_channels.or(self.device_channels),
+            sample_format: over.sample_format.or(self.sample_format),
+            buffer_size: over.buffer_size.or(self.buffer_size),
+            channel,
+            channels,
+            split_channels,
+            channel_names: if over.channel_names.is_empty() {
+                self.channel_names
+            } else {
+                over.channel_names
+            },
+            high_pass: over.high_pass.or(self.high_pass),
+            noise_gate: over.noise_gate.or(self.noise_gate),
+            agc: over.agc.or(self.agc),
+            denoise: over.denoise.or(self.denoise),
+            denoise_record: over.denoise_record.or(self.denoise_record),
+            pause_stream: over.pause_stream.or(self.pause_stream),
+            push_to_talk: over.push_to_talk.or(self.push_to_talk),
+            pre_roll: over.pre_roll.or(self.pre_roll),
+            ptt_pipe: over.ptt_pipe.or(self.ptt_pipe),
+            overload: over.overload.or(self.overload),
+            max_backlog: over.max_backlog.or(self.max_backlog),
+            meter: over.meter.or(self.meter),
+            tui: over.tui.or(self.tui),
+            control_socket: over.control_socket.or(self.control_socket),
+            format: over.format.or(self.format),
+            output_dir: over.output_dir.or(self.output_dir),
+            recording_dir: over.recording_dir.or(self.recording_dir),
+        }
+    }
+
+    /// Catch combinations the command line would have rejected, now that
+    /// they may come from different layers.
+    fn validate(&self) -> Result<(), String> {
+        if let Some(ref engine) = self.engine
+            && !ENGINES.contains(&engine.as_str())
+        {
+            return Err(format!(
+                "Unknown engine: {} (available: {})",
+                engine,
+                ENGINES.join(", ")
+            ));
+        }
+        if let Some(ref provider) = self.execution_provider {
+            execution::parse_provider(provider)?;
+        }
+        if let Some(ref level) = self.graph_optimization {
+            execution::parse_optimization(level)?;
+        }
+        if !self.input.is_empty() && !self.input_name.is_empty() {
+            return Err("input and input-name can't both be set".to_string());
+        }
+        if self.follow_default() && !(self.input.is_empty() && self.input_name.is_empty()) {
+            return Err("follow-default can't be combined with input or input-name".to_string());
+        }
+        if self.channel.is_some() && self.channels.is_some() {
+            return Err("channel and channels can't both be set".to_string());
+        }
+        if self.ptt_pipe.is_some() && !self.push_to_talk() {
+            return Err("ptt-pipe needs push-to-talk".to_string());
+        }
+        if let Some(ref format) = self.sample_format {
+            audio_config::parse_sample_format(format)?;
+        }
+        if let Some(ref channel) = self.channel {
+            downmix::parse_channel(channel)?;
+        }
+        if let Some(ref channels) = self.channels {
+            downmix::parse_channels(channels)?;
+        }
+        if let Some(ref overload) = self.overload {
+            pipeline::parse_overload(overload)?;
+        }
+        if let Some(ref format) = self.format {
+            file_transcriber::parse_format(format)?;
+        }
+        if self
+            .max_backlog
+            .is_some_and(|secs| !(secs > 0.0 && secs.is_finite()))
+        {
+            return Err("max-backlog must be a positive number of seconds".to_string());
+        }
+        Ok(())
+    }
+
+    /// The sample format to capture in, if one is forced. Settings returned
+    /// by `load` have already been checked.
+    pub fn sample_format(&self) -> Option<SampleFormat> {
+        let format = self.sample_format.as_deref()?;
+        audio_config::parse_sample_format(format).ok()
+    }
+
+    pub fn channel_mix(&self) -> ChannelMix {
+        let parsed = match (&self.channel, &self.channels) {
+            (Some(channel), _) => downmix::parse_channel(channel),
+            (None, Some(channels)) => downmix::parse_channels(channels),
+            (None, None) => return ChannelMix::default(),
+        };
+        parsed.unwrap_or_default()
+    }
+
+    /// Without a home directory to find the data directory in, the default
+    /// model is looked for in the current directory.
+    pub fn model(&self) -> &Path {
+        self.model
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_MODEL_NAME))
+    }
+
+    pub fn engine(&self) -> &str {
+        self.engine.as_deref().unwrap_or(ENGINES[0])
+    }
+
+    pub fn runtime(&self) -> Runtime {
+        let defaults = Runtime::default();
+        Runtime {
+            provider: self
+                .execution_provider
+                .as_deref()
+                .and_then(|provider| execution::parse_provider(provider).ok())
+                .unwrap_or(defaults.provider),
+            intra_threads: self.intra_threads.unwrap_or(defaults.intra_threads),
+            inter_threads: self.inter_threads.unwrap_or(defaults.inter_threads),
+            optimization: self
+                .graph_optimization
+                .as_deref()
+                .and_then(|level| execution::parse_optimization(level).ok())
+                .unwrap_or(defaults.optimization),
+        }
+    }
+
+    pub fn pre_roll(&self) -> u64 {
+        self.pre_roll.unwrap_or(DEFAULT_PRE_ROLL_MS)
+    }
+
+    pub fn overload(&self) -> Overload {
+        let defaults = Overload::default();
+        Overload {
+            policy: self
+                .overload
+                .as_deref()
+                .and_then(|policy| pipeline::parse_overload(policy).ok())
+                .unwrap_or(defaults.policy),
+            max_backlog: self
+                .max_backlog
+                .map_or(defaults.max_backlog, Duration::from_secs_f32),
+        }
+    }
+
+    /// Following the default device starts on it too.
+    pub fn default_device(&self) -> bool {
+        self.default_device.unwrap_or(false) || self.follow_default()
+    }
+
+    pub fn follow_default(&self) -> bool {
+        self.follow_default.unwrap_or(false)
+    }
+
+    pub fn split_channels(&self) -> bool {
+        self.split_channels.unwrap_or(false)
+    }
+
+    pub fn pause_stream(&self) -> bool {
+        self.pause_stream.unwrap_or(false)
+    }
+
+    pub fn push_to_talk(&self) -> bool {
+        self.push_to_talk.unwrap_or(false)
+    }
+
+    pub fn meter(&self) -> bool {
+        self.meter.unwrap_or(false)
+    }
+
+    pub fn tui(&self) -> bool {
+        self.tui.unwrap_or(false)
+    }
+
+    pub fn format(&self) -> TranscriptFormat {
+        self.format
+            .as_deref()
+            .and_then(|format| file_transcriber::parse_format(format).ok())
+            .unwrap_or_default()
+    }
+
+    /// Where recordings and transcripts saved from the terminal interface go.
+    pub fn recording_dir(&self) -> &Path {
+        self.recording_dir.as_deref().unwrap_or(Path::new(""))
+    }
+
+    /// `Some(None)` for the default socket path.
+    pub fn control_socket(&self) -> Option<Option<PathBuf>> {
+        match self.control_socket {
+            Some(SocketSetting::Enabled(true)) => Some(None),
+            Some(SocketSetting::Path(ref path)) => Some(Some(path.clone())),
+            Some(SocketSetting::Enabled(false)) | None => None,
+        }
+    }
+}
+
+/// Where the effective settings came from, for `config show`.
+pub struct Origin {
+    pub path: PathBuf,
+    pub loaded: bool,
+    pub profile: Option<String>,
+    pub profiles: Vec<String>,
+}
+
+/// `$XDG_CONFIG_HOME/rustscriber/config.toml`, or under `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Directory for rustscriber's own files: `$XDG_CONFIG_HOME/rustscriber`,
+/// or `~/.config/rustscriber`.
+pub fn config_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("rustscriber"))
+}
+
+/// Directory for downloaded data such as models:
+/// `$XDG_DATA_HOME/rustscriber`, or `~/.local/share/rustscriber`.
+pub fn data_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::home_dir().map(|home| home.join(".local/share")))
+        .map(|dir| dir.join("rustscriber"))
+}
+
+/// Merge the config file (`path`, or the default location if it exists),
+/// the named profile from it, and the command-line settings.
+pub fn load(
+    path: Option<&Path>,
+    profile: Option<&str>,
+    cli: Settings,
+) -> Result<(Settings, Origin), String> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => (
+            default_path().ok_or("Can't locate the config directory")?,
+            false,
+        ),
+    };
+
+    let (base, mut profiles, loaded) = match fs::read_to_string(&path) {
+        Ok(contents) => {
+            let (base, profiles) = parse(&contents)
+                .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+            (base, profiles, true)
+        }
+        Err(e) if required || e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(format!(
+                "Failed to read config file {}: {}",
+                path.display(),
+                e
+            ));
+        }
+        Err(_) => (Settings::default(), BTreeMap::new(), false),
+    };
+
+    let names: Vec<String> = profiles.keys().cloned().collect();
+    let chosen = match profile {
+        Some(name) => profiles.remove(name).ok_or_else(|| {
+            let available = if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            };
+            format!(
+                "No profile named {} in {} (available: {})",
+                name,
+                path.display(),
+                available
+            )
+        })?,
+        None => Settings::default(),
+    };
+
+    let settings = Settings::defaults().merge(base).merge(chosen).merge(cli);
+    settings.validate()?;
+    let origin = Origin {
+        path,
+        loaded,
+        profile: profile.map(str::to_string),
+        profiles: names,
+    };
+    Ok((settings, origin))
+}
+
+/// Split a config file into its top-level settings and its `[profiles.*]`.
+fn parse(contents: &str) -> Result<(Settings, BTreeMap<String, Settings>), String> {
+    let mut table: toml::Table = contents
+        .parse()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+    let profiles = match table.remove("profiles") {
+        Some(profiles) => profiles
+            .try_into()
+            .map_err(|e: toml::de::Error| format!("in [profiles]: {}", e))?,
+        None => BTreeMap::new(),
+    };
+    let base = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+    Ok((base, profiles))
+}
+
+/// Print the effective settings as TOML, headed by where they came from.
+pub fn show(settings: &Settings, origin: &Origin) -> Result<(), String> {
+    if origin.loaded {
+        println!("# Config file: {}", origin.path.display());
+    } else {
+        println!("# Config file: {} (not found)", origin.path.display());
+    }
+    if !origin.profiles.is_empty() {
+        println!("# Profiles: {}", origin.profiles.join(", "));
+    }
+    if let Some(ref profile) = origin.profile {
+        println!("# Using profile: {}", profile);
+    }
+    let toml = toml::to_string(settings).map_err(|e| e.to_string())?;
+    print!("{}", toml);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+model = "/models/nemotron"
+agc = -20.0
+input-name = ["Yeti"]
+
+[profiles.meeting]
+split-channels = true
+channel-names = ["Host", "Guest"]
+agc = -18.0
+
+[profiles.dictation]
+push-to-talk = true
+channel = "2"
+"#;
+
+    fn merged(profile: Option<&str>, cli: Settings) -> Settings {
+        let (base, mut profiles) = parse(FILE).unwrap();
+        let chosen = profile
+            .map(|name| profiles.remove(name).unwrap())
+            .unwrap_or_default();
+        Settings::defaults().merge(base).merge(chosen).merge(cli)
+    }
+
+    #[test]
+    fn profile_overrides_top_level() {
+        let settings = merged(Some("meeting"), Settings::default());
+        assert_eq!(settings.agc, Some(-18.0));
+        assert!(settings.split_channels());
+        assert_eq!(settings.model(), Path::new("/models/nemotron"));
+        assert_eq!(settings.input_name, vec!["Yeti"]);
+        assert_eq!(settings.pre_roll(), DEFAULT_PRE_ROLL_MS);
+    }
+
+    #[test]
+    fn cli_overrides_profile() {
+        let cli = Settings {
+            agc: Some(-25.0),
+            input: vec!["hw:1".to_string()],
+            channels: Some("1,3".to_string()),
+            ..Default::default()
+        };
+        let settings = merged(Some("dictation"), cli);
+        assert_eq!(settings.agc, Some(-25.0));
+        // Choosing a device by ID replaces the file's name pattern
+        assert_eq!(settings.input, vec!["hw:1"]);
+        assert!(settings.input_name.is_empty());
+        // Likewise a channel list replaces the profile's single channel
+        assert_eq!(settings.channels.as_deref(), Some("1,3"));
+        assert_eq!(settings.channel, None);
+        assert!(settings.push_to_talk());
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn follow_default_replaces_the_files_device() {
+        let cli = Settings {
+            follow_default: Some(true),
+            ..Default::default()
+        };
+        let settings = merged(None, cli);
+        assert!(settings.input_name.is_empty());
+        assert!(settings.default_device());
+        assert!(settings.validate().is_ok());
+
+        let (mut base, _) = parse(FILE).unwrap();
+        base.follow_default = Some(true);
+        assert!(base.validate().is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(parse("agcc = -20.0").is_err());
+        assert!(parse("[profiles.x]\nsplit_channels = true").is_err());
+    }
+
+    #[test]
+    fn unknown_engine_is_rejected() {
+        let settings = Settings {
+            engine: Some("whisper".to_string()),
+            ..Settings::defaults()
+        };
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn output_settings_from_a_profile() {
+        let (base, mut profiles) = parse(
+            "format = \"json\"\n[profiles.notes]\noutput-dir = \"/notes\"\nrecording-dir = \"/rec\"",
+        )
+        .unwrap();
+        let notes = profiles.remove("notes").unwrap();
+        let settings = Settings::defaults().merge(base).merge(notes);
+        assert!(matches!(settings.format(), TranscriptFormat::Json));
+        assert_eq!(settings.output_dir.as_deref(), Some(Path::new("/notes")));
+        assert_eq!(settings.recording_dir(), Path::new("/rec"));
+        assert!(settings.validate().is_ok());
+
+        let cli = Settings {
+            format: Some("text".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            settings.merge(cli).format(),
+            TranscriptFormat::Text
+        ));
+        let (base, _) = parse("format = \"srt\"").unwrap();
+        assert!(base.validate().is_err());
+    }
+
+    #[test]
+    fn default_model_is_in_the_data_directory() {
+        let model = data_dir().map(|dir| dir.join(DEFAULT_MODEL_NAME));
+        assert_eq!(Settings::defaults().model, model);
+        assert!(Settings::defaults().model().ends_with(DEFAULT_MODEL_NAME));
+    }
+
+    #[test]
+    fn overload_from_the_file() {
+        let (base, _) = parse("overload = \"skip-silence\"\nmax-backlog = 1.5").unwrap();
+        let overload = base.overload();
+        assert_eq!(overload.policy, pipeline::OverloadPolicy::SkipSilence);
+        assert_eq!(overload.max_backlog, Duration::from_millis(1500));
+        assert!(base.validate().is_ok());
+
+        let (base, _) = parse("overload = \"panic\"").unwrap();
+        assert!(base.validate().is_err());
+        let (base, _) = parse("max-backlog = 0.0").unwrap();
+        assert!(base.validate().is_err());
+    }
+
+    #[test]
+    fn runtime_defaults_and_checks() {
+        let runtime = Settings::defaults().runtime();
+        assert_eq!(runtime.intra_threads, execution::DEFAULT_INTRA_THREADS);
+        assert_eq!(runtime.inter_threads, execution::DEFAULT_INTER_THREADS);
+
+        let (base, _) = parse("execution-provider = \"CPU\"\nintra-threads = 8").unwrap();
+        assert!(base.validate().is_ok());
+        assert_eq!(base.runtime().intra_threads, 8);
+        let (base, _) = parse("execution-provider = \"tpu\"").unwrap();
+        assert!(base.validate().is_err());
+
+        assert_eq!(runtime.optimization, execution::OptimizationLevel::Layout);
+        let (base, _) = parse("graph-optimization = \"Basic\"").unwrap();
+        assert!(base.validate().is_ok());
+        assert_eq!(
+            base.runtime().optimization,
+            execution::OptimizationLevel::Basic
+        );
+        let (base, _) = parse("graph-optimization = \"3\"").unwrap();
+        assert!(base.validate().is_err());
+    }
+
+    #[test]
+    fn control_socket_setting() {
+        let (base, _) = parse("control-socket = true").unwrap();
+        assert_eq!(base.control_socket(), Some(None));
+        let (base, _) = parse("control-socket = \"/tmp/s.sock\"").unwrap();
+        assert_eq!(
+            base.control_socket(),
+            Some(Some(PathBuf::from("/tmp/s.sock")))
+        );
+    }
+}
@@ -0,0 +1,487 @@
+//! JSON-RPC 2.0 control interface for a running live session.
+//!
+//! Requests and responses are newline-delimited JSON objects on a Unix domain
+//! socket. After a successful `subscribe`, the connection switches to a stream
+//! of `transcript` notifications until the client disconnects or the session
+//! ends.
+
+use crate::error::RustscriberError;
+use crate::session::{ControlRequest, Reply, SessionCommand};
+use clap::Subcommand;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SESSION_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Default socket location: `$XDG_RUNTIME_DIR/rustscriber.sock`, falling back
+/// to the system temp directory.
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("rustscriber.sock")
+}
+
+pub struct ControlServer {
+    path: PathBuf,
+    stop_flag: Arc<AtomicBool>,
+    accept_thread: Option<JoinHandle<()>>,
+}
+
+impl ControlServer {
+    /// Bind the socket and forward each request to the session over `requests`.
+    pub fn start(path: &Path, requests: Sender<ControlRequest>) -> Result<Self, RustscriberError> {
+        let error = |e| RustscriberError::io(format!("Failed to open {}", path.display()), e);
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(RustscriberError::Settings(format!(
+                    "{} exists and isn't a socket; refusing to replace it",
+                    path.display()
+                )));
+            }
+            // A socket file left behind by a crashed session is safe to
+            // replace; a live one is not.
+            if UnixStream::connect(path).is_ok() {
+                return Err(RustscriberError::Failed(format!(
+                    "Another session is already listening on {}",
+                    path.display()
+                )));
+            }
+            fs::remove_file(path).map_err(error)?;
+        }
+
+        let listener = UnixListener::bind(path).map_err(error)?;
+        // Commands can stop the session or write files, so only the owner
+        // may connect
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(error)?;
+        listener.set_nonblocking(true).map_err(error)?;
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let stop_flag_thread = Arc::clone(&stop_flag);
+
+        let accept_thread = thread::spawn(move || {
+            while !stop_flag_thread.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let requests = requests.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = handle_connection(stream, requests) {
+                                eprintln!("Control connection error: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                    Err(e) => {
+                        eprintln!("Control socket error: {}", e);
+                        thread::sleep(Duration::from_millis(50));
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            stop_flag,
+            accept_thread: Some(accept_thread),
+        })
+    }
+
+    pub fn stop(mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.accept_thread.take() {
+            let _ = handle.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn handle_connection(stream: UnixStream, requests: Sender<ControlRequest>) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                write_message(&mut writer, &error_response(Value::Null, PARSE_ERROR, e))?;
+                continue;
+            }
+        };
+
+        if request.method == "subscribe" {
+            let (tx, events) = mpsc::channel();
+            let reply = dispatch(&requests, SessionCommand::Subscribe(tx));
+            write_message(&mut writer, &response(request.id, reply))?;
+
+            for event in events {
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "transcript",
+                    "params": event,
+                });
+                write_message(&mut writer, &notification)?;
+            }
+            return Ok(());
+        }
+
+        let message = match parse_command(&request.method, &request.params) {
+            Ok(command) => response(request.id, dispatch(&requests, command)),
+            Err((code, message)) => error_response(request.id, code, message),
+        };
+        write_message(&mut writer, &message)?;
+    }
+
+    Ok(())
+}
+
+fn parse_command(method: &str, params: &Value) -> Result<SessionCommand, (i64, String)> {
+    match method {
+        "pause" => Ok(SessionCommand::Pause),
+        "resume" => Ok(SessionCommand::Resume),
+        "stop" => Ok(SessionCommand::Stop),
+        "status" => Ok(SessionCommand::Status),
+        "switch-device" => Ok(SessionCommand::SwitchDevice(string_param(
+            params, "device",
+        )?)),
+        "start-recording" => Ok(SessionCommand::StartRecording(string_param(
+            params, "path",
+        )?)),
+        "stop-recording" => Ok(SessionCommand::StopRecording),
+        "ptt-press" => Ok(SessionCommand::Talk(true)),
+        "ptt-release" => Ok(SessionCommand::Talk(false)),
+        "ptt-toggle" => Ok(SessionCommand::ToggleTalk),
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", method))),
+    }
+}
+
+fn string_param(params: &Value, name: &str) -> Result<String, (i64, String)> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| {
+            (
+                INVALID_PARAMS,
+                format!("Missing string parameter \"{}\"", name),
+            )
+        })
+}
+
+/// Hand a command to the session thread and wait for its reply.
+fn dispatch(requests: &Sender<ControlRequest>, command: SessionCommand) -> Reply {
+    let (request, reply) = ControlRequest::new(command);
+    requests
+        .send(request)
+        .map_err(|_| "Session has ended".to_string())?;
+    reply.recv().map_err(|_| "Session has ended".to_string())?
+}
+
+fn response(id: Value, reply: Reply) -> Value {
+    match reply {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(message) => error_response(id, SESSION_ERROR, message),
+    }
+}
+
+fn error_response(id: Value, code: i64, message: impl ToString) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message.to_string() },
+    })
+}
+
+fn write_message(writer: &mut impl Write, message: &Value) -> io::Result<()> {
+    writeln!(writer, "{}", message)?;
+    writer.flush()
+}
+
+/// Commands for `rustscriber ctl`.
+#[derive(Subcommand)]
+pub enum CtlCommand {
+    /// Stop feeding audio to the model (it stays loaded)
+    Pause,
+    /// Resume after a pause
+    Resume,
+    /// End the session
+    Stop,
+    /// Print the session state as JSON
+    Status,
+    /// Move capture to another input device, by ID (see `devices`) or name pattern
+    SwitchDevice {
+        #[arg(value_name = "DEVICE")]
+        device: String,
+    },
+    /// Record the captured audio to a WAV file alongside transcription
+    StartRecording {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    /// Finalize the active recording
+    StopRecording,
+    /// Start a push-to-talk utterance
+    PttPress,
+    /// End the current push-to-talk utterance
+    PttRelease,
+    /// Press or release push-to-talk, whichever is not current
+    PttToggle,
+    /// Print transcript text as it is produced
+    Subscribe,
+}
+
+impl CtlCommand {
+    fn to_request(&self) -> io::Result<(&'static str, Value)> {
+        Ok(match self {
+            CtlCommand::Pause => ("pause", Value::Null),
+            CtlCommand::Resume => ("resume", Value::Null),
+            CtlCommand::Stop => ("stop", Value::Null),
+            CtlCommand::Status => ("status", Value::Null),
+            CtlCommand::SwitchDevice { device } => ("switch-device", json!({ "device": device })),
+            CtlCommand::StartRecording { file } => {
+                // The session may run from a different working directory
+                let path = std::env::current_dir()?.join(file);
+                ("start-recording", json!({ "path": path.to_string_lossy() }))
+            }
+            CtlCommand::StopRecording => ("stop-recording", Value::Null),
+            CtlCommand::PttPress => ("ptt-press", Value::Null),
+            CtlCommand::PttRelease => ("ptt-release", Value::Null),
+            CtlCommand::PttToggle => ("ptt-toggle", Value::Null),
+            CtlCommand::Subscribe => ("subscribe", Value::Null),
+        })
+    }
+}
+
+/// Send a single command to the session listening on `socket` and print the result.
+pub fn run_client(socket: &Path, command: &CtlCommand) -> Result<(), RustscriberError> {
+    let (method, params) = command
+        .to_request()
+        .map_err(|e| RustscriberError::io("Invalid command", e))?;
+
+    let stream = UnixStream::connect(socket).map_err(|e| {
+        RustscriberError::io(
+            format!(
+                "Could not connect to {} (is a session running with --control-socket, or `serve`?)",
+                socket.display()
+            ),
+            e,
+        )
+    })?;
+    session_client(stream, method, params, command)
+        .map_err(|e| RustscriberError::io("Lost the connection to the session", e))?
+}
+
+/// Talk to the session over `stream`. Connection trouble is the outer error;
+/// the session refusing the command is the inner one.
+fn session_client(
+    stream: UnixStream,
+    method: &str,
+    params: Value,
+    command: &CtlCommand,
+) -> io::Result<Result<(), RustscriberError>> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    write_message(&mut writer, &request)?;
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "session closed the connection",
+        ));
+    }
+    let reply: Value = serde_json::from_str(&line)?;
+    if let Some(error) = reply.get("error") {
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error");
+        return Ok(Err(RustscriberError::Failed(message.to_string())));
+    }
+
+    if !matches!(command, CtlCommand::Subscribe) {
+        println!("{}", serde_json::to_string_pretty(&reply["result"])?);
+        return Ok(Ok(()));
+    }
+
+    // Label of the source whose text is on the current line, when the
+    // session transcribes channels separately
+    let mut current_source: Option<String> = None;
+    for line in reader.lines() {
+        let notification: Value = serde_json::from_str(&line?)?;
+        let params = &notification["params"];
+        match (params["type"].as_str(), params["text"].as_str()) {
+            (Some("text"), Some(text)) => {
+                if let Some(source) = params["source"].as_str()
+                    && current_source.as_deref() != Some(source)
+                {
+                    if current_source.is_some() {
+                        println!();
+                    }
+                    print!("[{}]", source);
+                    current_source = Some(source.to_string());
+                }
+                print!("{}", text);
+                let _ = io::stdout().flush();
+            }
+            // The text of a push-to-talk press has already been streamed
+            (Some("final"), _) => {
+                println!();
+                current_source = None;
+            }
+            _ => {}
+        }
+    }
+    println!();
+    Ok(Ok(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(result: Result<SessionCommand, (i64, String)>) -> i64 {
+        match result {
+            Ok(_) => 0,
+            Err((code, _)) => code,
+        }
+    }
+
+    #[test]
+    fn parses_commands_and_their_params() {
+        let params = json!({ "device": "USB", "path": "/tmp/out.wav" });
+        assert!(matches!(
+            parse_command("switch-device", &params),
+            Ok(SessionCommand::SwitchDevice(device)) if device == "USB"
+        ));
+        assert!(matches!(
+            parse_command("start-recording", &params),
+            Ok(SessionCommand::StartRecording(path)) if path == "/tmp/out.wav"
+        ));
+        assert!(matches!(
+            parse_command("ptt-press", &Value::Null),
+            Ok(SessionCommand::Talk(true))
+        ));
+        assert_eq!(
+            code(parse_command("rewind", &Value::Null)),
+            METHOD_NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn rejects_missing_and_mistyped_params() {
+        assert_eq!(
+            code(parse_command("switch-device", &Value::Null)),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(parse_command("switch-device", &json!({ "device": 2 }))),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            code(parse_command(
+                "start-recording",
+                &json!({ "file": "x.wav" })
+            )),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            string_param(&json!({ "path": "a" }), "path"),
+            Ok("a".to_string())
+        );
+    }
+
+    #[test]
+    fn answers_each_line_with_a_result_or_error() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (requests, received) = mpsc::channel::<ControlRequest>();
+        thread::spawn(move || handle_connection(server, requests));
+        thread::spawn(move || {
+            for request in received {
+                let reply = match request.command {
+                    SessionCommand::Status => Ok(json!({ "state": "running" })),
+                    _ => Err("not now".to_string()),
+                };
+                let _ = request.reply.send(reply);
+            }
+        });
+
+        let mut writer = client.try_clone().unwrap();
+        let mut reader = BufReader::new(client);
+        let mut exchange = |line: &str| {
+            writeln!(writer, "{}", line).unwrap();
+            let mut reply = String::new();
+            reader.read_line(&mut reply).unwrap();
+            serde_json::from_str::<Value>(&reply).unwrap()
+        };
+
+        let reply = exchange("{not json");
+        assert_eq!(reply["error"]["code"], PARSE_ERROR);
+        assert_eq!(reply["id"], Value::Null);
+        // A request needs a method
+        assert_eq!(exchange(r#"{"id": 1}"#)["error"]["code"], PARSE_ERROR);
+        let reply = exchange(r#"{"id": 2, "method": "rewind"}"#);
+        assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(reply["id"], 2);
+        let reply = exchange(r#"{"id": 3, "method": "switch-device", "params": {"device": 1}}"#);
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+        let reply = exchange(r#"{"id": 4, "method": "pause"}"#);
+        assert_eq!(reply["error"]["code"], SESSION_ERROR);
+        assert_eq!(reply["error"]["message"], "not now");
+        let reply = exchange(r#"{"id": "s", "method": "status"}"#);
+        assert_eq!(reply["result"]["state"], "running");
+        assert_eq!(reply["id"], "s");
+    }
+
+    #[test]
+    fn refuses_to_replace_anything_but_a_socket() {
+        let dir = std::env::temp_dir().join(format!("rustscriber-control-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("notes.txt");
+        fs::write(&file, "keep me").unwrap();
+        let (requests, _received) = mpsc::channel();
+        assert!(matches!(
+            ControlServer::start(&file, requests.clone()),
+            Err(RustscriberError::Settings(_))
+        ));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep me");
+
+        let socket = dir.join("control.sock");
+        let server = ControlServer::start(&socket, requests).unwrap();
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        server.stop();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
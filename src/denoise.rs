@@ -0,0 +1,332 @@
+use crate::error::RustscriberError;
+use crate::sample_convert;
+use crate::wav_recorder;
+use cpal::{BufferSize, StreamConfig};
+use hound::WavWriter;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Analysis frame length, in seconds (rounded up to a power of two in samples)
+const FRAME_SECS: f32 = 0.02;
+/// Audio at the start used to seed the noise estimate, in seconds
+const SEED_SECS: f32 = 0.25;
+/// Weight of the previous frame in the "decision-directed" speech estimate;
+/// close to one keeps isolated bins from flickering ("musical noise")
+const DECISION_DIRECTED: f32 = 0.98;
+/// Per-frame smoothing of bin power before it is used to track noise
+const POWER_SMOOTHING: f32 = 0.7;
+
+/// Streaming spectral noise suppression: tracks the noise floor per frequency
+/// bin and applies a Wiener gain from each bin's estimated speech-to-noise
+/// ratio. Output lags input by half a frame; over time as many samples come
+/// out as go in.
+pub struct Denoiser {
+    max_attenuation_db: f32,
+    frame_len: usize,
+    hop: usize,
+    window: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    /// Most recent `frame_len` input samples
+    frame: Vec<f32>,
+    /// Input waiting for a full hop
+    pending: Vec<f32>,
+    /// Overlap-add accumulator for the output
+    overlap: Vec<f32>,
+    noise: Vec<f32>,
+    /// Smoothed power per bin
+    power: Vec<f32>,
+    /// Power per bin left after the previous frame's gain
+    clean: Vec<f32>,
+    frames_seen: usize,
+    seed_frames: usize,
+    /// Per-frame ceiling on how fast the noise estimate rises
+    noise_rise: f32,
+    recording: Option<ComparisonRecording>,
+}
+
+impl Denoiser {
+    /// `max_attenuation_db` bounds how far a bin can be pushed down; higher
+    /// removes more noise at the cost of more artefacts.
+    pub fn new(
+        max_attenuation_db: f32,
+        sample_rate: usize,
+        recording: Option<ComparisonRecording>,
+    ) -> Self {
+        let frame_len = ((FRAME_SECS * sample_rate as f32) as usize).next_power_of_two();
+        let hop = frame_len / 2;
+        let bins = frame_len / 2 + 1;
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        // Square root of a periodic Hann window, applied on both analysis and
+        // synthesis, sums to one at 50% overlap.
+        let window = (0..frame_len)
+            .map(|i| (0.5 - 0.5 * (2.0 * PI * i as f32 / frame_len as f32).cos()).sqrt())
+            .collect();
+
+        Self {
+            max_attenuation_db,
+            frame_len,
+            hop,
+            window,
+            fft: planner.plan_fft_forward(frame_len),
+            ifft: planner.plan_fft_inverse(frame_len),
+            frame: vec![0.0; frame_len],
+            pending: Vec::with_capacity(hop),
+            overlap: vec![0.0; frame_len],
+            noise: vec![0.0; bins],
+            power: vec![0.0; bins],
+            clean: vec![0.0; bins],
+            frames_seen: 0,
+            seed_frames: ((SEED_SECS * sample_rate as f32) as usize / hop).max(1),
+            // About 3 dB per second
+            noise_rise: 10f32.powf(0.3 * hop as f32 / sample_rate as f32),
+            recording,
+        }
+    }
+
+    /// Start over for a new input rate. A comparison recording can't change
+    /// rate mid-file, so it is finished.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        let recording = self.recording.take().and_then(|recording| {
+            if recording.sample_rate == sample_rate as u32 {
+                Some(recording)
+            } else {
+                recording.finish();
+                None
+            }
+        });
+        *self = Self::new(self.max_attenuation_db, sample_rate, recording);
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let mut output = Vec::with_capacity(input.len() + self.hop);
+
+        for &sample in input {
+            self.pending.push(sample);
+            if self.pending.len() == self.hop {
+                self.frame.copy_within(self.hop.., 0);
+                let tail = self.frame_len - self.hop;
+                self.frame[tail..].copy_from_slice(&self.pending);
+                self.pending.clear();
+                self.process_frame();
+                output.extend_from_slice(&self.overlap[..self.hop]);
+                self.overlap.copy_within(self.hop.., 0);
+                self.overlap[tail..].fill(0.0);
+            }
+        }
+
+        if let Some(ref mut recording) = self.recording {
+            recording.write(input, &output);
+        }
+        output
+    }
+
+    fn process_frame(&mut self) {
+        let mut time: Vec<f32> = self
+            .frame
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| s * w)
+            .collect();
+        let mut spectrum = self.fft.make_output_vec();
+        if self.fft.process(&mut time, &mut spectrum).is_err() {
+            return;
+        }
+
+        self.frames_seen += 1;
+        let floor = 10f32.powf(-self.max_attenuation_db / 20.0);
+        let seeding = self.frames_seen <= self.seed_frames;
+
+        for (bin, value) in spectrum.iter_mut().enumerate() {
+            let power = value.norm_sqr();
+            let smoothed = &mut self.power[bin];
+            *smoothed = POWER_SMOOTHING * *smoothed + (1.0 - POWER_SMOOTHING) * power;
+            let noise = &mut self.noise[bin];
+
+            if seeding {
+                // Running mean over the first frames, assumed to be mostly noise
+                *noise += (power - *noise) / self.frames_seen as f32;
+                self.clean[bin] = power;
+                continue;
+            }
+            // Follow dips straight away, rises only slowly, so speech doesn't
+            // get mistaken for noise
+            if *smoothed < *noise {
+                *noise = 0.8 * *noise + 0.2 * *smoothed;
+            } else {
+                *noise = (*noise * self.noise_rise).min(0.98 * *noise + 0.02 * *smoothed);
+            }
+
+            let noise = noise.max(f32::MIN_POSITIVE);
+            let snr = DECISION_DIRECTED * self.clean[bin] / noise
+                + (1.0 - DECISION_DIRECTED) * (power / noise - 1.0).max(0.0);
+            let gain = (snr / (1.0 + snr)).max(floor);
+            self.clean[bin] = gain * gain * power;
+            *value *= gain;
+        }
+
+        // The DC and Nyquist bins of a real signal have no imaginary part
+        spectrum[0].im = 0.0;
+        if let Some(last) = spectrum.last_mut() {
+            last.im = 0.0;
+        }
+
+        let mut out = self.ifft.make_output_vec();
+        if self.ifft.process(&mut spectrum, &mut out).is_err() {
+            return;
+        }
+        let scale = 1.0 / self.frame_len as f32;
+        for ((acc, s), w) in self.overlap.iter_mut().zip(&out).zip(&self.window) {
+            *acc += s * w * scale;
+        }
+    }
+}
+
+/// Side-by-side WAVs of one pipeline's audio before and after denoising, both
+/// mono at the input rate.
+pub struct ComparisonRecording {
+    sample_rate: u32,
+    raw: WavWriter<BufWriter<File>>,
+    denoised: WavWriter<BufWriter<File>>,
+}
+
+impl ComparisonRecording {
+    /// Create `<stem>-raw.wav` and `<stem>-denoised.wav`, with `label` (if
+    /// any) inserted after the stem.
+    pub fn create(
+        stem: &Path,
+        label: Option<&str>,
+        sample_rate: u32,
+    ) -> Result<Self, RustscriberError> {
+        let config = StreamConfig {
+            channels: 1,
+            sample_rate,
+            buffer_size: BufferSize::Default,
+        };
+        let raw_path = comparison_path(stem, label, "raw");
+        let denoised_path = comparison_path(stem, label, "denoised");
+        println!(
+            "Recording denoiser comparison to {} and {}",
+            raw_path.display(),
+            denoised_path.display()
+        );
+
+        Ok(Self {
+            sample_rate,
+            raw: wav_recorder::create_writer(&raw_path.to_string_lossy(), &config)?,
+            denoised: wav_recorder::create_writer(&denoised_path.to_string_lossy(), &config)?,
+        })
+    }
+
+    fn write(&mut self, raw: &[f32], denoised: &[f32]) {
+        for &sample in raw {
+            let _ = self.raw.write_sample(sample_convert::f32_to_i16(sample));
+        }
+        for &sample in denoised {
+            let _ = self
+                .denoised
+                .write_sample(sample_convert::f32_to_i16(sample));
+        }
+    }
+
+    fn finish(self) {
+        let _ = wav_recorder::finalize(self.raw);
+        let _ = wav_recorder::finalize(self.denoised);
+    }
+}
+
+fn comparison_path(stem: &Path, label: Option<&str>, kind: &str) -> PathBuf {
+    let base = stem.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match label {
+        Some(label) => {
+            // Labels can be device names, which may contain path separators
+            let label: String = label
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                .collect();
+            format!("{}-{}-{}.wav", base, label, kind)
+        }
+        None => format!("{}-{}.wav", base, kind),
+    };
+    stem.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: usize = 16000;
+
+    /// Deterministic white-ish noise in [-amplitude, amplitude]
+    fn noise(amplitude: f32, len: usize) -> Vec<f32> {
+        let mut state = 0x2545_f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                amplitude * (state as f32 / u32::MAX as f32 * 2.0 - 1.0)
+            })
+            .collect()
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn as_many_samples_come_out_as_go_in() {
+        let mut denoiser = Denoiser::new(20.0, RATE, None);
+        let input = noise(0.1, RATE);
+        let mut out = 0;
+        for block in input.chunks(100) {
+            out += denoiser.process(block).len();
+        }
+        // Up to one hop is still waiting for the next frame
+        assert!(out <= input.len() && input.len() - out < denoiser.hop);
+    }
+
+    #[test]
+    fn steady_noise_is_attenuated() {
+        let mut denoiser = Denoiser::new(20.0, RATE, None);
+        let input = noise(0.1, 4 * RATE);
+        let output = denoiser.process(&input);
+        let settled = output.len() / 2..;
+        let reduction = 20.0 * (rms(&input[settled.clone()]) / rms(&output[settled])).log10();
+        assert!(reduction > 10.0, "only {:.1} dB quieter", reduction);
+        // Never pushed below the attenuation floor by much
+        assert!(reduction < 25.0, "{:.1} dB quieter", reduction);
+    }
+
+    #[test]
+    fn a_tone_over_noise_survives() {
+        let mut denoiser = Denoiser::new(20.0, RATE, None);
+        // Seed the noise estimate, then add a loud tone
+        let mut input = noise(0.01, 4 * RATE);
+        for (i, sample) in input.iter_mut().enumerate().skip(RATE) {
+            *sample += 0.3 * (2.0 * PI * 440.0 * i as f32 / RATE as f32).sin();
+        }
+        let output = denoiser.process(&input);
+        let tone = 2 * RATE..output.len();
+        let loss = 20.0 * (rms(&input[tone.clone()]) / rms(&output[tone])).log10();
+        assert!(loss.abs() < 1.5, "tone changed by {:.1} dB", loss);
+    }
+
+    #[test]
+    fn comparison_paths_keep_labels_in_the_file_name() {
+        let stem = Path::new("out/take.wav");
+        assert_eq!(
+            comparison_path(stem, None, "raw"),
+            Path::new("out/take-raw.wav")
+        );
+        assert_eq!(
+            comparison_path(stem, Some("USB Mic/2"), "denoised"),
+            Path::new("out/take-USB_Mic_2-denoised.wav")
+        );
+    }
+}
@@ -1,71 +1,201 @@
+use clap::ValueEnum;
 use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{SupportedBufferSize, SupportedStreamConfigRange};
+use serde::Serialize;
 
-pub fn enumerate_devices() {
-    let host = cpal::default_host();
-    println!("Audio host: {:?}\n", host.id());
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
 
-    if let Some(device) = host.default_input_device() {
-        let name = device_name(&device);
-        println!("Default input device: {}", name);
-    } else {
-        println!("No default input device found");
-    }
+#[derive(Serialize)]
+struct HostInfo {
+    id: String,
+    name: &'static str,
+    is_default: bool,
+    /// Set if the host is compiled in but could not be opened
+    error: Option<String>,
+    default_input: Option<String>,
+    default_output: Option<String>,
+    input_devices: Vec<DeviceInfo>,
+    output_devices: Vec<DeviceInfo>,
+}
+
+#[derive(Serialize)]
+struct DeviceInfo {
+    id: Option<String>,
+    name: String,
+    configs: Vec<ConfigRange>,
+}
+
+#[derive(Serialize)]
+struct ConfigRange {
+    channels: u16,
+    min_sample_rate: u32,
+    max_sample_rate: u32,
+    sample_format: String,
+    /// `None` when the backend can't report buffer sizes
+    buffer_size: Option<BufferSizeRange>,
+}
+
+#[derive(Serialize)]
+struct BufferSizeRange {
+    min: u32,
+    max: u32,
+}
+
+/// List devices on every available host, or only on `only` if given.
+pub fn enumerate_devices(format: OutputFormat, only: Option<cpal::HostId>) {
+    let hosts = collect_hosts(only);
 
-    if let Some(device) = host.default_output_device() {
-        let name = device_name(&device);
-        println!("Default output device: {}", name);
+    match format {
+        OutputFormat::Json => match serde_json::to_string_pretty(&hosts) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Failed to serialize devices: {e}"),
+        },
+        OutputFormat::Text => print_hosts(&hosts),
     }
+}
+
+fn collect_hosts(only: Option<cpal::HostId>) -> Vec<HostInfo> {
+    let default_id = cpal::default_host().id();
+
+    cpal::available_hosts()
+        .into_iter()
+        .filter(|host_id| only.is_none_or(|id| id == *host_id))
+        .map(|host_id| {
+            let mut info = HostInfo {
+                id: host_id.to_string(),
+                name: host_id.name(),
+                is_default: host_id == default_id,
+                error: None,
+                default_input: None,
+                default_output: None,
+                input_devices: Vec::new(),
+                output_devices: Vec::new(),
+            };
 
-    println!("\n--- Input Devices ---");
-    match host.input_devices() {
-        Ok(devices) => {
-            for device in devices {
-                println!("  {}", device_name(&device));
-                if let Ok(id) = device.id() {
-                    println!("    id: {}", id);
+            let host = match cpal::host_from_id(host_id) {
+                Ok(host) => host,
+                Err(e) => {
+                    info.error = Some(e.to_string());
+                    return info;
                 }
-                if let Ok(configs) = device.supported_input_configs() {
-                    for cfg in configs {
-                        println!(
-                            "    channels={}, sample_rate={}..{}, format={:?}",
-                            cfg.channels(),
-                            cfg.min_sample_rate(),
-                            cfg.max_sample_rate(),
-                            cfg.sample_format(),
-                        );
-                    }
+            };
+
+            info.default_input = host.default_input_device().map(|d| device_name(&d));
+            info.default_output = host.default_output_device().map(|d| device_name(&d));
+
+            match host.input_devices() {
+                Ok(devices) => {
+                    info.input_devices = devices
+                        .map(|d| {
+                            let configs = d.supported_input_configs();
+                            device_info(&d, configs.ok().map(|c| c.collect()))
+                        })
+                        .collect();
                 }
+                Err(e) => info.error = Some(format!("Error listing input devices: {e}")),
             }
-        }
-        Err(e) => eprintln!("  Error listing input devices: {e}"),
-    }
 
-    println!("\n--- Output Devices ---");
-    match host.output_devices() {
-        Ok(devices) => {
-            for device in devices {
-                println!("  {}", device_name(&device));
-                if let Ok(id) = device.id() {
-                    println!("    id: {}", id);
-                }
-                if let Ok(configs) = device.supported_output_configs() {
-                    for cfg in configs {
-                        println!(
-                            "    channels={}, sample_rate={}..{}, format={:?}",
-                            cfg.channels(),
-                            cfg.min_sample_rate(),
-                            cfg.max_sample_rate(),
-                            cfg.sample_format(),
-                        );
-                    }
+            match host.output_devices() {
+                Ok(devices) => {
+                    info.output_devices = devices
+                        .map(|d| {
+                            let configs = d.supported_output_configs();
+                            device_info(&d, configs.ok().map(|c| c.collect()))
+                        })
+                        .collect();
                 }
+                Err(e) => info.error = Some(format!("Error listing output devices: {e}")),
             }
+
+            info
+        })
+        .collect()
+}
+
+fn device_info(
+    device: &cpal::Device,
+    configs: Option<Vec<SupportedStreamConfigRange>>,
+) -> DeviceInfo {
+    DeviceInfo {
+        id: device.id().ok().map(|id| id.to_string()),
+        name: device_name(device),
+        configs: configs
+            .unwrap_or_default()
+            .iter()
+            .map(|cfg| ConfigRange {
+                channels: cfg.channels(),
+                min_sample_rate: cfg.min_sample_rate(),
+                max_sample_rate: cfg.max_sample_rate(),
+                sample_format: format!("{:?}", cfg.sample_format()),
+                buffer_size: match *cfg.buffer_size() {
+                    SupportedBufferSize::Range { min, max } => Some(BufferSizeRange { min, max }),
+                    SupportedBufferSize::Unknown => None,
+                },
+            })
+            .collect(),
+    }
+}
+
+fn print_hosts(hosts: &[HostInfo]) {
+    let names: Vec<&str> = hosts.iter().map(|h| h.name).collect();
+    println!("Available audio hosts: {}", names.join(", "));
+
+    for host in hosts {
+        println!(
+            "\n=== Audio host: {} (--host {}){} ===\n",
+            host.name,
+            host.id,
+            if host.is_default { ", default" } else { "" }
+        );
+
+        if let Some(ref error) = host.error {
+            println!("  Unavailable: {}", error);
+            if host.input_devices.is_empty() && host.output_devices.is_empty() {
+                continue;
+            }
+        }
+
+        match host.default_input {
+            Some(ref name) => println!("Default input device: {}", name),
+            None => println!("No default input device found"),
+        }
+        if let Some(ref name) = host.default_output {
+            println!("Default output device: {}", name);
+        }
+
+        println!("\n--- Input Devices ---");
+        print_devices(&host.input_devices);
+
+        println!("\n--- Output Devices ---");
+        print_devices(&host.output_devices);
+    }
+}
+
+fn print_devices(devices: &[DeviceInfo]) {
+    for device in devices {
+        println!("  {}", device.name);
+        if let Some(ref id) = device.id {
+            println!("    id: {}", id);
+        }
+        for cfg in &device.configs {
+            let buffer = match cfg.buffer_size {
+                Some(ref b) => format!(", buffer={}..{}", b.min, b.max),
+                None => String::new(),
+            };
+            println!(
+                "    channels={}, sample_rate={}..{}, format={}{}",
+                cfg.channels, cfg.min_sample_rate, cfg.max_sample_rate, cfg.sample_format, buffer,
+            );
         }
-        Err(e) => eprintln!("  Error listing output devices: {e}"),
     }
 }
 
-fn device_name(device: &cpal::Device) -> String {
+pub fn device_name(device: &cpal::Device) -> String {
     device
         .description()
         .map(|desc| desc.name().to_string())
@@ -0,0 +1,318 @@
+use crate::config;
+use crate::device_enumerator::device_name;
+use crate::error::RustscriberError;
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Device, Host, HostId};
+use regex::RegexBuilder;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+/// How the user asked for an input device on the command line.
+#[derive(Default)]
+pub struct DeviceChoice {
+    /// Exact device IDs from `devices`; more than one captures from
+    /// every device at once
+    pub ids: Vec<String>,
+    /// Name patterns in order of preference; the first that matches wins
+    pub name_patterns: Vec<String>,
+    /// Ignore (and forget) the remembered device
+    pub use_default: bool,
+}
+
+/// The last device explicitly chosen, so later runs pick it up again.
+#[derive(Serialize, Deserialize)]
+struct LastDevice {
+    id: Option<String>,
+    name: String,
+}
+
+/// Open the audio host named `name` (e.g. "alsa", "jack", "wasapi"), or the
+/// platform default if `None`.
+pub fn select_host(name: Option<&str>) -> Result<Host, RustscriberError> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+
+    let available = cpal::available_hosts();
+    let host_id = available
+        .iter()
+        .copied()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<String> = available.iter().map(HostId::to_string).collect();
+            RustscriberError::Settings(format!(
+                "Unknown or unavailable audio host: {}\nAvailable hosts: {}",
+                name,
+                names.join(", ")
+            ))
+        })?;
+
+    cpal::host_from_id(host_id)
+        .map_err(|e| RustscriberError::Device(format!("Failed to open audio host {}: {}", name, e)))
+}
+
+/// Resolve `choice` to input devices on `host`: one per ID, or a single
+/// device otherwise. Explicit choices are remembered (the first, for several
+/// IDs); with no choice, the remembered device is tried before the host
+/// default.
+pub fn select_input_devices(
+    host: &Host,
+    choice: &DeviceChoice,
+) -> Result<Vec<Device>, RustscriberError> {
+    if !choice.ids.is_empty() {
+        let devices = choice
+            .ids
+            .iter()
+            .map(|device_id| {
+                find_by_id(host, device_id).ok_or_else(|| {
+                    no_match(
+                        host,
+                        &format!("No input device found with ID: {}", device_id),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        remember(&devices[0]);
+        return Ok(devices);
+    }
+
+    select_input_device(host, choice).map(|device| vec![device])
+}
+
+fn select_input_device(host: &Host, choice: &DeviceChoice) -> Result<Device, RustscriberError> {
+    if !choice.name_patterns.is_empty() {
+        for pattern in &choice.name_patterns {
+            if let Some(device) = find_by_name(host, pattern)? {
+                remember(&device);
+                return Ok(device);
+            }
+        }
+        return Err(no_match(
+            host,
+            &format!(
+                "No input device name matches any of: {}",
+                choice.name_patterns.join(", ")
+            ),
+        ));
+    }
+
+    if choice.use_default {
+        forget();
+    } else if let Some(device) = remembered_device(host) {
+        return Ok(device);
+    }
+
+    host.default_input_device()
+        .ok_or_else(|| no_match(host, "No default input device found"))
+}
+
+/// Find a device by exact ID, falling back to a name pattern. Used when
+/// switching devices on a running session.
+pub fn find_by_id_or_name(host: &Host, query: &str) -> Result<Device, RustscriberError> {
+    let device = match find_by_id(host, query) {
+        Some(device) => device,
+        None => find_by_name(host, query)?.ok_or_else(|| {
+            no_match(
+                host,
+                &format!("No input device ID or name matches: {}", query),
+            )
+        })?,
+    };
+    remember(&device);
+    Ok(device)
+}
+
+/// Find an input device on `host` by the ID shown by `devices`.
+pub fn find_by_id(host: &Host, device_id: &str) -> Option<Device> {
+    let devices: Vec<Device> = host.input_devices().ok()?.collect();
+    let index = position_by_id(&describe(&devices), device_id)?;
+    devices.into_iter().nth(index)
+}
+
+/// Find the first input device whose name contains `pattern`
+/// (case-insensitive) or matches it as a regular expression.
+fn find_by_name(host: &Host, pattern: &str) -> Result<Option<Device>, RustscriberError> {
+    let devices: Vec<Device> = host.input_devices()?.collect();
+    Ok(position_by_name(&describe(&devices), pattern).and_then(|i| devices.into_iter().nth(i)))
+}
+
+/// Names and IDs of `devices`, in order, for matching against.
+fn describe(devices: &[Device]) -> Vec<(String, Option<String>)> {
+    devices
+        .iter()
+        .map(|device| {
+            (
+                device_name(device),
+                device.id().ok().map(|id| id.to_string()),
+            )
+        })
+        .collect()
+}
+
+fn position_by_id(devices: &[(String, Option<String>)], device_id: &str) -> Option<usize> {
+    devices
+        .iter()
+        .position(|(_, id)| id.as_deref() == Some(device_id))
+}
+
+fn position_by_name(devices: &[(String, Option<String>)], pattern: &str) -> Option<usize> {
+    let needle = pattern.to_lowercase();
+    // Device names often contain parentheses, so a failed regex compile
+    // just means substring matching only.
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .ok();
+    devices.iter().position(|(name, _)| {
+        name.to_lowercase().contains(&needle) || regex.as_ref().is_some_and(|r| r.is_match(name))
+    })
+}
+
+/// The remembered device among `devices`: by ID, or, since IDs can change
+/// across reboots, by exact name.
+fn position_remembered(devices: &[(String, Option<String>)], last: &LastDevice) -> Option<usize> {
+    last.id
+        .as_deref()
+        .and_then(|id| position_by_id(devices, id))
+        .or_else(|| devices.iter().position(|(name, _)| *name == last.name))
+}
+
+/// Names and IDs (where the backend has them) of the input devices on `host`.
+pub fn list_input_devices(host: &Host) -> Result<Vec<(String, Option<String>)>, RustscriberError> {
+    let devices: Vec<Device> = host.input_devices()?.collect();
+    Ok(describe(&devices))
+}
+
+/// Build an error that lists the devices the user could have picked.
+fn no_match(host: &Host, message: &str) -> RustscriberError {
+    let mut out = format!("{}\n\nAvailable input devices:", message);
+    match list_input_devices(host) {
+        Ok(devices) => {
+            for (name, id) in &devices {
+                let id = id.as_deref().unwrap_or("<no id>");
+                out.push_str(&format!("\n  {}\n    id: {}", name, id));
+            }
+            if devices.is_empty() {
+                out.push_str("\n  (none)");
+            }
+        }
+        Err(e) => out.push_str(&format!("\n  {}", e)),
+    }
+    RustscriberError::Device(out)
+}
+
+fn last_device_path() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("last_device.json"))
+}
+
+fn remembered_device(host: &Host) -> Option<Device> {
+    let contents = fs::read_to_string(last_device_path()?).ok()?;
+    let last: LastDevice = serde_json::from_str(&contents).ok()?;
+
+    let devices: Vec<Device> = host.input_devices().ok()?.collect();
+    let device =
+        position_remembered(&describe(&devices), &last).and_then(|i| devices.into_iter().nth(i));
+
+    match device {
+        Some(ref d) => println!("Using remembered input device: {}", device_name(d)),
+        None => println!(
+            "Remembered input device \"{}\" not found; using the default",
+            last.name
+        ),
+    }
+    device
+}
+
+fn remember(device: &Device) {
+    let Some(path) = last_device_path() else {
+        return;
+    };
+    let last = LastDevice {
+        id: device.id().ok().map(|id| id.to_string()),
+        name: device_name(device),
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, serde_json::to_string_pretty(&last)?));
+    if let Err(e) = result {
+        eprintln!(
+            "Failed to remember input device in {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn forget() {
+    if let Some(path) = last_device_path() {
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices() -> Vec<(String, Option<String>)> {
+        vec![
+            (
+                "Built-in Microphone".to_string(),
+                Some("alsa:hw:0".to_string()),
+            ),
+            (
+                "USB Audio (Scarlett 2i2)".to_string(),
+                Some("alsa:hw:1".to_string()),
+            ),
+            ("Monitor of Speakers".to_string(), None),
+        ]
+    }
+
+    #[test]
+    fn matches_names_by_substring_or_regex() {
+        let devices = devices();
+        assert_eq!(position_by_name(&devices, "usb audio"), Some(1));
+        assert_eq!(position_by_name(&devices, "^monitor"), Some(2));
+        assert_eq!(position_by_name(&devices, "mic|usb"), Some(0));
+        // Not a valid regex, but still a substring
+        assert_eq!(position_by_name(&devices, "(Scarlett"), Some(1));
+        assert_eq!(position_by_name(&devices, "headset"), None);
+    }
+
+    #[test]
+    fn matches_ids_exactly() {
+        let devices = devices();
+        assert_eq!(position_by_id(&devices, "alsa:hw:1"), Some(1));
+        assert_eq!(position_by_id(&devices, "alsa:hw"), None);
+        assert_eq!(position_by_id(&devices, ""), None);
+    }
+
+    #[test]
+    fn remembered_device_falls_back_to_its_name() {
+        let devices = devices();
+        let last: LastDevice =
+            serde_json::from_str(r#"{"id": "alsa:hw:1", "name": "USB Audio (Scarlett 2i2)"}"#)
+                .unwrap();
+        assert_eq!(position_remembered(&devices, &last), Some(1));
+
+        // Renumbered after a reboot
+        let last = LastDevice {
+            id: Some("alsa:hw:5".to_string()),
+            name: "USB Audio (Scarlett 2i2)".to_string(),
+        };
+        assert_eq!(position_remembered(&devices, &last), Some(1));
+
+        // The name has to match exactly, not as a pattern
+        let last = LastDevice {
+            id: None,
+            name: "Monitor".to_string(),
+        };
+        assert_eq!(position_remembered(&devices, &last), None);
+        let last = LastDevice {
+            id: None,
+            name: "Monitor of Speakers".to_string(),
+        };
+        assert_eq!(position_remembered(&devices, &last), Some(2));
+    }
+}
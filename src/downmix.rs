@@ -0,0 +1,347 @@
+use std::f32::consts::PI;
+use std::fmt;
+
+/// How interleaved multi-channel input is reduced to the mono stream fed to
+/// the model. Channel indices are 0-based; the CLI takes them 1-based.
+#[derive(Debug, Clone, Default)]
+pub enum ChannelMix {
+    /// Average every channel
+    #[default]
+    Average,
+    /// Use a single channel as-is
+    Single(usize),
+    /// Weighted sum of a subset of channels
+    Weighted(Vec<(usize, f32)>),
+    /// Follow whichever channel carries the most speech-band energy
+    Auto,
+}
+
+impl fmt::Display for ChannelMix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelMix::Average => write!(f, "average of all channels"),
+            ChannelMix::Single(c) => write!(f, "channel {}", c + 1),
+            ChannelMix::Weighted(weights) => {
+                let parts: Vec<String> = weights
+                    .iter()
+                    .map(|(c, w)| format!("{}×{:.2}", c + 1, w))
+                    .collect();
+                write!(f, "mix of channels {}", parts.join(" + "))
+            }
+            ChannelMix::Auto => write!(f, "auto-selected channel"),
+        }
+    }
+}
+
+/// Parse `--channel`: a 1-based channel number, or `auto`.
+pub fn parse_channel(s: &str) -> Result<ChannelMix, String> {
+    if s.eq_ignore_ascii_case("auto") {
+        return Ok(ChannelMix::Auto);
+    }
+    Ok(ChannelMix::Single(parse_channel_number(s)?))
+}
+
+/// Parse `--channels`: a comma-separated list of 1-based channel numbers,
+/// each optionally weighted as `N:WEIGHT`. Unweighted lists are averaged.
+pub fn parse_channels(s: &str) -> Result<ChannelMix, String> {
+    let entries: Vec<&str> = s.split(',').map(str::trim).collect();
+    let default_weight = 1.0 / entries.len() as f32;
+
+    let weights = entries
+        .iter()
+        .map(|entry| match entry.split_once(':') {
+            Some((channel, weight)) => {
+                let weight: f32 = weight
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid channel weight: {}", weight))?;
+                Ok((parse_channel_number(channel)?, weight))
+            }
+            None => Ok((parse_channel_number(entry)?, default_weight)),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(ChannelMix::Weighted(weights))
+}
+
+fn parse_channel_number(s: &str) -> Result<usize, String> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n - 1),
+        _ => Err(format!(
+            "Invalid channel number (channels start at 1): {}",
+            s
+        )),
+    }
+}
+
+/// Reduces interleaved frames to mono according to a `ChannelMix`. Holds
+/// the per-channel state needed by `ChannelMix::Auto`, so each capture
+/// stream gets its own.
+pub struct Downmixer {
+    mix: ChannelMix,
+    channels: usize,
+    auto: Option<AutoSelect>,
+}
+
+impl ChannelMix {
+    /// Fewest channels a device needs for this mix to read from.
+    pub fn channels_needed(&self) -> usize {
+        match self {
+            ChannelMix::Single(c) => c + 1,
+            ChannelMix::Weighted(weights) => weights.iter().map(|&(c, _)| c + 1).max().unwrap_or(1),
+            ChannelMix::Average | ChannelMix::Auto => 1,
+        }
+    }
+}
+
+impl Downmixer {
+    pub fn new(mix: ChannelMix, channels: usize, sample_rate: u32) -> Result<Self, String> {
+        let needed = mix.channels_needed();
+        if needed > channels {
+            return Err(format!(
+                "Channel {} requested but the device only has {} channel(s)",
+                needed, channels
+            ));
+        }
+
+        let auto =
+            matches!(mix, ChannelMix::Auto).then(|| AutoSelect::new(channels, sample_rate as f32));
+
+        Ok(Self {
+            mix,
+            channels,
+            auto,
+        })
+    }
+
+    pub fn process(&mut self, data: &[f32]) -> Vec<f32> {
+        if self.channels == 1 {
+            return data.to_vec();
+        }
+
+        let frames = data.chunks_exact(self.channels);
+        match self.mix {
+            ChannelMix::Average => frames
+                .map(|frame| frame.iter().sum::<f32>() / self.channels as f32)
+                .collect(),
+            ChannelMix::Single(c) => frames.map(|frame| frame[c]).collect(),
+            ChannelMix::Weighted(ref weights) => frames
+                .map(|frame| weights.iter().map(|&(c, w)| frame[c] * w).sum())
+                .collect(),
+            ChannelMix::Auto => {
+                let auto = self.auto.as_mut().expect("auto state set in new");
+                frames.map(|frame| auto.next(frame)).collect()
+            }
+        }
+    }
+}
+
+/// Tracks smoothed speech-band (300–3400 Hz) energy per channel and picks the
+/// strongest, with hysteresis so it doesn't flap between similar channels,
+/// and a crossfade so a switch doesn't click.
+struct AutoSelect {
+    filters: Vec<SpeechBandFilter>,
+    energy: Vec<f32>,
+    smoothing: f32,
+    current: usize,
+    /// Consecutive frames another channel has clearly beaten `current`
+    challenger_frames: usize,
+    hold_frames: usize,
+    /// The channel being faded out after a switch
+    previous: usize,
+    /// Frames of the crossfade still to go
+    fade_left: usize,
+    fade_frames: usize,
+}
+
+/// Energy ratio (≈3 dB) a channel must exceed the current one by to take over
+const SWITCH_RATIO: f32 = 2.0;
+/// How long a channel must stay ahead before switching, in seconds
+const SWITCH_HOLD_SECS: f32 = 0.5;
+/// Time constant of the energy follower, in seconds
+const ENERGY_WINDOW_SECS: f32 = 0.3;
+/// Length of the crossfade when switching channels, in seconds
+const CROSSFADE_SECS: f32 = 0.02;
+
+impl AutoSelect {
+    fn new(channels: usize, sample_rate: f32) -> Self {
+        Self {
+            filters: (0..channels)
+                .map(|_| SpeechBandFilter::new(sample_rate))
+                .collect(),
+            energy: vec![0.0; channels],
+            smoothing: (-1.0 / (ENERGY_WINDOW_SECS * sample_rate)).exp(),
+            current: 0,
+            challenger_frames: 0,
+            hold_frames: (SWITCH_HOLD_SECS * sample_rate) as usize,
+            previous: 0,
+            fade_left: 0,
+            fade_frames: ((CROSSFADE_SECS * sample_rate) as usize).max(1),
+        }
+    }
+
+    /// Feed one frame and return its mono sample: the selected channel, or
+    /// a blend of the old and new one just after a switch.
+    fn next(&mut self, frame: &[f32]) -> f32 {
+        let current = self.update(frame);
+        if self.fade_left == 0 {
+            return frame[current];
+        }
+        self.fade_left -= 1;
+        let old = self.fade_left as f32 / self.fade_frames as f32;
+        frame[self.previous] * old + frame[current] * (1.0 - old)
+    }
+
+    /// Feed one frame and return the channel to use for it.
+    fn update(&mut self, frame: &[f32]) -> usize {
+        let mut best = self.current;
+        for (c, &sample) in frame.iter().enumerate() {
+            let band = self.filters[c].process(sample);
+            self.energy[c] = self.smoothing * self.energy[c] + (1.0 - self.smoothing) * band * band;
+            if self.energy[c] > self.energy[best] {
+                best = c;
+            }
+        }
+
+        if best != self.current && self.energy[best] > self.energy[self.current] * SWITCH_RATIO {
+            self.challenger_frames += 1;
+            if self.challenger_frames >= self.hold_frames {
+                self.previous = self.current;
+                self.fade_left = self.fade_frames;
+                self.current = best;
+                self.challenger_frames = 0;
+            }
+        } else {
+            self.challenger_frames = 0;
+        }
+
+        self.current
+    }
+}
+
+/// One-pole high-pass into one-pole low-pass, roughly isolating the speech band.
+struct SpeechBandFilter {
+    hp_alpha: f32,
+    lp_alpha: f32,
+    prev_input: f32,
+    hp_out: f32,
+    lp_out: f32,
+}
+
+impl SpeechBandFilter {
+    fn new(sample_rate: f32) -> Self {
+        let dt = 1.0 / sample_rate;
+        let hp_rc = 1.0 / (2.0 * PI * 300.0);
+        let lp_rc = 1.0 / (2.0 * PI * 3400.0);
+        Self {
+            hp_alpha: hp_rc / (hp_rc + dt),
+            lp_alpha: dt / (lp_rc + dt),
+            prev_input: 0.0,
+            hp_out: 0.0,
+            lp_out: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        self.hp_out = self.hp_alpha * (self.hp_out + x - self.prev_input);
+        self.prev_input = x;
+        self.lp_out += self.lp_alpha * (self.hp_out - self.lp_out);
+        self.lp_out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    #[test]
+    fn parses_channel_and_channels() {
+        assert!(matches!(parse_channel("2"), Ok(ChannelMix::Single(1))));
+        assert!(matches!(parse_channel("AUTO"), Ok(ChannelMix::Auto)));
+        assert!(parse_channel("0").is_err());
+        assert!(parse_channel("left").is_err());
+
+        let Ok(ChannelMix::Weighted(weights)) = parse_channels("1, 3") else {
+            panic!("expected a weighted mix");
+        };
+        assert_eq!(weights, [(0, 0.5), (2, 0.5)]);
+        let Ok(ChannelMix::Weighted(weights)) = parse_channels("1:0.7,2:0.3") else {
+            panic!("expected a weighted mix");
+        };
+        assert_eq!(weights, [(0, 0.7), (1, 0.3)]);
+        assert!(parse_channels("1:loud").is_err());
+        assert!(parse_channels("1,,2").is_err());
+    }
+
+    #[test]
+    fn rejects_channels_the_device_lacks() {
+        assert!(Downmixer::new(ChannelMix::Single(2), 2, RATE).is_err());
+        assert!(Downmixer::new(ChannelMix::Weighted(vec![(0, 0.5), (3, 0.5)]), 2, RATE).is_err());
+        assert!(Downmixer::new(ChannelMix::Single(1), 2, RATE).is_ok());
+        assert_eq!(
+            ChannelMix::Weighted(vec![(0, 0.5), (2, 0.5)]).channels_needed(),
+            3
+        );
+        assert_eq!(ChannelMix::Auto.channels_needed(), 1);
+    }
+
+    #[test]
+    fn mixes_frames() {
+        let stereo = [1.0, 0.0, 0.5, 0.5, -0.25, 0.75];
+        let mut average = Downmixer::new(ChannelMix::Average, 2, RATE).unwrap();
+        assert_eq!(average.process(&stereo), [0.5, 0.5, 0.25]);
+        let mut right = Downmixer::new(ChannelMix::Single(1), 2, RATE).unwrap();
+        assert_eq!(right.process(&stereo), [0.0, 0.5, 0.75]);
+        let mut weighted =
+            Downmixer::new(ChannelMix::Weighted(vec![(0, 1.0), (1, -1.0)]), 2, RATE).unwrap();
+        assert_eq!(weighted.process(&stereo), [1.0, 0.0, -1.0]);
+    }
+
+    fn tone(i: usize) -> f32 {
+        0.5 * (2.0 * PI * 1000.0 * i as f32 / RATE as f32).sin()
+    }
+
+    #[test]
+    fn auto_follows_the_loud_channel_after_a_hold() {
+        let mut auto = Downmixer::new(ChannelMix::Auto, 2, RATE).unwrap();
+        let input: Vec<f32> = (0..RATE as usize).flat_map(|i| [0.001, tone(i)]).collect();
+        let output = auto.process(&input);
+
+        let right: Vec<f32> = input.chunks_exact(2).map(|frame| frame[1]).collect();
+        let settled = output
+            .iter()
+            .zip(&right)
+            .rposition(|(out, r)| out != r)
+            .map_or(0, |i| i + 1);
+        // Not before the hold, and on channel 2 well before the end
+        assert!(settled >= (SWITCH_HOLD_SECS * RATE as f32) as usize);
+        assert!(settled < RATE as usize * 9 / 10);
+    }
+
+    #[test]
+    fn auto_crossfades_on_a_switch() {
+        let mut auto = AutoSelect::new(2, RATE as f32);
+        // A DC offset carries no speech-band energy, but shows in the output
+        let frames: Vec<[f32; 2]> = (0..RATE as usize).map(|i| [1.0, tone(i)]).collect();
+        let mut switch = None;
+        let mut output = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            output.push(auto.next(frame));
+            if auto.current == 1 && switch.is_none() {
+                switch = Some(i);
+            }
+        }
+
+        let switch = switch.expect("switched to channel 2");
+        // Channel 1 fades out linearly as channel 2 fades in
+        let fade = auto.fade_frames;
+        for k in 0..fade {
+            let old = (fade - 1 - k) as f32 / fade as f32;
+            let [left, right] = frames[switch + k];
+            assert!((output[switch + k] - (left * old + right * (1.0 - old))).abs() < 1e-6);
+        }
+        assert_eq!(output[switch + fade], frames[switch + fade][1]);
+    }
+}
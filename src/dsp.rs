@@ -0,0 +1,398 @@
+use serde::Serialize;
+use std::f32::consts::{FRAC_1_SQRT_2, PI};
+use std::sync::{Arc, Mutex};
+
+/// Levels below this are treated as silence when metering
+const METER_FLOOR_DB: f32 = -100.0;
+
+/// Which conditioning stages to run on a pipeline's mono audio before it is
+/// resampled. Each stage is off when `None`.
+#[derive(Debug, Clone, Default)]
+pub struct DspOptions {
+    /// High-pass cutoff in Hz, to remove rumble and handling noise
+    pub high_pass: Option<f32>,
+    /// Noise gate threshold in dBFS
+    pub noise_gate: Option<f32>,
+    /// AGC target RMS level in dBFS
+    pub agc: Option<f32>,
+}
+
+impl DspOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.high_pass.is_some() || self.noise_gate.is_some() || self.agc.is_some()
+    }
+}
+
+/// Levels from the most recent block, for status output.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct DspMeter {
+    pub input_dbfs: f32,
+    pub output_dbfs: f32,
+    pub peak_dbfs: f32,
+    pub agc_gain_db: f32,
+    pub gate_open: bool,
+}
+
+impl Default for DspMeter {
+    fn default() -> Self {
+        Self {
+            input_dbfs: METER_FLOOR_DB,
+            output_dbfs: METER_FLOOR_DB,
+            peak_dbfs: METER_FLOOR_DB,
+            agc_gain_db: 0.0,
+            gate_open: true,
+        }
+    }
+}
+
+pub type SharedMeter = Arc<Mutex<DspMeter>>;
+
+/// High-pass filter, noise gate and AGC, in that order, so the gate sees the
+/// signal without rumble and the AGC never amplifies gated noise.
+pub struct Dsp {
+    options: DspOptions,
+    high_pass: Option<Biquad>,
+    gate: Option<NoiseGate>,
+    agc: Option<Agc>,
+    meter: SharedMeter,
+}
+
+impl Dsp {
+    pub fn new(options: DspOptions, sample_rate: usize) -> Self {
+        let mut dsp = Self {
+            options,
+            high_pass: None,
+            gate: None,
+            agc: None,
+            meter: Arc::new(Mutex::new(DspMeter::default())),
+        };
+        dsp.set_sample_rate(sample_rate);
+        dsp
+    }
+
+    /// Rebuild every stage for a new input rate, dropping filter state.
+    pub fn set_sample_rate(&mut self, sample_rate: usize) {
+        let rate = sample_rate as f32;
+        self.high_pass = self.options.high_pass.map(|hz| Biquad::high_pass(hz, rate));
+        self.gate = self.options.noise_gate.map(|db| NoiseGate::new(db, rate));
+        self.agc = self.options.agc.map(|db| Agc::new(db, rate));
+    }
+
+    pub fn meter(&self) -> SharedMeter {
+        Arc::clone(&self.meter)
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        if samples.is_empty() {
+            return;
+        }
+        let input_dbfs = rms_dbfs(samples);
+
+        if let Some(ref mut filter) = self.high_pass {
+            for sample in samples.iter_mut() {
+                *sample = filter.process(*sample);
+            }
+        }
+
+        let mut gate_open = true;
+        for sample in samples.iter_mut() {
+            if let Some(ref mut gate) = self.gate {
+                *sample = gate.process(*sample);
+                gate_open = gate.is_open();
+            }
+            if let Some(ref mut agc) = self.agc {
+                *sample = agc.process(*sample, gate_open);
+            }
+        }
+
+        if let Ok(mut meter) = self.meter.lock() {
+            *meter = DspMeter {
+                input_dbfs,
+                output_dbfs: rms_dbfs(samples),
+                peak_dbfs: peak_dbfs(samples),
+                agc_gain_db: self.agc.as_ref().map_or(0.0, |agc| to_db(agc.gain)),
+                gate_open,
+            };
+        }
+    }
+}
+
+fn to_db(linear: f32) -> f32 {
+    if linear > 0.0 {
+        (20.0 * linear.log10()).max(METER_FLOOR_DB)
+    } else {
+        METER_FLOOR_DB
+    }
+}
+
+fn from_db(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+pub fn rms_dbfs(samples: &[f32]) -> f32 {
+    let mean_square = samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32;
+    to_db(mean_square.sqrt())
+}
+
+fn peak_dbfs(samples: &[f32]) -> f32 {
+    to_db(samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs())))
+}
+
+/// One-pole smoothing coefficient for a time constant in seconds.
+fn coefficient(secs: f32, sample_rate: f32) -> f32 {
+    (-1.0 / (secs * sample_rate)).exp()
+}
+
+/// Second-order Butterworth section (RBJ cookbook), direct form I.
+struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    fn high_pass(cutoff: f32, sample_rate: f32) -> Self {
+        // Keep the cutoff below Nyquist for odd rates
+        let cutoff = cutoff.min(sample_rate * 0.45);
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * FRAC_1_SQRT_2);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+        Self {
+            b0: (1.0 + cos) / 2.0 / a0,
+            b1: -(1.0 + cos) / a0,
+            b2: (1.0 + cos) / 2.0 / a0,
+            a1: -2.0 * cos / a0,
+            a2: (1.0 - alpha) / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Mutes audio whose envelope stays below the threshold. Holds open briefly
+/// after speech and ramps its gain so opening and closing don't click.
+struct NoiseGate {
+    threshold: f32,
+    envelope: f32,
+    attack: f32,
+    release: f32,
+    hold_samples: usize,
+    /// Samples left before a quiet signal closes the gate
+    hold_left: usize,
+    gain: f32,
+    open_ramp: f32,
+    close_ramp: f32,
+}
+
+impl NoiseGate {
+    fn new(threshold_db: f32, sample_rate: f32) -> Self {
+        Self {
+            threshold: from_db(threshold_db),
+            envelope: 0.0,
+            attack: coefficient(0.005, sample_rate),
+            release: coefficient(0.1, sample_rate),
+            hold_samples: (0.2 * sample_rate) as usize,
+            hold_left: 0,
+            gain: 0.0,
+            open_ramp: coefficient(0.002, sample_rate),
+            close_ramp: coefficient(0.05, sample_rate),
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let level = x.abs();
+        let k = if level > self.envelope {
+            self.attack
+        } else {
+            self.release
+        };
+        self.envelope = k * self.envelope + (1.0 - k) * level;
+
+        if self.envelope >= self.threshold {
+            self.hold_left = self.hold_samples;
+        } else {
+            self.hold_left = self.hold_left.saturating_sub(1);
+        }
+
+        let (target, ramp) = if self.is_open() {
+            (1.0, self.open_ramp)
+        } else {
+            (0.0, self.close_ramp)
+        };
+        self.gain = ramp * self.gain + (1.0 - ramp) * target;
+        x * self.gain
+    }
+
+    fn is_open(&self) -> bool {
+        self.hold_left > 0
+    }
+}
+
+/// Slow RMS-tracking gain towards a target level, with instant reduction on
+/// peaks that would otherwise clip.
+struct Agc {
+    /// Target RMS, the gain limits and the silence level, all linear
+    target: f32,
+    min_gain: f32,
+    max_gain: f32,
+    silence: f32,
+    mean_square: f32,
+    detector: f32,
+    gain: f32,
+    /// Smoothing when the gain needs to drop, and when it may rise
+    attack: f32,
+    release: f32,
+}
+
+/// Gain limits, so silence isn't boosted into noise and loud input isn't crushed
+const AGC_MAX_GAIN_DB: f32 = 30.0;
+const AGC_MIN_GAIN_DB: f32 = -20.0;
+/// Below this RMS the signal is treated as silence and the gain is held
+const AGC_SILENCE_DB: f32 = -60.0;
+/// Output peak ceiling
+const AGC_CEILING: f32 = 0.98;
+
+impl Agc {
+    fn new(target_db: f32, sample_rate: f32) -> Self {
+        Self {
+            target: from_db(target_db),
+            min_gain: from_db(AGC_MIN_GAIN_DB),
+            max_gain: from_db(AGC_MAX_GAIN_DB),
+            silence: from_db(AGC_SILENCE_DB),
+            mean_square: 0.0,
+            detector: coefficient(0.3, sample_rate),
+            gain: 1.0,
+            attack: coefficient(0.05, sample_rate),
+            release: coefficient(2.0, sample_rate),
+        }
+    }
+
+    /// `active` is false while a noise gate is closed, which freezes the gain.
+    fn process(&mut self, x: f32, active: bool) -> f32 {
+        if active {
+            self.mean_square = self.detector * self.mean_square + (1.0 - self.detector) * x * x;
+            let rms = self.mean_square.sqrt();
+
+            if rms > self.silence {
+                let wanted = (self.target / rms).clamp(self.min_gain, self.max_gain);
+                let k = if wanted < self.gain {
+                    self.attack
+                } else {
+                    self.release
+                };
+                self.gain = k * self.gain + (1.0 - k) * wanted;
+            }
+        }
+
+        let y = x * self.gain;
+        if y.abs() > AGC_CEILING {
+            // Back the gain off so this peak lands on the ceiling; the
+            // release brings it back up smoothly.
+            self.gain = AGC_CEILING / x.abs();
+            return AGC_CEILING.copysign(y);
+        }
+        y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 16000.0;
+
+    fn sine(hz: f32, amplitude: f32, secs: f32) -> Vec<f32> {
+        (0..(secs * RATE) as usize)
+            .map(|i| amplitude * (2.0 * PI * hz * i as f32 / RATE).sin())
+            .collect()
+    }
+
+    /// RMS of the last half, after filters and envelopes have settled
+    fn settled_dbfs(samples: &[f32]) -> f32 {
+        rms_dbfs(&samples[samples.len() / 2..])
+    }
+
+    #[test]
+    fn high_pass_removes_rumble_and_keeps_speech() {
+        let mut filter = Biquad::high_pass(100.0, RATE);
+        let rumble: Vec<f32> = sine(20.0, 0.5, 1.0)
+            .iter()
+            .map(|&x| filter.process(x))
+            .collect();
+        let mut filter = Biquad::high_pass(100.0, RATE);
+        let voice: Vec<f32> = sine(1000.0, 0.5, 1.0)
+            .iter()
+            .map(|&x| filter.process(x))
+            .collect();
+
+        let level = rms_dbfs(&sine(1000.0, 0.5, 1.0));
+        // Two octaves and a bit below a 12 dB/octave cutoff
+        assert!(settled_dbfs(&rumble) < level - 25.0);
+        assert!((settled_dbfs(&voice) - level).abs() < 0.5);
+    }
+
+    #[test]
+    fn gate_mutes_below_its_threshold() {
+        let mut gate = NoiseGate::new(-40.0, RATE);
+        let quiet: Vec<f32> = sine(440.0, 0.001, 1.0)
+            .iter()
+            .map(|&x| gate.process(x))
+            .collect();
+        assert!(!gate.is_open());
+        assert!(settled_dbfs(&quiet) <= METER_FLOOR_DB + 1.0);
+
+        let loud: Vec<f32> = sine(440.0, 0.1, 1.0)
+            .iter()
+            .map(|&x| gate.process(x))
+            .collect();
+        assert!(gate.is_open());
+        assert!((settled_dbfs(&loud) - rms_dbfs(&sine(440.0, 0.1, 1.0))).abs() < 0.5);
+    }
+
+    #[test]
+    fn agc_converges_on_its_target_without_clipping() {
+        let mut agc = Agc::new(-20.0, RATE);
+        let out: Vec<f32> = sine(440.0, 0.01, 20.0)
+            .iter()
+            .map(|&x| agc.process(x, true))
+            .collect();
+        assert!((settled_dbfs(&out) - -20.0).abs() < 1.0);
+
+        // A sudden loud burst is held under the ceiling
+        let burst: Vec<f32> = sine(440.0, 0.9, 0.5)
+            .iter()
+            .map(|&x| agc.process(x, true))
+            .collect();
+        assert!(burst.iter().all(|s| s.abs() <= AGC_CEILING));
+        assert!(agc.gain < 1.0);
+    }
+
+    #[test]
+    fn agc_holds_its_gain_while_gated() {
+        let mut agc = Agc::new(-20.0, RATE);
+        for x in sine(440.0, 0.01, 2.0) {
+            agc.process(x, false);
+        }
+        assert_eq!(agc.gain, 1.0);
+    }
+}
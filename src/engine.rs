@@ -0,0 +1,624 @@
+//! Streaming speech recognition with token-level output.
+//!
+//! `parakeet_rs::Nemotron` only hands back the text of each chunk, so this
+//! runs the same ONNX encoder and decoder itself, with the same log-mel front
+//! end, encoder cache handling and greedy decoding, and keeps each token's
+//! ID, encoder frame and log-probability. Loading the sessions here rather
+//! than through `parakeet_rs::NemotronModel` also lets `Runtime` choose the
+//! graph optimization level and see which execution provider registered.
+
+use crate::error::RustscriberError;
+use crate::execution::Runtime;
+use ndarray::{Array, Array1, Array2, Array3, Dimension, s};
+use ort::session::{Session, SessionOutputs};
+use ort::tensor::PrimitiveTensorElementType;
+use ort::value::Value;
+use parakeet_rs::{NemotronEncoderCache, SentencePieceVocab};
+use realfft::{RealFftPlanner, RealToComplex};
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+// Front end and model dimensions of Nemotron 0.6B, as parakeet-rs uses them
+const SAMPLE_RATE: usize = 16000;
+const N_FFT: usize = 512;
+const WIN_LENGTH: usize = 400;
+const HOP_LENGTH: usize = 160;
+const N_MELS: usize = 128;
+const PREEMPH: f32 = 0.97;
+const LOG_ZERO_GUARD: f32 = 5.960_464_5e-8;
+const FMAX: f32 = 8000.0;
+const NUM_ENCODER_LAYERS: usize = 24;
+const HIDDEN_DIM: usize = 1024;
+const LEFT_CONTEXT: usize = 70;
+const CONV_CONTEXT: usize = 8;
+const VOCAB_SIZE: usize = 1024;
+const BLANK_ID: usize = 1024;
+const DECODER_LSTM_DIM: usize = 640;
+/// Mel frames per encoder chunk (560 ms)
+const CHUNK_SIZE: usize = 56;
+/// Mel frames of left context fed along with each chunk after the first
+const PRE_ENCODE_CACHE: usize = 9;
+const MAX_SYMBOLS_PER_STEP: usize = 10;
+/// Audio covered by one encoder output frame (8x subsampled 10 ms hops)
+pub const FRAME_SECS: f64 = 0.08;
+
+/// One decoded subword token.
+#[derive(Debug, Clone)]
+pub struct DecodedToken {
+    pub id: usize,
+    /// The SentencePiece piece, with a leading space where a word starts
+    pub text: String,
+    /// Encoder output frame it was emitted on, counted from the last reset
+    pub frame: usize,
+    /// Log-probability the decoder gave the token
+    pub log_prob: f32,
+}
+
+/// A token placed in session time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Token {
+    pub id: usize,
+    pub text: String,
+    pub frame: usize,
+    pub start: f64,
+    pub end: f64,
+    pub log_prob: f32,
+}
+
+/// Tokens joined into a word. `confidence` is the geometric mean of the
+/// token probabilities.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Word {
+    pub word: String,
+    pub start: f64,
+    pub end: f64,
+    pub confidence: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// What one call to `Engine::transcribe_chunk` decoded. `first_frame` is the
+/// frame the chunk's audio starts at.
+#[derive(Debug, Default)]
+pub struct ChunkOutput {
+    pub tokens: Vec<DecodedToken>,
+    pub first_frame: usize,
+}
+
+impl ChunkOutput {
+    pub fn text(&self) -> String {
+        self.tokens.iter().map(|t| t.text.as_str()).collect()
+    }
+
+    /// Place the tokens in session time, given that the chunk's audio starts
+    /// at `start` and ends at `end`.
+    pub fn timed(self, start: f64, end: f64) -> Vec<Token> {
+        self.tokens
+            .into_iter()
+            .map(|token| {
+                let offset = (token.frame - self.first_frame) as f64 * FRAME_SECS;
+                Token {
+                    id: token.id,
+                    text: token.text,
+                    frame: token.frame,
+                    start: (start + offset).min(end),
+                    end: (start + offset + FRAME_SECS).min(end),
+                    log_prob: token.log_prob,
+                }
+            })
+            .collect()
+    }
+}
+
+/// What a pipeline needs from a streaming model. `Engine` is the real one.
+pub trait Recognizer: Send + 'static {
+    fn transcribe_chunk(&mut self, audio: &[f32]) -> Result<ChunkOutput, RustscriberError>;
+    fn reset(&mut self);
+}
+
+/// What one decoder step produced.
+struct DecoderStep {
+    /// Over the vocabulary, then blank
+    logits: Vec<f32>,
+    /// The decoder state to carry on from if this step emits a token
+    state_1: Array3<f32>,
+    state_2: Array3<f32>,
+}
+
+/// Streaming Nemotron: feed 16 kHz audio in chunks, get tokens back.
+pub struct Engine {
+    encoder: Session,
+    decoder_joint: Session,
+    /// The execution provider the encoder registered with
+    provider: &'static str,
+    vocab: SentencePieceVocab,
+    encoder_cache: NemotronEncoderCache,
+    state_1: Array3<f32>,
+    state_2: Array3<f32>,
+    last_token: i32,
+    mel_basis: Array2<f32>,
+    window: Vec<f32>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    /// Raw audio, kept back far enough to compute the encoder's left context
+    audio_buffer: Vec<f32>,
+    /// Samples of `audio_buffer` already sent to the encoder
+    audio_processed: usize,
+    chunk_idx: usize,
+    /// Encoder output frames decoded since the last reset
+    frames: usize,
+}
+
+impl Engine {
+    /// Load the model files from `path` (see `models::check`).
+    pub fn load(path: &Path, runtime: &Runtime) -> Result<Self, RustscriberError> {
+        let vocab = SentencePieceVocab::from_file(path.join("tokenizer.model"))
+            .map_err(|e| RustscriberError::model_load(path, e))?;
+        let session = |name: &str| {
+            let file = path.join(name);
+            if !file.exists() {
+                return Err(RustscriberError::model_load(
+                    path,
+                    format!("Missing {}", name),
+                ));
+            }
+            runtime
+                .session(&file)
+                .map_err(|e| RustscriberError::model_load(path, e))
+        };
+        let (encoder, provider) = session("encoder.onnx")?;
+        let (decoder_joint, _) = session("decoder_joint.onnx")?;
+
+        Ok(Self {
+            encoder,
+            decoder_joint,
+            provider,
+            vocab,
+            encoder_cache: new_cache(),
+            state_1: Array3::zeros((2, 1, DECODER_LSTM_DIM)),
+            state_2: Array3::zeros((2, 1, DECODER_LSTM_DIM)),
+            last_token: BLANK_ID as i32,
+            mel_basis: mel_filterbank(),
+            window: hann_window(),
+            fft: RealFftPlanner::<f32>::new().plan_fft_forward(N_FFT),
+            audio_buffer: Vec::new(),
+            audio_processed: 0,
+            chunk_idx: 0,
+            frames: 0,
+        })
+    }
+
+    /// The execution provider the model runs on, e.g. `cpu`.
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    /// Start again from a clean decoder state, e.g. for the next utterance.
+    pub fn reset(&mut self) {
+        self.encoder_cache = new_cache();
+        self.state_1.fill(0.0);
+        self.state_2.fill(0.0);
+        self.last_token = BLANK_ID as i32;
+        self.audio_buffer.clear();
+        self.audio_processed = 0;
+        self.chunk_idx = 0;
+        self.frames = 0;
+    }
+
+    /// Buffer `audio` and, once a full chunk of new mel frames is available,
+    /// run it through the encoder and decoder.
+    pub fn transcribe_chunk(&mut self, audio: &[f32]) -> Result<ChunkOutput, RustscriberError> {
+        self.audio_buffer.extend_from_slice(audio);
+        let mut output = ChunkOutput {
+            tokens: Vec::new(),
+            first_frame: self.frames,
+        };
+        if self.audio_buffer.len() < WIN_LENGTH {
+            return Ok(output);
+        }
+
+        // Frames as a centred STFT over the whole buffer would produce them
+        let total_frames =
+            1 + (self.audio_buffer.len() + 2 * (N_FFT / 2) - WIN_LENGTH) / HOP_LENGTH;
+        let main_start = self.audio_processed / HOP_LENGTH;
+        if total_frames.saturating_sub(main_start) < CHUNK_SIZE {
+            return Ok(output);
+        }
+
+        // Zeros stand in for the left context of the first chunk
+        let context_start = match self.chunk_idx {
+            0 => main_start,
+            _ => main_start.saturating_sub(PRE_ENCODE_CACHE),
+        };
+        let mel = self.log_mel(context_start, main_start + CHUNK_SIZE);
+        let width = PRE_ENCODE_CACHE + CHUNK_SIZE;
+        let offset = PRE_ENCODE_CACHE - (main_start - context_start);
+        let mut chunk = Array3::zeros((1, N_MELS, width));
+        chunk
+            .slice_mut(s![0, .., offset..offset + mel.ncols()])
+            .assign(&mel);
+
+        let (encoded, enc_len) = self.run_encoder(chunk, width)?;
+        output.tokens = self.decode(&encoded, enc_len)?;
+
+        self.audio_processed += CHUNK_SIZE * HOP_LENGTH;
+        self.chunk_idx += 1;
+
+        // Keep memory bounded, holding on to enough for the left context
+        let keep = (PRE_ENCODE_CACHE + CHUNK_SIZE) * HOP_LENGTH + WIN_LENGTH;
+        if self.audio_buffer.len() > keep * 2 {
+            let remove = (self.audio_buffer.len() - keep).min(self.audio_processed);
+            self.audio_buffer.drain(..remove);
+            self.audio_processed -= remove;
+        }
+        Ok(output)
+    }
+
+    /// Greedy transducer decoding of `frames` encoder output frames.
+    fn decode(
+        &mut self,
+        encoded: &Array3<f32>,
+        frames: usize,
+    ) -> Result<Vec<DecodedToken>, RustscriberError> {
+        let mut tokens = Vec::new();
+        for t in 0..frames {
+            let frame = encoded.slice(s![.., .., t..t + 1]).to_owned();
+
+            for _ in 0..MAX_SYMBOLS_PER_STEP {
+                let step = self.run_decoder(&frame)?;
+                let (id, log_prob) = best_token(&step.logits);
+                if id == BLANK_ID {
+                    break;
+                }
+                if id < VOCAB_SIZE {
+                    tokens.push(DecodedToken {
+                        id,
+                        text: self.vocab.decode_single(id),
+                        frame: self.frames + t,
+                        log_prob,
+                    });
+                }
+                self.last_token = id as i32;
+                self.state_1 = step.state_1;
+                self.state_2 = step.state_2;
+            }
+        }
+        self.frames += frames;
+        Ok(tokens)
+    }
+
+    /// Run `features` (`[1, N_MELS, length]`) through the encoder, carrying
+    /// its cache over from the last chunk. Returns the encoded frames
+    /// (`[1, HIDDEN_DIM, frames]`) and how many of them are valid.
+    fn run_encoder(
+        &mut self,
+        features: Array3<f32>,
+        length: usize,
+    ) -> Result<(Array3<f32>, usize), RustscriberError> {
+        let cache = &self.encoder_cache;
+        let outputs = self
+            .encoder
+            .run(ort::inputs![
+                "processed_signal" => Value::from_array(features).map_err(inference)?,
+                "processed_signal_length" => Value::from_array(Array1::from_vec(vec![length as i64])).map_err(inference)?,
+                "cache_last_channel" => Value::from_array(cache.cache_last_channel.clone()).map_err(inference)?,
+                "cache_last_time" => Value::from_array(cache.cache_last_time.clone()).map_err(inference)?,
+                "cache_last_channel_len" => Value::from_array(cache.cache_last_channel_len.clone()).map_err(inference)?
+            ])
+            .map_err(inference)?;
+
+        let encoded = output(&outputs, "encoded")?;
+        let encoded_len: Array1<i64> = output(&outputs, "encoded_len")?;
+        let cache = NemotronEncoderCache {
+            cache_last_channel: output(&outputs, "cache_last_channel_next")?,
+            cache_last_time: output(&outputs, "cache_last_time_next")?,
+            cache_last_channel_len: output(&outputs, "cache_last_channel_len_next")?,
+        };
+        drop(outputs);
+        self.encoder_cache = cache;
+        Ok((encoded, encoded_len[0].max(0) as usize))
+    }
+
+    /// One decoder and joint network step on `frame` (`[1, HIDDEN_DIM, 1]`)
+    /// after the last emitted token.
+    fn run_decoder(&mut self, frame: &Array3<f32>) -> Result<DecoderStep, RustscriberError> {
+        let outputs = self
+            .decoder_joint
+            .run(ort::inputs![
+                "encoder_outputs" => Value::from_array(frame.clone()).map_err(inference)?,
+                "targets" => Value::from_array(Array2::from_elem((1, 1), self.last_token)).map_err(inference)?,
+                "target_length" => Value::from_array(Array1::from_vec(vec![1i32])).map_err(inference)?,
+                "input_states_1" => Value::from_array(self.state_1.clone()).map_err(inference)?,
+                "input_states_2" => Value::from_array(self.state_2.clone()).map_err(inference)?
+            ])
+            .map_err(inference)?;
+
+        // The joint network's output is [1, 1, 1, vocab + blank]
+        let logits = outputs["outputs"]
+            .try_extract_array::<f32>()
+            .map_err(inference)?
+            .iter()
+            .copied()
+            .collect();
+        Ok(DecoderStep {
+            logits,
+            state_1: output(&outputs, "output_states_1")?,
+            state_2: output(&outputs, "output_states_2")?,
+        })
+    }
+
+    /// Log-mel frames `from..to` of the pre-emphasised buffer, zero-padded
+    /// at both ends as a centred STFT is. No normalisation: the encoder takes
+    /// raw log-mel values.
+    fn log_mel(&self, from: usize, to: usize) -> Array2<f32> {
+        let audio = &self.audio_buffer;
+        let pad = N_FFT / 2;
+        let sample = |i: usize| -> f32 {
+            // Index into the padded, pre-emphasised signal
+            let Some(i) = i.checked_sub(pad).filter(|&i| i < audio.len()) else {
+                return 0.0;
+            };
+            let v = match i {
+                0 => audio[0],
+                _ => audio[i] - PREEMPH * audio[i - 1],
+            };
+            if v.is_finite() { v } else { 0.0 }
+        };
+
+        let bins = N_FFT / 2 + 1;
+        let mut power = Array2::zeros((bins, to - from));
+        let mut input = self.fft.make_input_vec();
+        let mut spectrum = self.fft.make_output_vec();
+        for (column, frame) in (from..to).enumerate() {
+            let start = frame * HOP_LENGTH;
+            input.fill(0.0);
+            for (i, w) in self.window.iter().enumerate() {
+                input[i] = sample(start + i) * w;
+            }
+            // Lengths come from the planner, so this can't fail
+            let _ = self.fft.process(&mut input, &mut spectrum);
+            for (bin, value) in spectrum.iter().enumerate() {
+                let p = value.norm_sqr();
+                power[[bin, column]] = if p.is_finite() { p } else { 0.0 };
+            }
+        }
+
+        self.mel_basis
+            .dot(&power)
+            .mapv(|x| (x.max(0.0) + LOG_ZERO_GUARD).ln())
+    }
+}
+
+impl Recognizer for Engine {
+    fn transcribe_chunk(&mut self, audio: &[f32]) -> Result<ChunkOutput, RustscriberError> {
+        Engine::transcribe_chunk(self, audio)
+    }
+
+    fn reset(&mut self) {
+        Engine::reset(self)
+    }
+}
+
+fn inference(error: impl fmt::Display) -> RustscriberError {
+    RustscriberError::Inference(error.to_string())
+}
+
+/// Session output `name` as an owned array of the expected rank.
+fn output<T, D>(outputs: &SessionOutputs, name: &str) -> Result<Array<T, D>, RustscriberError>
+where
+    T: PrimitiveTensorElementType + Clone,
+    D: Dimension,
+{
+    outputs[name]
+        .try_extract_array::<T>()
+        .map_err(inference)?
+        .into_dimensionality::<D>()
+        .map(|array| array.to_owned())
+        .map_err(|e| inference(format!("{}: {}", name, e)))
+}
+
+fn new_cache() -> NemotronEncoderCache {
+    NemotronEncoderCache::with_dims(NUM_ENCODER_LAYERS, LEFT_CONTEXT, HIDDEN_DIM, CONV_CONTEXT)
+}
+
+/// The most likely token and its log-probability under a softmax of `logits`.
+fn best_token(logits: &[f32]) -> (usize, f32) {
+    let (id, max) =
+        logits
+            .iter()
+            .copied()
+            .enumerate()
+            .fold((BLANK_ID, f32::NEG_INFINITY), |best, (i, v)| {
+                if v > best.1 { (i, v) } else { best }
+            });
+    let sum: f32 = logits.iter().map(|v| (v - max).exp()).sum();
+    (id, -sum.ln())
+}
+
+/// Join subword tokens into words. A piece starting with a space starts a
+/// new word; the others continue the current one.
+pub fn words(tokens: &[Token], source: Option<&str>) -> Vec<Word> {
+    let mut words: Vec<(Word, f32, usize)> = Vec::new();
+    for token in tokens {
+        let starts_word = token.text.starts_with(' ');
+        match words.last_mut() {
+            Some((word, log_prob, count)) if !starts_word => {
+                word.word.push_str(&token.text);
+                word.end = token.end;
+                *log_prob += token.log_prob;
+                *count += 1;
+            }
+            _ => words.push((
+                Word {
+                    word: token.text.trim_start().to_string(),
+                    start: token.start,
+                    end: token.end,
+                    confidence: 0.0,
+                    source: source.map(str::to_string),
+                },
+                token.log_prob,
+                1,
+            )),
+        }
+    }
+    words
+        .into_iter()
+        .filter(|(word, _, _)| !word.word.is_empty())
+        .map(|(mut word, log_prob, count)| {
+            word.confidence = (log_prob / count as f32).exp();
+            word
+        })
+        .collect()
+}
+
+fn hann_window() -> Vec<f32> {
+    (0..WIN_LENGTH)
+        .map(|i| 0.5 - 0.5 * ((2.0 * PI * i as f32) / ((WIN_LENGTH - 1) as f32)).cos())
+        .collect()
+}
+
+/// Slaney-scale mel filterbank with Slaney normalisation, 0–8 kHz.
+fn mel_filterbank() -> Array2<f32> {
+    const F_SP: f32 = 200.0 / 3.0;
+    const MIN_LOG_HZ: f32 = 1000.0;
+    const MIN_LOG_MEL: f32 = MIN_LOG_HZ / F_SP;
+    const LOG_STEP: f32 = 0.068_751_78;
+
+    let hz_to_mel = |hz: f32| {
+        if hz < MIN_LOG_HZ {
+            hz / F_SP
+        } else {
+            MIN_LOG_MEL + (hz / MIN_LOG_HZ).ln() / LOG_STEP
+        }
+    };
+    let mel_to_hz = |mel: f32| {
+        if mel < MIN_LOG_MEL {
+            mel * F_SP
+        } else {
+            MIN_LOG_HZ * ((mel - MIN_LOG_MEL) * LOG_STEP).exp()
+        }
+    };
+
+    let bins = N_FFT / 2 + 1;
+    let mel_max = hz_to_mel(FMAX);
+    let points: Vec<f32> = (0..N_MELS + 2)
+        .map(|i| mel_to_hz(mel_max * i as f32 / (N_MELS + 1) as f32))
+        .collect();
+
+    let mut weights = Array2::zeros((N_MELS, bins));
+    for m in 0..N_MELS {
+        let (left, center, right) = (points[m], points[m + 1], points[m + 2]);
+        let norm = 2.0 / (right - left);
+        for bin in 0..bins {
+            let freq = SAMPLE_RATE as f32 / N_FFT as f32 * bin as f32;
+            let weight = if freq >= left && freq <= center && center != left {
+                (freq - left) / (center - left)
+            } else if freq > center && freq <= right && right != center {
+                (right - freq) / (right - center)
+            } else {
+                0.0
+            };
+            weights[[m, bin]] = weight * norm;
+        }
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(text: &str, start: f64, log_prob: f32) -> Token {
+        Token {
+            id: 0,
+            text: text.to_string(),
+            frame: 0,
+            start,
+            end: start + FRAME_SECS,
+            log_prob,
+        }
+    }
+
+    #[test]
+    fn subwords_join_into_words() {
+        let tokens = [
+            token(" hel", 0.0, 0.0),
+            token("lo", 0.08, (0.25f32).ln()),
+            token(" world", 0.4, (0.9f32).ln()),
+        ];
+        let words = words(&tokens, Some("ch1"));
+        assert_eq!(words.len(), 2);
+        assert_eq!(words[0].word, "hello");
+        assert_eq!((words[0].start, words[0].end), (0.0, 0.16));
+        assert!((words[0].confidence - 0.5).abs() < 1e-6);
+        assert_eq!(words[1].word, "world");
+        assert_eq!(words[1].source.as_deref(), Some("ch1"));
+    }
+
+    #[test]
+    fn best_token_is_a_log_softmax() {
+        let (id, log_prob) = best_token(&[0.0, 2.0f32.ln(), 0.0]);
+        assert_eq!(id, 1);
+        assert!((log_prob - 0.5f32.ln()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tokens_are_placed_by_frame() {
+        let output = ChunkOutput {
+            tokens: vec![DecodedToken {
+                id: 7,
+                text: " hi".to_string(),
+                frame: 9,
+                log_prob: 0.0,
+            }],
+            first_frame: 7,
+        };
+        let timed = output.timed(1.0, 1.56);
+        assert!((timed[0].start - 1.16).abs() < 1e-9);
+        assert!((timed[0].end - 1.24).abs() < 1e-9);
+    }
+
+    /// Checks this engine still decodes exactly as the parakeet-rs it was
+    /// written against. Needs the model: point `RUSTSCRIBER_TEST_MODEL` at its
+    /// directory and, optionally, `RUSTSCRIBER_TEST_AUDIO` at a 16 kHz mono
+    /// WAV with speech in it.
+    #[test]
+    #[ignore = "needs the model files"]
+    fn matches_parakeet_nemotron() {
+        let Some(model) = std::env::var_os("RUSTSCRIBER_TEST_MODEL") else {
+            panic!("Set RUSTSCRIBER_TEST_MODEL to the Nemotron model directory");
+        };
+        let model = Path::new(&model);
+        let audio: Vec<f32> = match std::env::var_os("RUSTSCRIBER_TEST_AUDIO") {
+            Some(path) => {
+                let mut reader = hound::WavReader::open(path).unwrap();
+                let spec = reader.spec();
+                assert_eq!((spec.sample_rate, spec.channels), (16000, 1));
+                match spec.sample_format {
+                    hound::SampleFormat::Float => {
+                        reader.samples::<f32>().map(Result::unwrap).collect()
+                    }
+                    hound::SampleFormat::Int => reader
+                        .samples::<i32>()
+                        .map(|s| s.unwrap() as f32 / (1u32 << (spec.bits_per_sample - 1)) as f32)
+                        .collect(),
+                }
+            }
+            None => (0..5 * SAMPLE_RATE)
+                .map(|i| 0.3 * (2.0 * PI * 220.0 * i as f32 / SAMPLE_RATE as f32).sin())
+                .collect(),
+        };
+
+        let mut engine = Engine::load(model, &Runtime::default()).unwrap();
+        let mut reference = parakeet_rs::Nemotron::from_pretrained(model, None).unwrap();
+        let mut ids = Vec::new();
+        for chunk in audio.chunks(CHUNK_SIZE * HOP_LENGTH) {
+            let output = engine.transcribe_chunk(chunk).unwrap();
+            ids.extend(output.tokens.iter().map(|t| t.id));
+            reference.transcribe_chunk(chunk).unwrap();
+        }
+        assert_eq!(engine.vocab.decode(&ids), reference.get_transcript());
+    }
+}
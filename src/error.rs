@@ -0,0 +1,240 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Shown whenever the device went away underneath us
+const UNPLUGGED: &str = "The input device is no longer available (was it unplugged?). \
+                         Run `rustscriber devices` to see what is connected";
+
+/// What went wrong, grouped by what the user has to do about it. Each kind
+/// has its own process exit status (see `exit_code`).
+#[derive(Debug)]
+pub enum RustscriberError {
+    /// The arguments or config file ask for something that doesn't make sense
+    Settings(String),
+    /// An audio host or input device couldn't be found, opened or kept running
+    Device(String),
+    /// None of the stream configs the device offers fits what was asked for
+    ConfigNegotiation(String),
+    /// The model couldn't be loaded from its directory
+    ModelLoad { path: PathBuf, message: String },
+    /// The model failed on a chunk of audio
+    Inference(String),
+    /// The resampler couldn't be set up for the input rate
+    Resampler(String),
+    /// Reading or writing failed; `context` says what was being done
+    Io { context: String, source: io::Error },
+    /// Audio in a format rustscriber can't handle
+    Format(String),
+    /// The command couldn't be carried out, e.g. recording while already
+    /// recording, or some files of a batch failing
+    Failed(String),
+}
+
+impl RustscriberError {
+    pub fn io(context: impl Into<String>, source: io::Error) -> Self {
+        RustscriberError::Io {
+            context: context.into(),
+            source,
+        }
+    }
+
+    /// Sort a WAV error into I/O trouble or a file hound can't handle.
+    pub fn wav(context: impl Into<String>, error: hound::Error) -> Self {
+        match error {
+            hound::Error::IoError(source) => Self::io(context, source),
+            other => RustscriberError::Format(format!("{}: {}", context.into(), other)),
+        }
+    }
+
+    pub fn model_load(path: impl Into<PathBuf>, error: impl fmt::Display) -> Self {
+        RustscriberError::ModelLoad {
+            path: path.into(),
+            message: error.to_string(),
+        }
+    }
+
+    /// Process exit status. 2 matches clap's status for invalid arguments.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            RustscriberError::Failed(_) => 1,
+            RustscriberError::Settings(_) => 2,
+            RustscriberError::Device(_) => 3,
+            RustscriberError::ConfigNegotiation(_) => 4,
+            RustscriberError::ModelLoad { .. } => 5,
+            RustscriberError::Inference(_) => 6,
+            RustscriberError::Resampler(_) => 7,
+            RustscriberError::Io { .. } => 8,
+            RustscriberError::Format(_) => 9,
+        }
+    }
+}
+
+impl fmt::Display for RustscriberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RustscriberError::Settings(message)
+            | RustscriberError::Device(message)
+            | RustscriberError::ConfigNegotiation(message)
+            | RustscriberError::Format(message)
+            | RustscriberError::Failed(message) => write!(f, "{}", message),
+            RustscriberError::ModelLoad { path, message } => write!(
+                f,
+                "Failed to load the model from {}: {}\n\
+                 Check the model files with `rustscriber models --model {}`",
+                path.display(),
+                message,
+                path.display()
+            ),
+            RustscriberError::Inference(message) => {
+                write!(f, "Speech recognition failed: {}", message)
+            }
+            RustscriberError::Resampler(message) => write!(
+                f,
+                "Failed to set up resampling to 16 kHz: {}\n\
+                 Try capturing at another rate with --sample-rate",
+                message
+            ),
+            RustscriberError::Io { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for RustscriberError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RustscriberError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<cpal::BuildStreamError> for RustscriberError {
+    fn from(error: cpal::BuildStreamError) -> Self {
+        match error {
+            cpal::BuildStreamError::DeviceNotAvailable => {
+                RustscriberError::Device(UNPLUGGED.to_string())
+            }
+            cpal::BuildStreamError::StreamConfigNotSupported
+            | cpal::BuildStreamError::InvalidArgument => {
+                RustscriberError::ConfigNegotiation(format!(
+                    "The device rejected the stream config ({}). Try other \
+                     --sample-rate, --device-channels or --sample-format values",
+                    error
+                ))
+            }
+            other => {
+                RustscriberError::Device(format!("Failed to open the input stream: {}", other))
+            }
+        }
+    }
+}
+
+impl From<cpal::PlayStreamError> for RustscriberError {
+    fn from(error: cpal::PlayStreamError) -> Self {
+        match error {
+            cpal::PlayStreamError::DeviceNotAvailable => {
+                RustscriberError::Device(UNPLUGGED.to_string())
+            }
+            other => {
+                RustscriberError::Device(format!("Failed to start the input stream: {}", other))
+            }
+        }
+    }
+}
+
+impl From<cpal::PauseStreamError> for RustscriberError {
+    fn from(error: cpal::PauseStreamError) -> Self {
+        match error {
+            cpal::PauseStreamError::DeviceNotAvailable => {
+                RustscriberError::Device(UNPLUGGED.to_string())
+            }
+            other => {
+                RustscriberError::Device(format!("Failed to pause the input stream: {}", other))
+            }
+        }
+    }
+}
+
+impl From<cpal::SupportedStreamConfigsError> for RustscriberError {
+    fn from(error: cpal::SupportedStreamConfigsError) -> Self {
+        match error {
+            cpal::SupportedStreamConfigsError::DeviceNotAvailable => {
+                RustscriberError::Device(UNPLUGGED.to_string())
+            }
+            other => RustscriberError::Device(format!(
+                "Failed to read the device's supported configs: {}",
+                other
+            )),
+        }
+    }
+}
+
+impl From<cpal::DevicesError> for RustscriberError {
+    fn from(error: cpal::DevicesError) -> Self {
+        RustscriberError::Device(format!("Failed to list input devices: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(description: &str) -> cpal::BackendSpecificError {
+        cpal::BackendSpecificError {
+            description: description.to_string(),
+        }
+    }
+
+    #[test]
+    fn each_kind_has_its_own_exit_code() {
+        let errors = [
+            (RustscriberError::Failed(String::new()), 1),
+            (RustscriberError::Settings(String::new()), 2),
+            (RustscriberError::Device(String::new()), 3),
+            (RustscriberError::ConfigNegotiation(String::new()), 4),
+            (RustscriberError::model_load("model", "missing"), 5),
+            (RustscriberError::Inference(String::new()), 6),
+            (RustscriberError::Resampler(String::new()), 7),
+            (RustscriberError::io("reading", io::Error::other("disk")), 8),
+            (RustscriberError::Format(String::new()), 9),
+        ];
+        for (error, code) in errors {
+            assert_eq!(error.exit_code(), code, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn sorts_wav_errors() {
+        let error = RustscriberError::wav("x.wav", hound::Error::IoError(io::Error::other("disk")));
+        assert_eq!(error.exit_code(), 8);
+        let error = RustscriberError::wav("x.wav", hound::Error::Unsupported);
+        assert_eq!(error.exit_code(), 9);
+        assert!(error.to_string().starts_with("x.wav: "));
+    }
+
+    #[test]
+    fn sorts_cpal_errors() {
+        let unplugged = RustscriberError::from(cpal::BuildStreamError::DeviceNotAvailable);
+        assert_eq!(unplugged.exit_code(), 3);
+        assert_eq!(unplugged.to_string(), UNPLUGGED);
+        let rejected = RustscriberError::from(cpal::BuildStreamError::StreamConfigNotSupported);
+        assert_eq!(rejected.exit_code(), 4);
+        let other = RustscriberError::from(cpal::BuildStreamError::BackendSpecific {
+            err: backend("busy"),
+        });
+        assert!(matches!(other, RustscriberError::Device(ref m) if m.ends_with("busy")));
+
+        for error in [
+            RustscriberError::from(cpal::PlayStreamError::DeviceNotAvailable),
+            RustscriberError::from(cpal::PauseStreamError::DeviceNotAvailable),
+            RustscriberError::from(cpal::SupportedStreamConfigsError::DeviceNotAvailable),
+        ] {
+            assert_eq!(error.to_string(), UNPLUGGED);
+        }
+        let error = RustscriberError::from(cpal::DevicesError::BackendSpecific {
+            err: backend("no server"),
+        });
+        assert_eq!(error.exit_code(), 3);
+    }
+}
@@ -0,0 +1,458 @@
+//! Measuring accuracy against reference transcripts: word and character
+//! error rates over a NeMo-style manifest.
+
+use crate::error::RustscriberError;
+use crate::transcriber::TranscriptEvent;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+
+/// One line of a manifest. Other NeMo fields (e.g. `duration`) are ignored.
+#[derive(Deserialize)]
+pub struct ManifestEntry {
+    pub audio_filepath: PathBuf,
+    /// The reference transcript
+    pub text: String,
+}
+
+/// Read a JSONL manifest. Relative audio paths are taken from the
+/// manifest's directory, so a manifest can sit next to its clips.
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>, RustscriberError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| RustscriberError::io(format!("Failed to read {}", path.display()), e))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut entries = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut entry: ManifestEntry = serde_json::from_str(line).map_err(|e| {
+            RustscriberError::Settings(format!("{} line {}: {}", path.display(), number + 1, e))
+        })?;
+        entry.audio_filepath = dir.join(&entry.audio_filepath);
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Lowercase, drop punctuation and collapse whitespace, so only the words
+/// are compared. Apostrophes inside words are kept ("don't"); hyphens split
+/// words ("well-known" → "well known").
+pub fn normalize(text: &str) -> String {
+    let cleaned: String = text
+        .to_lowercase()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '\'' {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect();
+    cleaned
+        .split_whitespace()
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The text of a file's transcript, in time order.
+pub fn hypothesis(events: &[TranscriptEvent]) -> String {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            TranscriptEvent::Text { text, .. } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// One step of an alignment between a reference and a hypothesis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edit<T> {
+    Same(T),
+    Substitute {
+        reference: T,
+        hypothesis: T,
+    },
+    /// In the reference but missing from the hypothesis
+    Delete(T),
+    /// In the hypothesis but not the reference
+    Insert(T),
+}
+
+/// A minimum edit distance alignment of `hypothesis` against `reference`.
+/// Among equally short alignments, substitutions are preferred over a
+/// deletion and insertion pair.
+pub fn align<T: Copy + PartialEq>(reference: &[T], hypothesis: &[T]) -> Vec<Edit<T>> {
+    let (n, m) = (reference.len(), hypothesis.len());
+    // cost[i][j]: edits to turn the first i reference items into the first
+    // j hypothesis items
+    let mut cost = vec![vec![0u32; m + 1]; n + 1];
+    for (i, row) in cost.iter_mut().enumerate() {
+        row[0] = i as u32;
+    }
+    for (j, cell) in cost[0].iter_mut().enumerate() {
+        *cell = j as u32;
+    }
+    for i in 1..=n {
+        for j in 1..=m {
+            let diagonal = cost[i - 1][j - 1] + u32::from(reference[i - 1] != hypothesis[j - 1]);
+            cost[i][j] = diagonal.min(cost[i - 1][j] + 1).min(cost[i][j - 1] + 1);
+        }
+    }
+
+    let mut edits = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (n, m);
+    while i > 0 || j > 0 {
+        if i > 0 && j > 0 {
+            let same = reference[i - 1] == hypothesis[j - 1];
+            if cost[i][j] == cost[i - 1][j - 1] + u32::from(!same) {
+                edits.push(if same {
+                    Edit::Same(reference[i - 1])
+                } else {
+                    Edit::Substitute {
+                        reference: reference[i - 1],
+                        hypothesis: hypothesis[j - 1],
+                    }
+                });
+                i -= 1;
+                j -= 1;
+                continue;
+            }
+        }
+        if i > 0 && cost[i][j] == cost[i - 1][j] + 1 {
+            edits.push(Edit::Delete(reference[i - 1]));
+            i -= 1;
+        } else {
+            edits.push(Edit::Insert(hypothesis[j - 1]));
+            j -= 1;
+        }
+    }
+    edits.reverse();
+    edits
+}
+
+/// The error counts of the alignment `align` would find, keeping only two
+/// rows of costs rather than the whole matrix. Used for characters, whose
+/// alignment is never shown.
+pub fn count_errors<T: PartialEq>(reference: &[T], hypothesis: &[T]) -> ErrorCounts {
+    // Each cell holds the counts of its best path; ties are broken as
+    // `align`'s traceback breaks them: substitution, deletion, insertion
+    let mut previous: Vec<ErrorCounts> = (0..=hypothesis.len())
+        .map(|j| ErrorCounts {
+            insertions: j,
+            ..Default::default()
+        })
+        .collect();
+    let mut current = previous.clone();
+    for (i, r) in reference.iter().enumerate() {
+        current[0] = ErrorCounts {
+            reference: i + 1,
+            deletions: i + 1,
+            ..Default::default()
+        };
+        for (j, h) in hypothesis.iter().enumerate() {
+            let mut diagonal = previous[j];
+            diagonal.reference += 1;
+            diagonal.substitutions += usize::from(r != h);
+            let mut delete = previous[j + 1];
+            delete.reference += 1;
+            delete.deletions += 1;
+            let mut insert = current[j];
+            insert.insertions += 1;
+            current[j + 1] = [delete, insert].into_iter().fold(diagonal, |best, c| {
+                if c.errors() < best.errors() { c } else { best }
+            });
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[hypothesis.len()]
+}
+
+/// Error counts against a reference of `reference` words (or characters).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ErrorCounts {
+    pub reference: usize,
+    pub substitutions: usize,
+    pub deletions: usize,
+    pub insertions: usize,
+}
+
+impl ErrorCounts {
+    pub fn from_edits<T>(edits: &[Edit<T>]) -> Self {
+        let mut counts = Self::default();
+        for edit in edits {
+            match edit {
+                Edit::Same(_) => counts.reference += 1,
+                Edit::Substitute { .. } => {
+                    counts.reference += 1;
+                    counts.substitutions += 1;
+                }
+                Edit::Delete(_) => {
+                    counts.reference += 1;
+                    counts.deletions += 1;
+                }
+                Edit::Insert(_) => counts.insertions += 1,
+            }
+        }
+        counts
+    }
+
+    pub fn errors(&self) -> usize {
+        self.substitutions + self.deletions + self.insertions
+    }
+
+    /// Errors per reference item. An empty reference counts each inserted
+    /// item as a whole error rather than dividing by zero.
+    pub fn rate(&self) -> f64 {
+        self.errors() as f64 / self.reference.max(1) as f64
+    }
+}
+
+impl AddAssign for ErrorCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.reference += other.reference;
+        self.substitutions += other.substitutions;
+        self.deletions += other.deletions;
+        self.insertions += other.insertions;
+    }
+}
+
+impl fmt::Display for ErrorCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}% ({} sub, {} del, {} ins / {})",
+            self.rate() * 100.0,
+            self.substitutions,
+            self.deletions,
+            self.insertions,
+            self.reference
+        )
+    }
+}
+
+/// How a hypothesis compares to its reference, after normalizing both.
+#[derive(Serialize)]
+pub struct Score {
+    pub reference: String,
+    pub hypothesis: String,
+    pub wer: f64,
+    pub cer: f64,
+    pub words: ErrorCounts,
+    pub chars: ErrorCounts,
+    /// The word alignment, marked up like `git diff --word-diff`: words
+    /// only in the reference as `[-word-]`, only in the hypothesis as
+    /// `{+word+}`
+    pub alignment: String,
+}
+
+impl Score {
+    pub fn new(reference: &str, hypothesis: &str) -> Self {
+        let reference = normalize(reference);
+        let hypothesis = normalize(hypothesis);
+        let ref_words: Vec<&str> = reference.split(' ').filter(|w| !w.is_empty()).collect();
+        let hyp_words: Vec<&str> = hypothesis.split(' ').filter(|w| !w.is_empty()).collect();
+        let ref_chars: Vec<char> = reference.chars().collect();
+        let hyp_chars: Vec<char> = hypothesis.chars().collect();
+        let edits = align(&ref_words, &hyp_words);
+        let words = ErrorCounts::from_edits(&edits);
+        let chars = count_errors(&ref_chars, &hyp_chars);
+        let alignment = edits
+            .iter()
+            .map(|edit| match edit {
+                Edit::Same(word) => word.to_string(),
+                Edit::Substitute {
+                    reference,
+                    hypothesis,
+                } => format!("[-{}-]{{+{}+}}", reference, hypothesis),
+                Edit::Delete(word) => format!("[-{}-]", word),
+                Edit::Insert(word) => format!("{{+{}+}}", word),
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        Self {
+            wer: words.rate(),
+            cer: chars.rate(),
+            reference,
+            hypothesis,
+            words,
+            chars,
+            alignment,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// One manifest entry's outcome.
+#[derive(Serialize)]
+pub struct FileReport {
+    pub audio_filepath: String,
+    #[serde(flatten)]
+    pub result: FileResult,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum FileResult {
+    Scored(Score),
+    /// The file couldn't be read or transcribed
+    Failed {
+        error: String,
+    },
+}
+
+/// Totals over every scored file. The rates weigh each file by its length,
+/// not each file equally.
+#[derive(Default, Serialize)]
+pub struct Overall {
+    pub files: usize,
+    pub failed: usize,
+    pub wer: f64,
+    pub cer: f64,
+    pub words: ErrorCounts,
+    pub chars: ErrorCounts,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub files: Vec<FileReport>,
+    pub overall: Overall,
+}
+
+impl Report {
+    pub fn new(files: Vec<FileReport>) -> Self {
+        let mut overall = Overall {
+            files: files.len(),
+            ..Default::default()
+        };
+        for file in &files {
+            match &file.result {
+                FileResult::Scored(score) => {
+                    overall.words += score.words;
+                    overall.chars += score.chars;
+                }
+                FileResult::Failed { .. } => overall.failed += 1,
+            }
+        }
+        overall.wer = overall.words.rate();
+        overall.cer = overall.chars.rate();
+        Self { files, overall }
+    }
+
+    /// Format the report for output. With `diff`, text output shows the
+    /// alignment under each file that has word errors.
+    pub fn render(&self, format: ReportFormat, diff: bool) -> String {
+        match format {
+            ReportFormat::Json => {
+                let mut json = serde_json::to_string_pretty(self).unwrap_or_default();
+                json.push('\n');
+                json
+            }
+            ReportFormat::Text => {
+                let mut out = String::new();
+                for file in &self.files {
+                    match &file.result {
+                        FileResult::Scored(score) => {
+                            out.push_str(&format!(
+                                "{}: WER {}, CER {}\n",
+                                file.audio_filepath, score.words, score.chars
+                            ));
+                            if diff && score.words.errors() > 0 {
+                                out.push_str(&format!("    {}\n", score.alignment));
+                            }
+                        }
+                        FileResult::Failed { error } => {
+                            out.push_str(&format!("{}: failed: {}\n", file.audio_filepath, error));
+                        }
+                    }
+                }
+                let overall = &self.overall;
+                let failed = match overall.failed {
+                    0 => String::new(),
+                    n => format!(", {} failed", n),
+                };
+                out.push_str(&format!(
+                    "Overall ({} files{}): WER {}, CER {}\n",
+                    overall.files, failed, overall.words, overall.chars
+                ));
+                out
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_keeps_only_words() {
+        assert_eq!(
+            normalize("  Hello, World! It's a well-known 'quote'. "),
+            "hello world it's a well known quote"
+        );
+    }
+
+    #[test]
+    fn counts_each_kind_of_error() {
+        let score = Score::new("the cat sat on the mat", "Today, the cat sit on mat.");
+        assert_eq!(
+            score.words,
+            ErrorCounts {
+                reference: 6,
+                substitutions: 1,
+                deletions: 1,
+                insertions: 1,
+            }
+        );
+        assert!((score.words.rate() - 0.5).abs() < 1e-9);
+        assert_eq!(
+            score.alignment,
+            "{+today+} the cat [-sat-]{+sit+} on [-the-] mat"
+        );
+    }
+
+    #[test]
+    fn identical_text_has_no_errors() {
+        let score = Score::new("Good morning.", "good morning");
+        assert_eq!(score.words.errors(), 0);
+        assert_eq!(score.chars.errors(), 0);
+        assert_eq!(score.chars.reference, "good morning".len());
+    }
+
+    #[test]
+    fn counting_agrees_with_the_alignment() {
+        let pairs = [
+            ("kitten", "sitting"),
+            ("", "abc"),
+            ("abc", ""),
+            ("the cat sat", "a cat sat down"),
+            ("aaab", "abbb"),
+            ("flaw", "lawn"),
+        ];
+        for (reference, hypothesis) in pairs {
+            let reference: Vec<char> = reference.chars().collect();
+            let hypothesis: Vec<char> = hypothesis.chars().collect();
+            assert_eq!(
+                count_errors(&reference, &hypothesis),
+                ErrorCounts::from_edits(&align(&reference, &hypothesis)),
+                "{:?} / {:?}",
+                reference,
+                hypothesis
+            );
+        }
+    }
+}
@@ -0,0 +1,301 @@
+//! Where ONNX Runtime runs the model: the execution provider, its thread
+//! pools and how far the graph is optimized. Which providers exist depends on
+//! the Cargo features rustscriber was built with; which of those actually
+//! work depends on the ONNX Runtime library it finds.
+
+use ort::session::Session;
+use ort::session::builder::{GraphOptimizationLevel, SessionBuilder};
+use parakeet_rs::ExecutionProvider;
+use std::fmt;
+use std::path::Path;
+
+/// ONNX Runtime's intra-op pool size when none is given, as parakeet-rs sets it
+pub const DEFAULT_INTRA_THREADS: usize = 4;
+pub const DEFAULT_INTER_THREADS: usize = 1;
+
+/// Execution providers compiled in, by the names settings use for them.
+pub fn providers() -> Vec<(&'static str, ExecutionProvider)> {
+    #[allow(unused_mut)]
+    let mut providers = vec![("cpu", ExecutionProvider::Cpu)];
+    #[cfg(feature = "coreml")]
+    providers.push(("coreml", ExecutionProvider::CoreML));
+    #[cfg(feature = "directml")]
+    providers.push(("directml", ExecutionProvider::DirectML));
+    #[cfg(feature = "migraphx")]
+    providers.push(("migraphx", ExecutionProvider::MIGraphX));
+    providers
+}
+
+pub fn parse_provider(s: &str) -> Result<ExecutionProvider, String> {
+    let providers = providers();
+    let name = s.to_ascii_lowercase();
+    match providers.iter().find(|(n, _)| *n == name) {
+        Some(&(_, provider)) => Ok(provider),
+        None => Err(format!(
+            "Execution provider {} isn't compiled in (available: {})",
+            s,
+            providers
+                .iter()
+                .map(|(n, _)| *n)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+pub fn provider_name(provider: ExecutionProvider) -> &'static str {
+    providers()
+        .into_iter()
+        .find(|&(_, p)| p == provider)
+        .map_or("cpu", |(name, _)| name)
+}
+
+/// Whether the ONNX Runtime library offers `provider`. Providers it doesn't
+/// offer are skipped when the model loads, leaving the CPU.
+pub fn is_available(provider: ExecutionProvider) -> bool {
+    #[allow(unused_imports)]
+    use ort::ep::ExecutionProvider as Ep;
+    let available: ort::Result<bool> = match provider {
+        ExecutionProvider::Cpu => Ok(true),
+        #[cfg(feature = "coreml")]
+        ExecutionProvider::CoreML => Ep::is_available(&ort::ep::CoreML::default()),
+        #[cfg(feature = "directml")]
+        ExecutionProvider::DirectML => Ep::is_available(&ort::ep::DirectML::default()),
+        #[cfg(feature = "migraphx")]
+        ExecutionProvider::MIGraphX => Ep::is_available(&ort::ep::MIGraphX::default()),
+        // Providers parakeet-rs was built with that rustscriber has no
+        // feature for
+        #[allow(unreachable_patterns)]
+        _ => Ok(false),
+    };
+    available.unwrap_or(false)
+}
+
+/// How much ONNX Runtime rewrites the model graph before running it. Higher
+/// levels load more slowly and usually run faster.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OptimizationLevel {
+    Disable,
+    /// Removes redundant nodes and folds constants
+    Basic,
+    /// Also fuses nodes into larger operators
+    Extended,
+    /// Also changes data layouts; what parakeet-rs has always used
+    Layout,
+    All,
+}
+
+const OPTIMIZATION_LEVELS: [(&str, OptimizationLevel); 5] = [
+    ("disable", OptimizationLevel::Disable),
+    ("basic", OptimizationLevel::Basic),
+    ("extended", OptimizationLevel::Extended),
+    ("layout", OptimizationLevel::Layout),
+    ("all", OptimizationLevel::All),
+];
+
+pub fn parse_optimization(s: &str) -> Result<OptimizationLevel, String> {
+    let name = s.to_ascii_lowercase();
+    match OPTIMIZATION_LEVELS.iter().find(|(n, _)| *n == name) {
+        Some(&(_, level)) => Ok(level),
+        None => Err(format!(
+            "Unknown graph optimization level {} (expected one of: {})",
+            s,
+            OPTIMIZATION_LEVELS
+                .iter()
+                .map(|(n, _)| *n)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+impl OptimizationLevel {
+    pub fn name(self) -> &'static str {
+        OPTIMIZATION_LEVELS
+            .iter()
+            .find(|&&(_, level)| level == self)
+            .map_or("all", |(name, _)| name)
+    }
+
+    fn ort(self) -> GraphOptimizationLevel {
+        match self {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Basic => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Extended => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Layout => GraphOptimizationLevel::Level3,
+            OptimizationLevel::All => GraphOptimizationLevel::All,
+        }
+    }
+}
+
+/// How the model's ONNX Runtime sessions are set up.
+#[derive(Debug, Clone, Copy)]
+pub struct Runtime {
+    pub provider: ExecutionProvider,
+    /// Threads used within one operator; 0 lets ONNX Runtime choose
+    pub intra_threads: usize,
+    /// Threads used to run independent operators in parallel; 0 lets ONNX
+    /// Runtime choose
+    pub inter_threads: usize,
+    pub optimization: OptimizationLevel,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self {
+            provider: ExecutionProvider::Cpu,
+            intra_threads: DEFAULT_INTRA_THREADS,
+            inter_threads: DEFAULT_INTER_THREADS,
+            optimization: OptimizationLevel::Layout,
+        }
+    }
+}
+
+impl Runtime {
+    /// Load the ONNX model at `path` into a session. Returns the session and
+    /// the provider it runs on: the one asked for if it registered, otherwise
+    /// the CPU, which ONNX Runtime always has.
+    pub fn session(&self, path: &Path) -> ort::Result<(Session, &'static str)> {
+        let mut builder = Session::builder()?
+            .with_optimization_level(self.optimization.ort())?
+            .with_intra_threads(self.intra_threads)?
+            .with_inter_threads(self.inter_threads)?;
+        let provider = self.running_on(self.register(&mut builder));
+        Ok((builder.commit_from_file(path)?, provider))
+    }
+
+    /// The provider sessions run on, given how registering ours went.
+    fn running_on(&self, registered: Result<(), ort::ep::RegisterError>) -> &'static str {
+        match registered {
+            Ok(()) => provider_name(self.provider),
+            Err(_) => "cpu",
+        }
+    }
+
+    /// Register the provider asked for on `builder`. Nothing needs
+    /// registering for the CPU.
+    #[allow(unused_variables)]
+    fn register(&self, builder: &mut SessionBuilder) -> Result<(), ort::ep::RegisterError> {
+        #[allow(unused_imports)]
+        use ort::ep::ExecutionProvider as Ep;
+        match self.provider {
+            ExecutionProvider::Cpu => Ok(()),
+            #[cfg(feature = "coreml")]
+            ExecutionProvider::CoreML => {
+                use ort::ep::coreml::{ComputeUnits, CoreML};
+                // As parakeet-rs sets it up
+                let coreml = CoreML::default().with_compute_units(ComputeUnits::CPUAndGPU);
+                Ep::register(&coreml, builder)
+            }
+            #[cfg(feature = "directml")]
+            ExecutionProvider::DirectML => Ep::register(&ort::ep::DirectML::default(), builder),
+            #[cfg(feature = "migraphx")]
+            ExecutionProvider::MIGraphX => Ep::register(&ort::ep::MIGraphX::default(), builder),
+            #[allow(unreachable_patterns)]
+            _ => Err(ort::ep::RegisterError::MissingFeature),
+        }
+    }
+
+    /// Why the provider asked for isn't used, given the one the model
+    /// actually runs on.
+    pub fn fallback_warning(&self, provider: &str) -> Option<String> {
+        (provider != provider_name(self.provider)).then(|| {
+            format!(
+                "Execution provider {} couldn't be registered with ONNX Runtime; running on the {}",
+                provider_name(self.provider),
+                provider
+            )
+        })
+    }
+}
+
+/// Thread counts as settings give them, with 0 as "default".
+pub fn threads(n: usize) -> String {
+    match n {
+        0 => "default".to_string(),
+        n => n.to_string(),
+    }
+}
+
+/// The settings asked for; what the model ends up on is the `Engine`'s
+/// `provider()`.
+impl fmt::Display for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} execution provider, {} intra-op / {} inter-op threads, {} graph optimization",
+            provider_name(self.provider),
+            threads(self.intra_threads),
+            threads(self.inter_threads),
+            self.optimization.name()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_compiled_in_providers() {
+        assert_eq!(parse_provider("CPU"), Ok(ExecutionProvider::Cpu));
+        let error = parse_provider("tpu").unwrap_err();
+        assert!(error.contains("tpu isn't compiled in"));
+        assert!(error.contains("available: cpu"));
+        #[cfg(not(feature = "coreml"))]
+        assert!(parse_provider("coreml").is_err());
+        assert_eq!(provider_name(ExecutionProvider::Cpu), "cpu");
+        assert!(is_available(ExecutionProvider::Cpu));
+    }
+
+    #[test]
+    fn parses_optimization_levels() {
+        for (name, level) in OPTIMIZATION_LEVELS {
+            assert_eq!(parse_optimization(&name.to_uppercase()), Ok(level));
+            assert_eq!(level.name(), name);
+        }
+        assert!(
+            parse_optimization("max")
+                .unwrap_err()
+                .contains("disable, basic")
+        );
+    }
+
+    #[test]
+    fn defaults_to_the_layout_level() {
+        let runtime = Runtime::default();
+        assert_eq!(runtime.optimization, OptimizationLevel::Layout);
+        assert!(matches!(
+            runtime.optimization.ort(),
+            GraphOptimizationLevel::Level3
+        ));
+        assert_eq!(
+            runtime.to_string(),
+            "cpu execution provider, 4 intra-op / 1 inter-op threads, layout graph optimization"
+        );
+        assert_eq!(threads(0), "default");
+    }
+
+    #[test]
+    fn falls_back_to_the_cpu() {
+        let runtime = Runtime::default();
+        assert_eq!(runtime.running_on(Ok(())), "cpu");
+        assert_eq!(
+            runtime.running_on(Err(ort::ep::RegisterError::MissingFeature)),
+            "cpu"
+        );
+        assert_eq!(runtime.fallback_warning("cpu"), None);
+
+        #[cfg(feature = "coreml")]
+        {
+            let runtime = Runtime {
+                provider: ExecutionProvider::CoreML,
+                ..Runtime::default()
+            };
+            assert_eq!(runtime.running_on(Ok(())), "coreml");
+            let provider = runtime.running_on(Err(ort::ep::RegisterError::MissingFeature));
+            assert_eq!(provider, "cpu");
+            assert!(runtime.fallback_warning(provider).is_some());
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::downmix::Downmixer;
+use crate::engine::{self, Engine, Token, Word};
+use crate::error::RustscriberError;
+use crate::execution::Runtime;
+use crate::merge;
+use crate::pipeline::{CaptureBuffer, ChunkTimings, Controls, Pipeline};
+use crate::transcriber::{self, Subscribers, TranscriberOptions, TranscriptEvent};
+use clap::ValueEnum;
+use hound::WavReader;
+use serde::Serialize;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum TranscriptFormat {
+    /// Plain text, one line per speaker turn with split channels
+    #[default]
+    Text,
+    /// The transcript events with their timings
+    Json,
+}
+
+pub fn parse_format(s: &str) -> Result<TranscriptFormat, String> {
+    TranscriptFormat::from_str(s, true)
+        .map_err(|_| format!("Unknown transcript format: {} (expected text or json)", s))
+}
+
+impl TranscriptFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            TranscriptFormat::Text => "txt",
+            TranscriptFormat::Json => "json",
+        }
+    }
+}
+
+/// A decoded audio file, as interleaved f32 samples.
+pub struct AudioFile {
+    pub name: String,
+    pub samples: Vec<f32>,
+    pub channels: u16,
+    pub sample_rate: u32,
+}
+
+impl AudioFile {
+    /// Read a WAV file, or WAV data from stdin when `path` is `-`.
+    pub fn read(path: &Path) -> Result<Self, RustscriberError> {
+        if path == Path::new("-") {
+            return Self::decode(WavReader::new(io::stdin().lock()), "stdin");
+        }
+        Self::decode(WavReader::open(path), &path.display().to_string())
+    }
+
+    fn decode<R: Read>(
+        reader: Result<WavReader<R>, hound::Error>,
+        name: &str,
+    ) -> Result<Self, RustscriberError> {
+        let error = |e| RustscriberError::wav(format!("Failed to read {}", name), e);
+        let reader = reader.map_err(error)?;
+        let spec = reader.spec();
+        let samples: Result<Vec<f32>, _> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.into_samples::<f32>().collect(),
+            hound::SampleFormat::Int => {
+                // Same full-scale mapping as live capture
+                let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .into_samples::<i32>()
+                    .map(|sample| sample.map(|s| s as f32 * scale))
+                    .collect()
+            }
+        };
+        let samples = samples.map_err(error)?;
+        if spec.channels == 0 || spec.sample_rate == 0 {
+            return Err(RustscriberError::Format(format!(
+                "{} has no audio channels or no sample rate",
+                name
+            )));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            samples,
+            channels: spec.channels,
+            sample_rate: spec.sample_rate,
+        })
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        let frames = self.samples.len() / self.channels.max(1) as usize;
+        frames as f64 / self.sample_rate as f64
+    }
+}
+
+/// Transcribes whole files through the same stages as live capture, as fast
+/// as the model runs. Models are kept between files, so a batch only loads
+/// them once.
+pub struct FileTranscriber {
+    model_path: PathBuf,
+    options: TranscriberOptions,
+    /// Models left over from earlier files
+    models: Vec<Engine>,
+    timings: Option<ChunkTimings>,
+    /// The execution provider the models registered with
+    provider: &'static str,
+}
+
+impl FileTranscriber {
+    pub fn new(model_path: &Path, options: TranscriberOptions) -> Self {
+        Self {
+            model_path: model_path.to_path_buf(),
+            options,
+            models: Vec::new(),
+            timings: None,
+            provider: "cpu",
+        }
+    }
+
+    /// Record how long each chunk of later files takes in `timings`.
+    pub fn timed(&mut self, timings: ChunkTimings) {
+        self.timings = Some(timings);
+    }
+
+    /// Make sure there is a model for each of `count` pipelines.
+    pub fn load_models(&mut self, count: usize) -> Result<(), RustscriberError> {
+        if self.models.len() >= count {
+            return Ok(());
+        }
+        while self.models.len() < count {
+            eprintln!(
+                "Loading Nemotron model from {}...",
+                self.model_path.display()
+            );
+            let model = Engine::load(&self.model_path, &self.options.runtime)?;
+            self.provider = model.provider();
+            self.models.push(model);
+        }
+        eprintln!("Running with the {}", self.options.runtime);
+        if let Some(warning) = self.options.runtime.fallback_warning(self.provider) {
+            eprintln!("{}", warning);
+        }
+        Ok(())
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.options.runtime
+    }
+
+    /// The execution provider the models run on, once loaded.
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    /// The number of pipelines (and so models) `audio` needs.
+    pub fn pipelines(&self, audio: &AudioFile) -> usize {
+        transcriber::pipeline_labels(audio.channels as usize, &self.options, None).len()
+    }
+
+    /// Transcribe `audio`, returning its events in time order. With split
+    /// channels, each channel gets its own pipeline and model.
+    pub fn transcribe(
+        &mut self,
+        audio: &AudioFile,
+    ) -> Result<Vec<TranscriptEvent>, RustscriberError> {
+        let input_rate = audio.sample_rate as usize;
+        let channels = audio.channels as usize;
+        let labels = transcriber::pipeline_labels(channels, &self.options, None);
+        let sources: Vec<Vec<f32>> = if self.options.split_channels {
+            (0..channels)
+                .map(|c| {
+                    audio
+                        .samples
+                        .iter()
+                        .skip(c)
+                        .step_by(channels)
+                        .copied()
+                        .collect()
+                })
+                .collect()
+        } else {
+            let mut downmixer = Downmixer::new(
+                self.options.channel_mix.clone(),
+                channels,
+                audio.sample_rate,
+            )
+            .map_err(RustscriberError::Settings)?;
+            vec![downmixer.process(&audio.samples)]
+        };
+
+        self.load_models(labels.len())?;
+
+        let (events_tx, events_rx) = mpsc::channel();
+        let (transcript_tx, transcript_rx) = mpsc::channel();
+        let subscribers: Subscribers = Arc::new(Mutex::new(vec![transcript_tx]));
+        let merger = merge::spawn_merger(labels.len(), events_rx, subscribers, false);
+
+        let mut workers = Vec::new();
+        for (source, (label, samples)) in labels.into_iter().zip(sources).enumerate() {
+            let mut model = self.models.pop().expect("a model per pipeline");
+            model.reset();
+            let stages = transcriber::build_stages(&self.options, label.as_deref(), input_rate)?;
+            let pipeline = Pipeline::new(
+                source,
+                label,
+                model,
+                CaptureBuffer::shared(input_rate),
+                stages,
+                Controls::default(),
+                events_tx.clone(),
+            )
+            .timed(self.timings.clone());
+            workers.push(thread::spawn(move || {
+                pipeline.transcribe_all(samples, input_rate)
+            }));
+        }
+        drop(events_tx);
+
+        let mut result = Ok(());
+        for worker in workers {
+            match worker.join() {
+                Ok((model, outcome)) => {
+                    self.models.push(model);
+                    result = result.and(outcome);
+                }
+                Err(_) => {
+                    result = result.and(Err(RustscriberError::Failed(
+                        "Transcription thread panicked".to_string(),
+                    )));
+                }
+            }
+        }
+        let _ = merger.join();
+        result.map(|()| transcript_rx.try_iter().collect())
+    }
+}
+
+#[derive(Serialize)]
+struct JsonTranscript<'a> {
+    file: &'a str,
+    duration: f64,
+    words: Vec<Word>,
+    events: &'a [TranscriptEvent],
+}
+
+/// Format a file's transcript for output.
+pub fn render(audio: &AudioFile, events: &[TranscriptEvent], format: TranscriptFormat) -> String {
+    match format {
+        TranscriptFormat::Text => render_text(events),
+        TranscriptFormat::Json => {
+            let transcript = JsonTranscript {
+                file: &audio.name,
+                duration: audio.duration_secs(),
+                words: words(events),
+                events,
+            };
+            let mut json = serde_json::to_string_pretty(&transcript).unwrap_or_default();
+            json.push('\n');
+            json
+        }
+    }
+}
+
+/// Every source's tokens joined into words, in time order.
+fn words(events: &[TranscriptEvent]) -> Vec<Word> {
+    let mut sources: Vec<(Option<&str>, Vec<Token>)> = Vec::new();
+    for event in events {
+        let TranscriptEvent::Text { tokens, source, .. } = event else {
+            continue;
+        };
+        let source = source.as_deref();
+        match sources.iter_mut().find(|(s, _)| *s == source) {
+            Some((_, all)) => all.extend_from_slice(tokens),
+            None => sources.push((source, tokens.clone())),
+        }
+    }
+    let mut words: Vec<Word> = sources
+        .iter()
+        .flat_map(|(source, tokens)| engine::words(tokens, *source))
+        .collect();
+    words.sort_by(|a, b| a.start.total_cmp(&b.start));
+    words
+}
+
+/// The transcript's text, with a `[label]` line per speaker turn when the
+/// events carry sources.
+fn render_text(events: &[TranscriptEvent]) -> String {
+    let mut lines: Vec<(Option<&str>, String)> = Vec::new();
+    for event in events {
+        let TranscriptEvent::Text { text, source, .. } = event else {
+            continue;
+        };
+        let source = source.as_deref();
+        match lines.last_mut() {
+            Some((last, line)) if *last == source => line.push_str(text),
+            _ => lines.push((source, text.clone())),
+        }
+    }
+
+    let mut out = String::new();
+    for (source, line) in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(source) = source {
+            out.push_str(&format!("[{}] ", source));
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str, source: Option<&str>) -> TranscriptEvent {
+        TranscriptEvent::Text {
+            text: text.to_string(),
+            start: 0.0,
+            end: 0.0,
+            tokens: Vec::new(),
+            source: source.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn text_joins_chunks_into_one_line() {
+        let events = [text(" hello", None), text(" world", None)];
+        assert_eq!(render_text(&events), "hello world\n");
+    }
+
+    #[test]
+    fn text_starts_a_line_per_speaker_turn() {
+        let events = [
+            text(" so what", Some("Host")),
+            text(" got you", Some("Host")),
+            text(" honestly", Some("Guest")),
+            text(" right", Some("Host")),
+        ];
+        assert_eq!(
+            render_text(&events),
+            "[Host] so what got you\n[Guest] honestly\n[Host] right\n"
+        );
+    }
+}
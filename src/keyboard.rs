@@ -0,0 +1,107 @@
+use crate::session::{ControlRequest, SessionCommand};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{execute, terminal};
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Single-key shortcuts for a live session. The terminal stays in raw mode
+/// until this is dropped.
+pub struct KeyboardControl {
+    stop_flag: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    push_to_talk: bool,
+    /// Whether the terminal reports key releases, enabling hold-to-talk
+    key_releases: bool,
+}
+
+impl KeyboardControl {
+    pub fn start(requests: Sender<ControlRequest>, push_to_talk: bool) -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+
+        // Most terminals only report presses. Where releases are available
+        // (kitty keyboard protocol), Space can be held to talk.
+        let key_releases =
+            push_to_talk && terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_releases {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let stop_flag_thread = Arc::clone(&stop_flag);
+
+        let thread = thread::spawn(move || {
+            while !stop_flag_thread.load(Ordering::Relaxed) {
+                // Poll so the thread notices shutdown without waiting for a key
+                match event::poll(Duration::from_millis(100)) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(_) => break,
+                }
+                let Ok(Event::Key(key)) = event::read() else {
+                    continue;
+                };
+
+                let command = match (key.code, key.kind) {
+                    (KeyCode::Char(' '), KeyEventKind::Press) if key_releases => {
+                        SessionCommand::Talk(true)
+                    }
+                    (KeyCode::Char(' '), KeyEventKind::Release) if key_releases => {
+                        SessionCommand::Talk(false)
+                    }
+                    (_, KeyEventKind::Release | KeyEventKind::Repeat) => continue,
+                    (KeyCode::Char(' '), _) if push_to_talk => SessionCommand::ToggleTalk,
+                    (KeyCode::Char('p') | KeyCode::Char(' '), _) => SessionCommand::TogglePause,
+                    // Raw mode swallows SIGINT, so handle Ctrl-C here
+                    (KeyCode::Char('c'), _) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        SessionCommand::Stop
+                    }
+                    (KeyCode::Char('q') | KeyCode::Enter | KeyCode::Esc, _) => SessionCommand::Stop,
+                    _ => continue,
+                };
+
+                let (request, _) = ControlRequest::new(command);
+                if requests.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            stop_flag,
+            thread: Some(thread),
+            push_to_talk,
+            key_releases,
+        })
+    }
+
+    pub fn help(&self) -> &'static str {
+        match (self.push_to_talk, self.key_releases) {
+            (true, true) => "hold Space: talk, p: pause/resume, q/Enter: stop",
+            (true, false) => "Space: start/stop talking, p: pause/resume, q/Enter: stop",
+            (false, _) => "p/Space: pause/resume, q/Enter: stop",
+        }
+    }
+}
+
+impl Drop for KeyboardControl {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+        if self.key_releases {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -0,0 +1,414 @@
+use crossterm::{queue, terminal};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Levels below this are shown as silence
+const FLOOR_DB: f32 = -100.0;
+/// How much audio each published reading covers, in seconds
+const WINDOW_SECS: f32 = 0.05;
+/// RMS below this counts as no signal
+const SILENCE_DB: f32 = -60.0;
+/// How long the input has to stay silent before it is flagged
+const SILENCE_SECS: f32 = 3.0;
+/// Samples at or beyond this magnitude count as clipped
+const CLIP_LEVEL: f32 = 0.999;
+/// Clipped samples in one window that count as clipping rather than a
+/// single full-scale peak
+const CLIP_SAMPLES: usize = 2;
+/// How long the clipping flag stays up after the last clipped window
+const CLIP_HOLD_SECS: f32 = 2.0;
+/// Mean level beyond which the input is flagged for DC offset
+const DC_LIMIT: f32 = 0.02;
+/// Time constant of the DC estimate, in seconds
+const DC_SECS: f32 = 1.0;
+/// How fast the held peak falls back, in dB per second
+const PEAK_FALL_DB_PER_SEC: f32 = 20.0;
+
+/// The latest levels of one input device, across all of its channels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputLevels {
+    pub device: String,
+    pub rms_dbfs: f32,
+    pub peak_dbfs: f32,
+    /// Recent peak, falling back slowly, for the meter's peak marker
+    pub peak_hold_dbfs: f32,
+    /// Average sample value; should sit close to zero
+    pub dc_offset: f32,
+    /// How long the input has been below the silence threshold
+    pub silent_secs: f32,
+    pub clipping: bool,
+}
+
+impl InputLevels {
+    fn new(device: String) -> Self {
+        Self {
+            device,
+            rms_dbfs: FLOOR_DB,
+            peak_dbfs: FLOOR_DB,
+            peak_hold_dbfs: FLOOR_DB,
+            dc_offset: 0.0,
+            silent_secs: 0.0,
+            clipping: false,
+        }
+    }
+
+    /// Problems with the input worth telling the user about.
+    pub fn warnings(&self) -> Vec<LevelWarning> {
+        let mut warnings = Vec::new();
+        if self.silent_secs >= SILENCE_SECS {
+            warnings.push(LevelWarning::Silence);
+        }
+        if self.clipping {
+            warnings.push(LevelWarning::Clipping);
+        }
+        if self.dc_offset.abs() > DC_LIMIT {
+            warnings.push(LevelWarning::DcOffset);
+        }
+        warnings
+    }
+}
+
+pub type SharedLevels = Arc<Mutex<InputLevels>>;
+
+pub fn shared_levels(device: String) -> SharedLevels {
+    Arc::new(Mutex::new(InputLevels::new(device)))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LevelWarning {
+    Silence,
+    Clipping,
+    DcOffset,
+}
+
+impl fmt::Display for LevelWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelWarning::Silence => write!(f, "no signal, is the microphone muted?"),
+            LevelWarning::Clipping => write!(f, "input is clipping, lower the input gain"),
+            LevelWarning::DcOffset => {
+                write!(f, "large DC offset, check the microphone or interface")
+            }
+        }
+    }
+}
+
+/// Measures interleaved input in the capture callback and publishes a
+/// reading to its `SharedLevels` every `WINDOW_SECS`.
+pub struct LevelMeter {
+    shared: SharedLevels,
+    window_len: usize,
+    window_secs: f32,
+    count: usize,
+    sum: f32,
+    sum_squares: f32,
+    peak: f32,
+    clipped: usize,
+    dc: f32,
+    dc_coefficient: f32,
+    peak_hold_dbfs: f32,
+    silent_secs: f32,
+    since_clip_secs: f32,
+}
+
+impl LevelMeter {
+    /// Start measuring a stream, resetting `shared` to silence.
+    pub fn new(shared: SharedLevels, sample_rate: u32, channels: u16) -> Self {
+        let frames = ((sample_rate as f32 * WINDOW_SECS) as usize).max(1);
+        let window_secs = frames as f32 / sample_rate as f32;
+        if let Ok(mut levels) = shared.lock() {
+            let device = std::mem::take(&mut levels.device);
+            *levels = InputLevels::new(device);
+        }
+        Self {
+            shared,
+            window_len: frames * channels.max(1) as usize,
+            window_secs,
+            count: 0,
+            sum: 0.0,
+            sum_squares: 0.0,
+            peak: 0.0,
+            clipped: 0,
+            dc: 0.0,
+            dc_coefficient: (-window_secs / DC_SECS).exp(),
+            peak_hold_dbfs: FLOOR_DB,
+            silent_secs: 0.0,
+            since_clip_secs: CLIP_HOLD_SECS,
+        }
+    }
+
+    pub fn process(&mut self, data: &[f32]) {
+        for &sample in data {
+            self.count += 1;
+            self.sum += sample;
+            self.sum_squares += sample * sample;
+            self.peak = self.peak.max(sample.abs());
+            if sample.abs() >= CLIP_LEVEL {
+                self.clipped += 1;
+            }
+            if self.count == self.window_len {
+                self.publish();
+            }
+        }
+    }
+
+    fn publish(&mut self) {
+        let n = self.count as f32;
+        let rms_dbfs = to_db((self.sum_squares / n).sqrt());
+        let peak_dbfs = to_db(self.peak);
+
+        self.dc = self.dc_coefficient * self.dc + (1.0 - self.dc_coefficient) * self.sum / n;
+        self.peak_hold_dbfs = (self.peak_hold_dbfs - PEAK_FALL_DB_PER_SEC * self.window_secs)
+            .max(peak_dbfs)
+            .max(FLOOR_DB);
+        if rms_dbfs < SILENCE_DB {
+            self.silent_secs += self.window_secs;
+        } else {
+            self.silent_secs = 0.0;
+        }
+        if self.clipped >= CLIP_SAMPLES {
+            self.since_clip_secs = 0.0;
+        } else {
+            self.since_clip_secs += self.window_secs;
+        }
+
+        // Update in place: the device name stays put, so nothing is
+        // allocated on the audio thread.
+        if let Ok(mut levels) = self.shared.lock() {
+            levels.rms_dbfs = rms_dbfs;
+            levels.peak_dbfs = peak_dbfs;
+            levels.peak_hold_dbfs = self.peak_hold_dbfs;
+            levels.dc_offset = self.dc;
+            levels.silent_secs = self.silent_secs;
+            levels.clipping = self.since_clip_secs < CLIP_HOLD_SECS;
+        }
+
+        self.count = 0;
+        self.sum = 0.0;
+        self.sum_squares = 0.0;
+        self.peak = 0.0;
+        self.clipped = 0;
+    }
+}
+
+fn to_db(linear: f32) -> f32 {
+    if linear > 0.0 {
+        (20.0 * linear.log10()).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+/// Watches the inputs' levels from a background thread, printing a warning
+/// to stderr whenever one appears and, optionally, a live meter bar.
+pub struct LevelMonitor {
+    stop_flag: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Range covered by the meter bar
+const BAR_MIN_DB: f32 = -60.0;
+const BAR_WIDTH: usize = 30;
+
+impl LevelMonitor {
+    pub fn start(levels: Vec<SharedLevels>, show_meter: bool) -> Self {
+        let stop_flag = Arc::new(AtomicBool::new(false));
+        let stop_flag_thread = Arc::clone(&stop_flag);
+
+        let thread = thread::spawn(move || {
+            let mut reported: Vec<Vec<LevelWarning>> = vec![Vec::new(); levels.len()];
+            let mut stderr = io::stderr();
+
+            while !stop_flag_thread.load(Ordering::Relaxed) {
+                let readings: Vec<InputLevels> = levels
+                    .iter()
+                    .filter_map(|shared| shared.lock().ok().map(|l| l.clone()))
+                    .collect();
+
+                for (reading, reported) in readings.iter().zip(&mut reported) {
+                    let warnings = reading.warnings();
+                    for warning in warnings.iter().filter(|w| !reported.contains(w)) {
+                        // Raw mode may be on, so spell out the carriage returns
+                        if show_meter {
+                            let _ =
+                                queue!(stderr, terminal::Clear(terminal::ClearType::CurrentLine));
+                        }
+                        let _ = write!(stderr, "\rWarning ({}): {}\r\n", reading.device, warning);
+                    }
+                    *reported = warnings;
+                }
+
+                if show_meter {
+                    let bars: Vec<String> = readings
+                        .iter()
+                        .map(|reading| meter_bar(reading, readings.len() > 1))
+                        .collect();
+                    let _ = queue!(stderr, terminal::Clear(terminal::ClearType::CurrentLine));
+                    let _ = write!(stderr, "\r{}", bars.join("  "));
+                }
+                let _ = stderr.flush();
+                thread::sleep(Duration::from_millis(100));
+            }
+
+            if show_meter {
+                let _ = queue!(stderr, terminal::Clear(terminal::ClearType::CurrentLine));
+                let _ = write!(stderr, "\r");
+                let _ = stderr.flush();
+            }
+        });
+
+        Self {
+            stop_flag,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for LevelMonitor {
+    fn drop(&mut self) {
+        self.stop_flag.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.thread.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// One input's meter, e.g. `[#########     |        ] -32 dBFS`: RMS as the
+/// filled part and the held peak as a marker.
+fn meter_bar(levels: &InputLevels, labelled: bool) -> String {
+    let cell = |db: f32| {
+        let fraction = ((db - BAR_MIN_DB) / -BAR_MIN_DB).clamp(0.0, 1.0);
+        (fraction * BAR_WIDTH as f32).round() as usize
+    };
+    let filled = cell(levels.rms_dbfs);
+    let peak = cell(levels.peak_hold_dbfs).min(BAR_WIDTH - 1);
+
+    let bar: String = (0..BAR_WIDTH)
+        .map(|i| match i {
+            _ if i < filled => '#',
+            _ if i == peak && levels.peak_hold_dbfs > BAR_MIN_DB => '|',
+            _ => ' ',
+        })
+        .collect();
+    let clip = if levels.clipping { " CLIP" } else { "" };
+    let label = if labelled {
+        format!("{} ", levels.device)
+    } else {
+        String::new()
+    };
+    format!("{}[{}] {:>4.0} dBFS{}", label, bar, levels.rms_dbfs, clip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1 kHz mono, so each 50-sample window is 0.05 s
+    const RATE: u32 = 1000;
+
+    fn meter() -> (LevelMeter, SharedLevels) {
+        let shared = shared_levels("mic".to_string());
+        (LevelMeter::new(Arc::clone(&shared), RATE, 1), shared)
+    }
+
+    fn feed(meter: &mut LevelMeter, sample: f32, secs: f32) {
+        let len = (secs * RATE as f32).round() as usize;
+        // Alternate the sign so only `dc` input has an offset
+        let data: Vec<f32> = (0..len)
+            .map(|i| if i % 2 == 0 { sample } else { -sample })
+            .collect();
+        meter.process(&data);
+    }
+
+    fn warnings(shared: &SharedLevels) -> Vec<LevelWarning> {
+        shared.lock().unwrap().warnings()
+    }
+
+    #[test]
+    fn flags_silence_after_a_while() {
+        let (mut meter, shared) = meter();
+        feed(&mut meter, 0.0, 2.9);
+        assert!(warnings(&shared).is_empty());
+        feed(&mut meter, 0.0, 0.2);
+        assert_eq!(warnings(&shared), [LevelWarning::Silence]);
+
+        feed(&mut meter, 0.1, 0.05);
+        assert!(warnings(&shared).is_empty());
+        assert_eq!(shared.lock().unwrap().silent_secs, 0.0);
+    }
+
+    #[test]
+    fn holds_clipping_after_the_last_clipped_window() {
+        let (mut meter, shared) = meter();
+        // A single full-scale sample is a peak, not clipping
+        let mut data = vec![0.1; 50];
+        data[10] = 1.0;
+        meter.process(&data);
+        assert!(warnings(&shared).is_empty());
+
+        data[20] = -1.0;
+        meter.process(&data);
+        assert_eq!(warnings(&shared), [LevelWarning::Clipping]);
+        feed(&mut meter, 0.1, 1.9);
+        assert_eq!(warnings(&shared), [LevelWarning::Clipping]);
+        feed(&mut meter, 0.1, 0.2);
+        assert!(warnings(&shared).is_empty());
+    }
+
+    #[test]
+    fn flags_a_dc_offset() {
+        let (mut meter, shared) = meter();
+        feed(&mut meter, 0.1, 2.0);
+        assert!(warnings(&shared).is_empty());
+
+        let offset = vec![0.1; 5 * RATE as usize];
+        meter.process(&offset);
+        assert_eq!(warnings(&shared), [LevelWarning::DcOffset]);
+        assert!((shared.lock().unwrap().dc_offset - 0.1).abs() < 0.01);
+    }
+
+    #[test]
+    fn peak_hold_falls_back_slowly() {
+        let (mut meter, shared) = meter();
+        feed(&mut meter, 0.5, 0.05);
+        let peak = to_db(0.5);
+        assert!((shared.lock().unwrap().peak_hold_dbfs - peak).abs() < 0.01);
+
+        feed(&mut meter, 0.01, 1.0);
+        let levels = shared.lock().unwrap();
+        assert!((levels.peak_hold_dbfs - (peak - PEAK_FALL_DB_PER_SEC)).abs() < 0.01);
+        assert!((levels.peak_dbfs - -40.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn renders_the_bar() {
+        let mut levels = InputLevels::new("mic".to_string());
+        levels.rms_dbfs = -30.0;
+        levels.peak_hold_dbfs = -6.0;
+        assert_eq!(
+            meter_bar(&levels, false),
+            format!("[{}{}|{}]  -30 dBFS", "#".repeat(15), " ".repeat(12), "  ")
+        );
+
+        levels.clipping = true;
+        levels.rms_dbfs = 0.0;
+        levels.peak_hold_dbfs = 0.0;
+        assert_eq!(
+            meter_bar(&levels, true),
+            format!("mic [{}]    0 dBFS CLIP", "#".repeat(30))
+        );
+
+        // Silence leaves the bar and its peak marker empty
+        let silent = InputLevels::new("mic".to_string());
+        assert_eq!(
+            meter_bar(&silent, false),
+            format!("[{}] -100 dBFS", " ".repeat(30))
+        );
+    }
+}
@@ -1,95 +1,1122 @@
 mod audio_config;
+mod bench;
+mod config;
+#[cfg(unix)]
+mod control;
+mod denoise;
 mod device_enumerator;
+mod device_selector;
+mod downmix;
+mod dsp;
+mod engine;
+mod error;
+mod eval;
+mod execution;
+mod file_transcriber;
+mod keyboard;
+mod level_meter;
+mod merge;
+mod models;
+mod pipeline;
+mod push_to_talk;
+mod reconnect;
+mod sample_convert;
+mod session;
 mod transcriber;
+mod tui;
 mod wav_recorder;
 
-use clap::Parser;
-use cpal::traits::{DeviceTrait, HostTrait};
-use std::io::{self, Write};
-use std::path::Path;
-use transcriber::Transcriber;
+use clap::{Parser, Subcommand};
+use cpal::traits::DeviceTrait;
+use error::RustscriberError;
+use file_transcriber::{AudioFile, FileTranscriber, TranscriptFormat};
+use level_meter::LevelMonitor;
+use session::{ControlRequest, Session, SessionCommand};
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use transcriber::{Input, Transcriber, TranscriberOptions};
 use wav_recorder::WavRecorder;
 
-const MODEL_PATH: &str = "/Users/edmistond/Downloads/prs-nemotron";
-
 #[derive(Parser)]
 #[command(name = "rustscriber")]
 #[command(about = "Audio transcription tool", long_about = None)]
 struct Args {
-    /// List all available audio input and output devices
+    #[command(subcommand)]
+    command: Command,
+
+    /// Use a named profile from the config file
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Read settings from this file instead of
+    /// ~/.config/rustscriber/config.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all available audio hosts and their input and output devices
+    Devices {
+        /// Only list devices on this host, e.g. alsa, jack, coreaudio, wasapi
+        #[arg(long, value_name = "NAME")]
+        host: Option<String>,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: device_enumerator::OutputFormat,
+    },
+    /// Transcribe live audio from one or more input devices
+    Listen(ListenArgs),
+    /// Record an input device to a WAV file until Enter is pressed
+    Record {
+        /// WAV file to write
+        file: String,
+
+        #[command(flatten)]
+        source: SourceArgs,
+    },
+    /// Transcribe a WAV file, or WAV data from stdin with `-`
+    Transcribe {
+        file: PathBuf,
+
+        /// Write the transcript to this file instead of stdout
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Transcript format: `text` (default) or `json`
+        #[arg(long, value_name = "FORMAT", value_parser = checked(file_transcriber::parse_format))]
+        format: Option<String>,
+
+        #[command(flatten)]
+        model: ModelArgs,
+
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    /// Transcribe several WAV files, writing each transcript next to its file
+    Batch {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Write the transcripts to this directory instead
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+
+        /// Transcript format: `text` (default) or `json`
+        #[arg(long, value_name = "FORMAT", value_parser = checked(file_transcriber::parse_format))]
+        format: Option<String>,
+
+        #[command(flatten)]
+        model: ModelArgs,
+
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    /// Measure word and character error rates against reference transcripts
+    Eval {
+        /// JSONL manifest, one `{"audio_filepath": ..., "text": ...}` per line
+        manifest: PathBuf,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: eval::ReportFormat,
+
+        /// Show how each transcript lines up against its reference
+        #[arg(long)]
+        diff: bool,
+
+        /// Fail if the overall word error rate is above this percentage
+        #[arg(long, value_name = "PERCENT")]
+        max_wer: Option<f64>,
+
+        #[command(flatten)]
+        model: ModelArgs,
+
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    /// Time the transcription pipeline to see whether this machine keeps up
+    /// with live audio
+    Bench {
+        /// WAV file to run through (default: a synthetic speech-like signal)
+        file: Option<PathBuf>,
+
+        /// Length of the synthetic signal in seconds
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 60.0,
+            conflicts_with = "file"
+        )]
+        seconds: f64,
+
+        /// Sample rate of the synthetic signal
+        #[arg(
+            long,
+            value_name = "HZ",
+            default_value_t = 48000,
+            conflicts_with = "file"
+        )]
+        sample_rate: u32,
+
+        /// Channels of the synthetic signal
+        #[arg(long, value_name = "N", default_value_t = 2, conflicts_with = "file")]
+        input_channels: u16,
+
+        #[arg(long, value_enum, default_value_t)]
+        format: eval::ReportFormat,
+
+        #[command(flatten)]
+        model: ModelArgs,
+
+        #[command(flatten)]
+        processing: ProcessingArgs,
+    },
+    /// Transcribe live audio in the background, controlled only through the
+    /// control socket (see `ctl`)
+    #[cfg(unix)]
+    Serve {
+        /// Socket path (default: $XDG_RUNTIME_DIR/rustscriber.sock)
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+
+        #[command(flatten)]
+        source: SourceArgs,
+
+        #[command(flatten)]
+        model: ModelArgs,
+
+        #[command(flatten)]
+        processing: ProcessingArgs,
+
+        #[command(flatten)]
+        session: SessionArgs,
+    },
+    /// Check that the model files are in place
+    Models {
+        #[command(flatten)]
+        model: ModelArgs,
+    },
+    /// Send a command to a running session's control socket
+    #[cfg(unix)]
+    Ctl {
+        /// Socket path (default: $XDG_RUNTIME_DIR/rustscriber.sock)
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+
+        #[command(subcommand)]
+        command: control::CtlCommand,
+    },
+    /// Inspect the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the settings commands would start from: the defaults, the
+    /// config file and the --profile, before any other flags
+    Show,
+}
+
+#[derive(clap::Args)]
+struct ListenArgs {
+    #[command(flatten)]
+    source: SourceArgs,
+
+    #[command(flatten)]
+    model: ModelArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+
+    #[command(flatten)]
+    session: SessionArgs,
+
+    /// Show a live input level meter on stderr
+    #[arg(long)]
+    meter: bool,
+
+    /// Full-screen terminal interface: scrolling transcript, level meters,
+    /// status bar and shortcuts for pause, recording, device switching,
+    /// bookmarks and saving the transcript
+    #[arg(long, conflicts_with = "meter")]
+    tui: bool,
+
+    /// Accept control commands on a Unix socket while transcribing
+    /// (default path: $XDG_RUNTIME_DIR/rustscriber.sock)
+    #[cfg(unix)]
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<Option<PathBuf>>,
+}
+
+/// Which input devices to capture from, and how to open them.
+#[derive(clap::Args)]
+struct SourceArgs {
+    /// Audio host backend to use, e.g. alsa, jack, coreaudio, wasapi
+    /// (see `devices`; defaults to the platform default)
+    #[arg(long, value_name = "NAME")]
+    host: Option<String>,
+
+    /// Select input device by ID (see `devices` for available IDs).
+    /// Repeat to transcribe several devices at once.
+    #[arg(long, value_name = "DEVICE_ID", conflicts_with = "input_name")]
+    input: Vec<String>,
+
+    /// Select input device by name, as a case-insensitive substring or regex.
+    /// Repeat to give fallbacks in order of preference.
+    #[arg(long, value_name = "PATTERN")]
+    input_name: Vec<String>,
+
+    /// Use the system default input device instead of the one remembered
+    /// from the last --input/--input-name
+    #[arg(long, conflicts_with_all = ["input", "input_name"])]
+    default_device: bool,
+
+    /// Capture at this sample rate instead of the best-scoring one
+    #[arg(long, value_name = "HZ")]
+    sample_rate: Option<u32>,
+
+    /// Open the device with this many channels
+    #[arg(long, value_name = "N")]
+    device_channels: Option<u16>,
+
+    /// Capture in this sample format (e.g. f32, i16, i24)
+    #[arg(long, value_name = "FORMAT", value_parser = checked(audio_config::parse_sample_format))]
+    sample_format: Option<String>,
+
+    /// Ask the backend for this many frames per callback
+    #[arg(long, value_name = "FRAMES")]
+    buffer_size: Option<u32>,
+}
+
+#[derive(clap::Args)]
+struct ModelArgs {
+    /// Directory holding the model files
+    #[arg(long, value_name = "DIR")]
+    model: Option<PathBuf>,
+
+    /// Speech recognition engine (currently only `nemotron`)
+    #[arg(long, value_name = "NAME")]
+    engine: Option<String>,
+
+    /// ONNX Runtime execution provider: `cpu`, or one compiled in with a
+    /// Cargo feature (`coreml`, `directml`, `migraphx`)
+    #[arg(long, value_name = "NAME", value_parser = checked(execution::parse_provider))]
+    execution_provider: Option<String>,
+
+    /// Threads ONNX Runtime uses within an operator (default 4, 0 to let it
+    /// choose)
+    #[arg(long, value_name = "N")]
+    intra_threads: Option<usize>,
+
+    /// Threads ONNX Runtime uses to run operators in parallel (default 1)
+    #[arg(long, value_name = "N")]
+    inter_threads: Option<usize>,
+
+    /// How far ONNX Runtime optimizes the model graph: `disable`, `basic`,
+    /// `extended`, `layout` (the default) or `all`
+    #[arg(long, value_name = "LEVEL", value_parser = checked(execution::parse_optimization))]
+    graph_optimization: Option<String>,
+}
+
+/// How audio is reduced and conditioned before it reaches the model.
+#[derive(clap::Args)]
+struct ProcessingArgs {
+    /// Transcribe a single input channel (numbered from 1), or `auto` to
+    /// follow the channel with the most speech
+    #[arg(long, value_name = "N|auto", value_parser = checked(downmix::parse_channel), conflicts_with = "channels")]
+    channel: Option<String>,
+
+    /// Mix a subset of channels, e.g. `1,3`, optionally weighted as
+    /// `1:0.7,3:0.3` (default: average of all channels)
+    #[arg(long, value_name = "LIST", value_parser = checked(downmix::parse_channels))]
+    channels: Option<String>,
+
+    /// Transcribe each input channel separately (one model per channel) and
+    /// print a merged transcript labelled by channel
+    #[arg(long, conflicts_with_all = ["channel", "channels"])]
+    split_channels: bool,
+
+    /// Labels for --split-channels, in channel order, e.g. `Host,Guest`
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    channel_names: Vec<String>,
+
+    /// High-pass filter the input to remove rumble (cutoff in Hz, default 80)
+    #[arg(long, value_name = "HZ", num_args = 0..=1, default_missing_value = "80")]
+    high_pass: Option<f32>,
+
+    /// Mute input quieter than this level in dBFS (default -50)
+    #[arg(
+        long,
+        value_name = "DBFS",
+        num_args = 0..=1,
+        default_missing_value = "-50",
+        allow_negative_numbers = true
+    )]
+    noise_gate: Option<f32>,
+
+    /// Automatic gain control towards this RMS level in dBFS (default -20)
+    #[arg(
+        long,
+        value_name = "DBFS",
+        num_args = 0..=1,
+        default_missing_value = "-20",
+        allow_negative_numbers = true
+    )]
+    agc: Option<f32>,
+
+    /// Suppress steady background noise, attenuating it by up to this many
+    /// dB (default 20)
+    #[arg(long, value_name = "DB", num_args = 0..=1, default_missing_value = "20")]
+    denoise: Option<f32>,
+
+    /// With --denoise, also record each source before and after suppression
+    /// to STEM-raw.wav and STEM-denoised.wav for comparison (one input
+    /// file only)
+    #[arg(long, value_name = "STEM")]
+    denoise_record: Option<PathBuf>,
+}
+
+/// Pausing and push-to-talk for live sessions.
+#[derive(clap::Args)]
+struct SessionArgs {
+    /// Also stop the audio stream while paused (releases the device, but
+    /// pauses any active recording too)
+    #[arg(long)]
+    pause_stream: bool,
+
+    /// Only transcribe while push-to-talk is held (Space, `ctl ptt-press`
+    /// or --ptt-pipe), printing one line per press
     #[arg(long)]
-    enumerate: bool,
+    push_to_talk: bool,
+
+    /// Audio to keep from before each push-to-talk press, in milliseconds
+    /// (default 300)
+    #[arg(long, value_name = "MS")]
+    pre_roll: Option<u64>,
+
+    /// Read push-to-talk commands (`press`, `release`, `toggle`) from a named
+    /// pipe created with mkfifo
+    #[arg(long, value_name = "PATH")]
+    ptt_pipe: Option<PathBuf>,
 
-    /// Select input device by ID (see --enumerate for available IDs)
-    #[arg(long, value_name = "DEVICE_ID")]
-    input: Option<String>,
+    /// Capture from the system default input device and move to whichever
+    /// device becomes the default later (e.g. a headset being plugged in)
+    #[arg(long, conflicts_with_all = ["input", "input_name"])]
+    follow_default: bool,
 
-    /// Record audio to a WAV file
-    #[arg(long, value_name = "FILE")]
-    record: Option<String>,
+    /// What to do when transcription falls more than --max-backlog behind
+    /// live audio: `warn`, `drop` the oldest audio, or `skip-silence`
+    #[arg(long, value_name = "POLICY", value_parser = checked(pipeline::parse_overload))]
+    overload: Option<String>,
+
+    /// How far behind live audio transcription may fall, in seconds
+    /// (default 2)
+    #[arg(long, value_name = "SECS")]
+    max_backlog: Option<f32>,
+
+    /// Directory for recordings and transcripts saved from the terminal
+    /// interface (default: the current directory)
+    #[arg(long, value_name = "DIR")]
+    recording_dir: Option<PathBuf>,
+}
+
+/// Validate a flag's value with `parse` but keep it as text, so it can be
+/// merged with the config file before being parsed for real.
+fn checked<T, E>(parse: fn(&str) -> Result<T, E>) -> impl Fn(&str) -> Result<String, E> + Clone {
+    move |s| parse(s).map(|_| s.to_string())
+}
+
+/// Flags can only switch things on; unset means "no opinion".
+fn flag(set: bool) -> Option<bool> {
+    set.then_some(true)
 }
 
-fn main() {
-    let args = Args::parse();
+// Each group of flags fills in its part of the settings to layer over the
+// config file.
 
-    if args.enumerate {
-        device_enumerator::enumerate_devices();
-        return;
+impl SourceArgs {
+    fn apply(&self, settings: &mut config::Settings) {
+        settings.host = self.host.clone();
+        settings.input = self.input.clone();
+        settings.input_name = self.input_name.clone();
+        settings.default_device = flag(self.default_device);
+        settings.sample_rate = self.sample_rate;
+        settings.device_channels = self.device_channels;
+        settings.sample_format = self.sample_format.clone();
+        settings.buffer_size = self.buffer_size;
     }
+}
 
-    let host = cpal::default_host();
-    let device = if let Some(ref device_id) = args.input {
-        host.input_devices()
-            .expect("Failed to list input devices")
-            .find(|d| {
-                d.id()
-                    .map(|id| id.to_string() == *device_id)
-                    .unwrap_or(false)
-            })
-            .unwrap_or_else(|| panic!("No input device found with ID: {}", device_id))
-    } else {
-        host.default_input_device()
-            .expect("No default input device found")
+impl ModelArgs {
+    fn apply(&self, settings: &mut config::Settings) {
+        settings.model = self.model.clone();
+        settings.engine = self.engine.clone();
+        settings.execution_provider = self.execution_provider.clone();
+        settings.intra_threads = self.intra_threads;
+        settings.inter_threads = self.inter_threads;
+        settings.graph_optimization = self.graph_optimization.clone();
+    }
+}
+
+impl ProcessingArgs {
+    fn apply(&self, settings: &mut config::Settings) {
+        settings.channel = self.channel.clone();
+        settings.channels = self.channels.clone();
+        settings.split_channels = flag(self.split_channels);
+        settings.channel_names = self.channel_names.clone();
+        settings.high_pass = self.high_pass;
+        settings.noise_gate = self.noise_gate;
+        settings.agc = self.agc;
+        settings.denoise = self.denoise;
+        settings.denoise_record = self.denoise_record.clone();
+    }
+}
+
+impl SessionArgs {
+    fn apply(&self, settings: &mut config::Settings) {
+        settings.pause_stream = flag(self.pause_stream);
+        settings.push_to_talk = flag(self.push_to_talk);
+        settings.pre_roll = self.pre_roll;
+        settings.ptt_pipe = self.ptt_pipe.clone();
+        settings.follow_default = flag(self.follow_default);
+        settings.overload = self.overload.clone();
+        settings.max_backlog = self.max_backlog;
+        settings.recording_dir = self.recording_dir.clone();
+    }
+}
+
+impl ListenArgs {
+    fn settings(&self) -> config::Settings {
+        let mut settings = config::Settings::default();
+        self.source.apply(&mut settings);
+        self.model.apply(&mut settings);
+        self.processing.apply(&mut settings);
+        self.session.apply(&mut settings);
+        settings.meter = flag(self.meter);
+        settings.tui = flag(self.tui);
+        #[cfg(unix)]
+        {
+            settings.control_socket = self.control_socket.clone().map(|path| match path {
+                Some(path) => config::SocketSetting::Path(path),
+                None => config::SocketSetting::Enabled(true),
+            });
+        }
+        settings
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), RustscriberError> {
+    let load = |cli: config::Settings| {
+        config::load(args.config.as_deref(), args.profile.as_deref(), cli)
+            .map_err(RustscriberError::Settings)
+    };
+
+    match args.command {
+        Command::Devices { ref host, format } => {
+            let (settings, _) = load(config::Settings {
+                host: host.clone(),
+                ..Default::default()
+            })?;
+            let host = device_selector::select_host(settings.host.as_deref())?;
+            let only = settings.host.is_some().then(|| host.id());
+            device_enumerator::enumerate_devices(format, only);
+            Ok(())
+        }
+        Command::Listen(ref listen_args) => {
+            let (settings, _) = load(listen_args.settings())?;
+            listen(&settings, false)
+        }
+        Command::Record {
+            ref file,
+            ref source,
+        } => {
+            let mut cli = config::Settings::default();
+            source.apply(&mut cli);
+            let (settings, _) = load(cli)?;
+            record(&settings, file)
+        }
+        Command::Transcribe {
+            ref file,
+            ref output,
+            ref format,
+            ref model,
+            ref processing,
+        } => {
+            let mut cli = config::Settings {
+                format: format.clone(),
+                ..Default::default()
+            };
+            model.apply(&mut cli);
+            processing.apply(&mut cli);
+            let (settings, _) = load(cli)?;
+            transcribe(&settings, file, output.as_deref())
+        }
+        Command::Batch {
+            ref files,
+            ref output_dir,
+            ref format,
+            ref model,
+            ref processing,
+        } => {
+            let mut cli = config::Settings {
+                format: format.clone(),
+                output_dir: output_dir.clone(),
+                ..Default::default()
+            };
+            model.apply(&mut cli);
+            processing.apply(&mut cli);
+            let (settings, _) = load(cli)?;
+            batch(&settings, files)
+        }
+        Command::Eval {
+            ref manifest,
+            format,
+            diff,
+            max_wer,
+            ref model,
+            ref processing,
+        } => {
+            let mut cli = config::Settings::default();
+            model.apply(&mut cli);
+            processing.apply(&mut cli);
+            let (settings, _) = load(cli)?;
+            evaluate(&settings, manifest, format, diff, max_wer)
+        }
+        Command::Bench {
+            ref file,
+            seconds,
+            sample_rate,
+            input_channels,
+            format,
+            ref model,
+            ref processing,
+        } => {
+            let mut cli = config::Settings::default();
+            model.apply(&mut cli);
+            processing.apply(&mut cli);
+            let (settings, _) = load(cli)?;
+            let audio = match file {
+                Some(file) => AudioFile::read(file)?,
+                None if seconds > 0.0 && sample_rate > 0 && input_channels > 0 => {
+                    bench::synthetic(seconds, sample_rate, input_channels)
+                }
+                None => {
+                    return Err(RustscriberError::Settings(
+                        "--seconds, --sample-rate and --input-channels must be above zero"
+                            .to_string(),
+                    ));
+                }
+            };
+            let mut transcriber =
+                FileTranscriber::new(settings.model(), transcriber_options(&settings, true));
+            let report = bench::measure(&mut transcriber, &audio)?;
+            print!("{}", report.render(format));
+            Ok(())
+        }
+        #[cfg(unix)]
+        Command::Serve {
+            ref socket,
+            ref source,
+            ref model,
+            ref processing,
+            ref session,
+        } => {
+            let mut cli = config::Settings::default();
+            source.apply(&mut cli);
+            model.apply(&mut cli);
+            processing.apply(&mut cli);
+            session.apply(&mut cli);
+            cli.control_socket = Some(match socket {
+                Some(path) => config::SocketSetting::Path(path.clone()),
+                None => config::SocketSetting::Enabled(true),
+            });
+            let (settings, _) = load(cli)?;
+            listen(&settings, true)
+        }
+        Command::Models { ref model } => {
+            let mut cli = config::Settings::default();
+            model.apply(&mut cli);
+            let (settings, _) = load(cli)?;
+            models::check(settings.engine(), settings.model(), &settings.runtime())
+        }
+        #[cfg(unix)]
+        Command::Ctl {
+            ref socket,
+            ref command,
+        } => {
+            let socket = socket.clone().unwrap_or_else(control::default_socket_path);
+            control::run_client(&socket, command)
+        }
+        Command::Config {
+            command: ConfigCommand::Show,
+        } => {
+            let (settings, origin) = load(config::Settings::default())?;
+            config::show(&settings, &origin).map_err(RustscriberError::Failed)
+        }
+    }
+}
+
+/// Pick the host and input devices from `settings` and choose a stream
+/// config for each device.
+fn open_inputs(
+    settings: &config::Settings,
+) -> Result<(cpal::Host, audio_config::ConfigOverrides, Vec<Input>), RustscriberError> {
+    let host = device_selector::select_host(settings.host.as_deref())?;
+    let choice = device_selector::DeviceChoice {
+        ids: settings.input.clone(),
+        name_patterns: settings.input_name.clone(),
+        use_default: settings.default_device(),
+    };
+    let devices = device_selector::select_input_devices(&host, &choice)?;
+    println!("Audio host: {}", host.id().name());
+
+    let config_overrides = audio_config::ConfigOverrides {
+        sample_rate: settings.sample_rate,
+        channels: settings.device_channels,
+        sample_format: settings.sample_format(),
+        buffer_size: settings.buffer_size,
+        min_channels: settings.channel_mix().channels_needed() as u16,
+        split_channels: settings.split_channels(),
     };
-    println!("Using input device: {:?}", device.description());
+    let mut inputs = Vec::new();
+    for device in devices {
+        println!("Using input device: {:?}", device.description());
 
-    let (supported_config, sample_format) = audio_config::select_input_config(&device)
-        .expect("Failed to select input config");
-    let config: cpal::StreamConfig = supported_config.into();
+        let selected = audio_config::select_input_config(&device, &config_overrides)?;
 
-    println!(
-        "Audio config: {} channels, {} Hz, {:?}",
-        config.channels, config.sample_rate, sample_format
-    );
+        println!(
+            "Audio config: {} channels, {} Hz, {:?}",
+            selected.config.channels, selected.config.sample_rate, selected.sample_format
+        );
+        println!("  chosen because: {}", selected.reason);
+        inputs.push(Input {
+            device,
+            config: selected.config,
+            sample_format: selected.sample_format,
+        });
+    }
+    Ok((host, config_overrides, inputs))
+}
+
+fn transcriber_options(settings: &config::Settings, quiet: bool) -> TranscriberOptions {
+    TranscriberOptions {
+        pause_stream: settings.pause_stream(),
+        push_to_talk: settings.push_to_talk(),
+        pre_roll: Duration::from_millis(settings.pre_roll()),
+        channel_mix: settings.channel_mix(),
+        split_channels: settings.split_channels(),
+        channel_names: settings.channel_names.clone(),
+        dsp: dsp::DspOptions {
+            high_pass: settings.high_pass,
+            noise_gate: settings.noise_gate,
+            agc: settings.agc,
+        },
+        denoise: settings.denoise,
+        denoise_record: settings.denoise_record.clone(),
+        overload: settings.overload(),
+        runtime: settings.runtime(),
+        quiet,
+    }
+}
 
-    if let Some(filename) = args.record {
-        let recorder = WavRecorder::new(&filename, &device, &config, sample_format)
-            .expect("Failed to create WAV recorder");
+fn record(settings: &config::Settings, filename: &str) -> Result<(), RustscriberError> {
+    let (host, config_overrides, mut inputs) = open_inputs(settings)?;
+    if inputs.len() > 1 {
+        return Err(RustscriberError::Settings(
+            "record takes a single input device".to_string(),
+        ));
+    }
+    let mut recorder = WavRecorder::new(filename, inputs.remove(0))?;
 
-        recorder.start().expect("Failed to start recording");
-        println!("\nRecording to {}... Press Enter to stop.", filename);
+    recorder.start()?;
+    println!("\nRecording to {}... Press Enter to stop.", filename);
 
-        let _ = io::stdout().flush();
+    let _ = io::stdout().flush();
+    let monitor = LevelMonitor::start(vec![recorder.shared_levels()], io::stderr().is_terminal());
+    let (enter_tx, enter) = mpsc::channel();
+    thread::spawn(move || {
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+        let _ = io::stdin().read_line(&mut input);
+        let _ = enter_tx.send(());
+    });
+    // Keep the recording going across unplugging and replugging the device
+    while let Err(RecvTimeoutError::Timeout) = enter.recv_timeout(Duration::from_millis(200)) {
+        if let Some(change) = recorder.recover(&host, &config_overrides) {
+            eprintln!("\n[{}]", change);
+        }
+    }
+    drop(monitor);
 
-        recorder
-            .stop_and_finalize()
-            .expect("Failed to finalize recording");
-        println!("Recording saved to {}", filename);
-    } else {
-        // Default: live ASR
-        let t = Transcriber::new(Path::new(MODEL_PATH), &device, &config, sample_format)
-            .expect("Failed to create transcriber");
+    recorder.stop_and_finalize()?;
+    println!("Recording saved to {}", filename);
+    Ok(())
+}
 
-        t.start().expect("Failed to start transcription");
+/// Transcribe live input until stopped. A `headless` session has no keyboard
+/// or terminal interface and is controlled only through the control socket.
+fn listen(settings: &config::Settings, headless: bool) -> Result<(), RustscriberError> {
+    let tui = settings.tui() && !headless;
+    if tui && !io::stdout().is_terminal() {
+        return Err(RustscriberError::Settings(
+            "--tui needs a terminal".to_string(),
+        ));
+    }
+    if let Some(ref path) = settings.ptt_pipe
+        && !path.exists()
+    {
+        return Err(RustscriberError::Settings(format!(
+            "Push-to-talk pipe {} does not exist (create it with mkfifo)",
+            path.display()
+        )));
+    }
+
+    let (host, config_overrides, inputs) = open_inputs(settings)?;
+    let t = Transcriber::new(settings.model(), inputs, transcriber_options(settings, tui))?;
+
+    t.start()?;
+    let started = Instant::now();
+
+    let (requests, requests_rx) = mpsc::channel();
+
+    #[cfg(unix)]
+    let control_server = match settings.control_socket() {
+        Some(path) => {
+            let path = path.unwrap_or_else(control::default_socket_path);
+            let server = control::ControlServer::start(&path, requests.clone())?;
+            println!("Control socket listening on {}", path.display());
+            Some(server)
+        }
+        None => None,
+    };
+    #[cfg(unix)]
+    let stop_on_eof = control_server.is_none();
+    #[cfg(not(unix))]
+    let stop_on_eof = true;
+
+    if let Some(path) = settings.ptt_pipe.clone() {
+        push_to_talk::spawn_pipe_listener(path, requests.clone());
+    }
+
+    if tui {
+        let tui = tui::spawn(
+            requests,
+            tui::TuiOptions {
+                host: host.id(),
+                model: settings.model().display().to_string(),
+                push_to_talk: settings.push_to_talk(),
+                recording_dir: settings.recording_dir().to_path_buf(),
+                started,
+            },
+        );
+        Session::new(host, config_overrides, t)
+            .quiet()
+            .follow_default(settings.follow_default())
+            .run(requests_rx);
+        match tui.join() {
+            Ok(Err(e)) => eprintln!("Terminal interface failed: {}", e),
+            Err(_) => eprintln!("Terminal interface panicked"),
+            Ok(Ok(())) => {}
+        }
+
+        #[cfg(unix)]
+        if let Some(server) = control_server {
+            server.stop();
+        }
+        println!("Transcription stopped.");
+        return Ok(());
+    }
+
+    let keyboard = if headless {
+        println!("\nListening... Stop with `rustscriber ctl stop`.\n");
+        None
+    } else if io::stdin().is_terminal() {
+        let keyboard = keyboard::KeyboardControl::start(requests, settings.push_to_talk())
+            .map_err(|e| RustscriberError::io("Failed to read the keyboard", e))?;
+        // Raw mode is on, so spell out the carriage returns
+        print!("\r\nListening... ({})\r\n\r\n", keyboard.help());
+        Some(keyboard)
+    } else {
         println!("\nListening... Press Enter to stop.\n");
+        spawn_stdin_listener(requests, stop_on_eof);
+        None
+    };
+    let _ = io::stdout().flush();
+
+    let monitor = LevelMonitor::start(t.shared_levels(), settings.meter() && !headless);
+    Session::new(host, config_overrides, t)
+        .follow_default(settings.follow_default())
+        .run(requests_rx);
+    drop(monitor);
+    drop(keyboard);
+
+    #[cfg(unix)]
+    if let Some(server) = control_server {
+        server.stop();
+    }
+    println!("\nTranscription stopped.");
+    Ok(())
+}
+
+fn transcribe(
+    settings: &config::Settings,
+    file: &Path,
+    output: Option<&Path>,
+) -> Result<(), RustscriberError> {
+    let format = settings.format();
+    let audio = AudioFile::read(file)?;
+    let mut transcriber =
+        FileTranscriber::new(settings.model(), transcriber_options(settings, true));
+    let events = transcriber.transcribe(&audio)?;
+    let transcript = file_transcriber::render(&audio, &events, format);
+
+    match output {
+        Some(path) => fs::write(path, transcript)
+            .map_err(|e| RustscriberError::io(format!("Failed to write {}", path.display()), e))?,
+        None => print!("{}", transcript),
+    }
+    Ok(())
+}
+
+/// The comparison recording's names come from its stem alone, so every file
+/// after the first would overwrite the one before.
+fn check_denoise_record(settings: &config::Settings, files: usize) -> Result<(), RustscriberError> {
+    match settings.denoise_record {
+        Some(ref stem) if settings.denoise.is_some() && files > 1 => {
+            Err(RustscriberError::Settings(format!(
+                "--denoise-record {} records a single file; transcribe the files one at a time to compare them",
+                stem.display()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Transcribe each file in turn, carrying on past failures; fails at the end
+/// if any file did.
+fn batch(settings: &config::Settings, files: &[PathBuf]) -> Result<(), RustscriberError> {
+    let format = settings.format();
+    let mut transcriber =
+        FileTranscriber::new(settings.model(), transcriber_options(settings, true));
+    let outputs = batch_outputs(files, settings.output_dir.as_deref(), format)?;
+    check_denoise_record(settings, files.len())?;
+    let mut failed = 0;
+
+    for (file, output) in files.iter().zip(outputs) {
+        let result = AudioFile::read(file).and_then(|audio| {
+            let events = transcriber.transcribe(&audio)?;
+            let transcript = file_transcriber::render(&audio, &events, format);
+            fs::write(&output, transcript).map_err(|e| {
+                RustscriberError::io(format!("Failed to write {}", output.display()), e)
+            })
+        });
+        match result {
+            Ok(()) => eprintln!("{} → {}", file.display(), output.display()),
+            Err(e) => {
+                eprintln!("{}: {}", file.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(RustscriberError::Failed(format!(
+            "{} of {} file(s) failed",
+            failed,
+            files.len()
+        )));
+    }
+    Ok(())
+}
 
-        let _ = io::stdout().flush();
+/// Transcribe every file in `manifest` and print how far each transcript
+/// is from its reference.
+fn evaluate(
+    settings: &config::Settings,
+    manifest: &Path,
+    format: eval::ReportFormat,
+    diff: bool,
+    max_wer: Option<f64>,
+) -> Result<(), RustscriberError> {
+    let entries = eval::read_manifest(manifest)?;
+    check_denoise_record(settings, entries.len())?;
+    let mut transcriber =
+        FileTranscriber::new(settings.model(), transcriber_options(settings, true));
+    let mut files = Vec::with_capacity(entries.len());
+
+    for (i, entry) in entries.iter().enumerate() {
+        eprintln!(
+            "[{}/{}] {}",
+            i + 1,
+            entries.len(),
+            entry.audio_filepath.display()
+        );
+        let result =
+            AudioFile::read(&entry.audio_filepath).and_then(|audio| transcriber.transcribe(&audio));
+        let result = match result {
+            Ok(events) => {
+                eval::FileResult::Scored(eval::Score::new(&entry.text, &eval::hypothesis(&events)))
+            }
+            // Every file would fail the same way
+            Err(e @ RustscriberError::ModelLoad { .. }) => return Err(e),
+            Err(e) => eval::FileResult::Failed {
+                error: e.to_string(),
+            },
+        };
+        files.push(eval::FileReport {
+            audio_filepath: entry.audio_filepath.display().to_string(),
+            result,
+        });
+    }
+
+    let report = eval::Report::new(files);
+    print!("{}", report.render(format, diff));
+    let overall = &report.overall;
+    if overall.failed > 0 {
+        return Err(RustscriberError::Failed(format!(
+            "{} of {} file(s) failed",
+            overall.failed, overall.files
+        )));
+    }
+    if let Some(max_wer) = max_wer
+        && overall.wer * 100.0 > max_wer
+    {
+        return Err(RustscriberError::Failed(format!(
+            "WER {:.1}% is above --max-wer {}%",
+            overall.wer * 100.0,
+            max_wer
+        )));
+    }
+    Ok(())
+}
+
+/// `<stem>.txt` (or `.json`) next to `file`, or in `output_dir`. Only the
+/// last extension is replaced, so `talk.v1.wav` becomes `talk.v1.txt`.
+fn batch_output(
+    file: &Path,
+    output_dir: Option<&Path>,
+    format: TranscriptFormat,
+) -> Result<PathBuf, RustscriberError> {
+    let stem = file
+        .file_stem()
+        .filter(|_| file != Path::new("-"))
+        .ok_or_else(|| {
+            RustscriberError::Settings("batch needs file paths, not stdin".to_string())
+        })?;
+    let dir = output_dir.or(file.parent()).unwrap_or(Path::new(""));
+    Ok(dir.join(format!("{}.{}", stem.to_string_lossy(), format.extension())))
+}
+
+/// The output path for each of `files`, refusing to start when two of them
+/// would write the same transcript (e.g. `a/x.wav` and `b/x.wav` with
+/// `--output-dir`).
+fn batch_outputs(
+    files: &[PathBuf],
+    output_dir: Option<&Path>,
+    format: TranscriptFormat,
+) -> Result<Vec<PathBuf>, RustscriberError> {
+    let mut outputs: Vec<PathBuf> = Vec::with_capacity(files.len());
+    for file in files {
+        let output = batch_output(file, output_dir, format)?;
+        if let Some(i) = outputs.iter().position(|o| *o == output) {
+            return Err(RustscriberError::Settings(format!(
+                "{} and {} would both be written to {}",
+                files[i].display(),
+                file.display(),
+                output.display()
+            )));
+        }
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
+
+/// Stop the session when Enter is pressed. With `stop_on_eof` unset, a closed
+/// stdin (e.g. running in the background) leaves the session to be stopped
+/// through the control socket instead.
+fn spawn_stdin_listener(requests: Sender<ControlRequest>, stop_on_eof: bool) {
+    thread::spawn(move || {
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+        if matches!(io::stdin().read_line(&mut input), Ok(0)) && !stop_on_eof {
+            return;
+        }
+        let (request, _) = ControlRequest::new(SessionCommand::Stop);
+        let _ = requests.send(request);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn flags_do_not_clash() {
+        // Flattened flag groups share one namespace per subcommand
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn batch_output_keeps_multi_dot_stems() {
+        let files = [
+            PathBuf::from("in/talk.v1.wav"),
+            PathBuf::from("in/talk.v2.wav"),
+        ];
+        let outputs = batch_outputs(&files, None, TranscriptFormat::Text).unwrap();
+        assert_eq!(
+            outputs,
+            [
+                PathBuf::from("in/talk.v1.txt"),
+                PathBuf::from("in/talk.v2.txt")
+            ]
+        );
+        assert_eq!(
+            batch_output(
+                Path::new("talk.wav"),
+                Some(Path::new("out")),
+                TranscriptFormat::Json
+            )
+            .unwrap(),
+            PathBuf::from("out/talk.json")
+        );
+    }
+
+    #[test]
+    fn batch_outputs_reject_collisions() {
+        let files = [PathBuf::from("a/x.wav"), PathBuf::from("b/x.wav")];
+        assert!(batch_outputs(&files, None, TranscriptFormat::Text).is_ok());
+        assert!(batch_outputs(&files, Some(Path::new("out")), TranscriptFormat::Text).is_err());
+        let files = [PathBuf::from("x.wav"), PathBuf::from("x.flac")];
+        assert!(batch_outputs(&files, None, TranscriptFormat::Text).is_err());
+    }
 
-        t.stop();
-        println!("\nTranscription stopped.");
+    #[test]
+    fn denoise_record_needs_a_single_file() {
+        let mut settings = config::Settings {
+            denoise_record: Some(PathBuf::from("compare")),
+            ..Default::default()
+        };
+        // Without --denoise nothing is recorded
+        assert!(check_denoise_record(&settings, 3).is_ok());
+        settings.denoise = Some(20.0);
+        assert!(check_denoise_record(&settings, 1).is_ok());
+        assert!(check_denoise_record(&settings, 2).is_err());
     }
 }
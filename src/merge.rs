@@ -0,0 +1,245 @@
+use crate::pipeline::SourceEvent;
+use crate::transcriber::{Subscribers, TranscriptEvent};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::mpsc::Receiver;
+use std::thread::{self, JoinHandle};
+
+/// Collect events from every pipeline and print and broadcast them in session
+/// time order. An event is held until every source has got past its start, so
+/// a slower pipeline can't have its text appear after later text from another.
+/// With `print` unset, events only go to subscribers.
+pub fn spawn_merger(
+    sources: usize,
+    events: Receiver<SourceEvent>,
+    subscribers: Subscribers,
+    print: bool,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut merger = Merger::new(sources);
+        let mut printer = print.then(|| Printer::new(sources > 1));
+        let mut emit = |(source, event): (usize, TranscriptEvent)| {
+            if let Some(printer) = &mut printer {
+                printer.print(source, &event);
+            }
+            broadcast(&subscribers, event);
+        };
+
+        for message in events {
+            merger.push(message);
+            merger.release(&mut emit);
+        }
+        merger.finish(&mut emit);
+    })
+}
+
+/// Orders events across sources. Each source's events wait in their own
+/// queue, in the order the source produced them, and the queue whose head
+/// starts earliest goes next once every source's clock has passed it. Within
+/// one source the order never changes, so a press's `Final` (which starts
+/// where the press did) still follows that press's text.
+struct Merger {
+    clocks: Vec<f64>,
+    queues: Vec<VecDeque<TranscriptEvent>>,
+}
+
+impl Merger {
+    fn new(sources: usize) -> Self {
+        Self {
+            clocks: vec![0.0; sources],
+            queues: vec![VecDeque::new(); sources],
+        }
+    }
+
+    fn push(&mut self, message: SourceEvent) {
+        self.clocks[message.source] = message.clock;
+        if let Some(event) = message.event {
+            self.queues[message.source].push_back(event);
+        }
+    }
+
+    /// Pass on every event no source can still come in ahead of.
+    fn release(&mut self, emit: &mut impl FnMut((usize, TranscriptEvent))) {
+        let horizon = self.clocks.iter().copied().fold(f64::INFINITY, f64::min);
+        self.release_until(horizon, emit);
+    }
+
+    /// Pass on everything left, once every source has finished.
+    fn finish(&mut self, emit: &mut impl FnMut((usize, TranscriptEvent))) {
+        self.release_until(f64::INFINITY, emit);
+    }
+
+    fn release_until(&mut self, horizon: f64, emit: &mut impl FnMut((usize, TranscriptEvent))) {
+        // Ties go to the lower source, as the earliest head is found first
+        while let Some(source) = self
+            .queues
+            .iter()
+            .enumerate()
+            .filter_map(|(source, queue)| Some((source, queue.front()?.start())))
+            .filter(|&(_, start)| start <= horizon)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(source, _)| source)
+        {
+            if let Some(event) = self.queues[source].pop_front() {
+                emit((source, event));
+            }
+        }
+    }
+}
+
+/// Send an event to every subscriber, dropping any that have gone away.
+fn broadcast(subscribers: &Subscribers, event: TranscriptEvent) {
+    if let Ok(mut guard) = subscribers.lock() {
+        guard.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+/// Streams text to stdout. With several sources, each change of speaker
+/// starts a new line prefixed with the source's label.
+struct Printer {
+    labelled: bool,
+    /// Source whose text the current line holds, if any
+    line: Option<usize>,
+}
+
+impl Printer {
+    fn new(labelled: bool) -> Self {
+        Self {
+            labelled,
+            line: None,
+        }
+    }
+
+    fn print(&mut self, source: usize, event: &TranscriptEvent) {
+        match event {
+            TranscriptEvent::Text {
+                text,
+                source: label,
+                ..
+            } => {
+                if self.labelled && self.line != Some(source) {
+                    if self.line.is_some() {
+                        print!("\r\n");
+                    }
+                    print!("[{}]", label.as_deref().unwrap_or("?"));
+                }
+                self.line = Some(source);
+                print!("{}", text);
+            }
+            // The text of a press has already been streamed; end its line
+            TranscriptEvent::Final { .. } => {
+                if !self.labelled || self.line == Some(source) {
+                    print!("\r\n");
+                    self.line = None;
+                }
+            }
+            TranscriptEvent::Gap { .. } => {}
+        }
+        let _ = std::io::stdout().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcriber::GapReason;
+
+    fn text(start: f64, text: &str) -> TranscriptEvent {
+        TranscriptEvent::Text {
+            text: text.to_string(),
+            start,
+            end: start + 0.56,
+            tokens: Vec::new(),
+            source: None,
+        }
+    }
+
+    fn message(source: usize, clock: f64, event: Option<TranscriptEvent>) -> SourceEvent {
+        SourceEvent {
+            source,
+            clock,
+            event,
+        }
+    }
+
+    fn released(merger: &mut Merger) -> Vec<(usize, f64)> {
+        let mut out = Vec::new();
+        merger.release(&mut |(source, event)| out.push((source, event.start())));
+        out
+    }
+
+    #[test]
+    fn slow_source_holds_back_fast_one() {
+        let mut merger = Merger::new(2);
+        merger.push(message(0, 1.12, Some(text(0.56, " a"))));
+        merger.push(message(0, 1.68, Some(text(1.12, " b"))));
+        assert!(released(&mut merger).is_empty());
+
+        // Source 1 gets to 1.0: source 0's first event can go, and source
+        // 1's own event, which starts earlier, goes ahead of it
+        merger.push(message(1, 1.0, Some(text(0.3, " c"))));
+        assert_eq!(released(&mut merger), [(1, 0.3), (0, 0.56)]);
+
+        merger.push(message(1, 2.24, None));
+        assert_eq!(released(&mut merger), [(0, 1.12)]);
+    }
+
+    #[test]
+    fn keeps_order_within_a_source() {
+        let mut merger = Merger::new(1);
+        // Out of start order, as a gap reported late can be
+        merger.push(message(0, 2.0, Some(text(1.5, " later"))));
+        merger.push(message(0, 2.0, Some(text(1.0, " earlier"))));
+        assert_eq!(released(&mut merger), [(0, 1.5), (0, 1.0)]);
+    }
+
+    #[test]
+    fn final_follows_queued_text_of_its_press() {
+        let mut merger = Merger::new(2);
+        merger.push(message(0, 0.56, Some(text(0.0, " hello"))));
+        merger.push(message(0, 1.12, Some(text(0.56, " there"))));
+        merger.push(message(
+            0,
+            1.12,
+            Some(TranscriptEvent::Final {
+                text: "hello there".to_string(),
+                start: 0.0,
+                end: 1.12,
+                words: Vec::new(),
+                source: None,
+            }),
+        ));
+        merger.push(message(1, 2.0, None));
+
+        let mut out = Vec::new();
+        merger.release(&mut |(_, event)| out.push(event));
+        assert!(matches!(out[0], TranscriptEvent::Text { ref text, .. } if text == " hello"));
+        assert!(matches!(out[1], TranscriptEvent::Text { ref text, .. } if text == " there"));
+        assert!(matches!(out[2], TranscriptEvent::Final { .. }));
+    }
+
+    #[test]
+    fn a_source_in_an_outage_does_not_stall_the_others() {
+        let mut merger = Merger::new(2);
+        merger.push(message(1, 0.5, None));
+        merger.push(message(0, 1.12, Some(text(0.56, " a"))));
+        assert!(released(&mut merger).is_empty());
+
+        // Source 1's input is gone, but its clock keeps up with wall time
+        merger.push(message(1, 1.2, None));
+        assert_eq!(released(&mut merger), [(0, 0.56)]);
+        merger.push(message(0, 2.24, Some(text(1.68, " b"))));
+        merger.push(message(1, 2.3, None));
+        assert_eq!(released(&mut merger), [(0, 1.68)]);
+
+        // The input is back: its gap starts in the past and goes straight out
+        let gap = TranscriptEvent::Gap {
+            start: 0.5,
+            end: 2.5,
+            reason: GapReason::Disconnected,
+            source: None,
+        };
+        merger.push(message(1, 2.5, Some(gap)));
+        assert_eq!(released(&mut merger), [(1, 0.5)]);
+    }
+}
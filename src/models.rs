@@ -0,0 +1,59 @@
+use crate::error::RustscriberError;
+use crate::execution::{self, Runtime};
+use std::fs;
+use std::path::Path;
+
+/// Files the Nemotron engine loads from its model directory
+const NEMOTRON_FILES: [&str; 4] = [
+    "encoder.onnx",
+    "encoder.onnx.data",
+    "decoder_joint.onnx",
+    "tokenizer.model",
+];
+
+/// Print which of `engine`'s model files are present in `dir`, and which
+/// execution providers could run them. Fails if any files are missing.
+pub fn check(engine: &str, dir: &Path, runtime: &Runtime) -> Result<(), RustscriberError> {
+    println!("Engine: {}", engine);
+    println!("Runs with: {}", runtime);
+    if !execution::is_available(runtime.provider) {
+        println!(
+            "  {} isn't in this ONNX Runtime build; the model would run on the CPU",
+            execution::provider_name(runtime.provider)
+        );
+    }
+    println!("Execution providers compiled in:");
+    for (name, provider) in execution::providers() {
+        let available = if execution::is_available(provider) {
+            "available"
+        } else {
+            "not in this ONNX Runtime build"
+        };
+        println!("  {:<20} {}", name, available);
+    }
+    println!("Model directory: {}", dir.display());
+
+    let mut missing = 0;
+    for name in NEMOTRON_FILES {
+        match fs::metadata(dir.join(name)) {
+            Ok(meta) if meta.is_file() => {
+                println!("  {:<20} {:>8.1} MB", name, meta.len() as f64 / 1e6);
+            }
+            _ => {
+                println!("  {:<20} missing", name);
+                missing += 1;
+            }
+        }
+    }
+
+    if missing > 0 {
+        return Err(RustscriberError::Failed(format!(
+            "{} model file(s) missing from {}; download them from \
+             https://huggingface.co/altunenes/parakeet-rs/tree/main/nemotron-speech-streaming-en-0.6b",
+            missing,
+            dir.display()
+        )));
+    }
+    println!("All model files present.");
+    Ok(())
+}
@@ -0,0 +1,873 @@
+use crate::denoise::Denoiser;
+use crate::dsp::{self, Dsp};
+use crate::engine::{self, ChunkOutput, Engine, Recognizer, Token};
+use crate::error::RustscriberError;
+use crate::push_to_talk::Gate;
+use crate::transcriber::{GapReason, TranscriptEvent};
+use rubato::{FftFixedIn, Resampler};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const ASR_SAMPLE_RATE: usize = 16000;
+/// 560ms at 16kHz — required chunk size for Nemotron
+const NEMOTRON_CHUNK_SIZE: usize = 8960;
+/// Silent chunks pushed through the model after a push-to-talk release (or at
+/// the end of a file) so the decoder emits the end of the utterance
+const PTT_FLUSH_CHUNKS: usize = 2;
+/// Chunks quieter than this are skipped under `OverloadPolicy::SkipSilence`
+const SILENCE_DBFS: f32 = -50.0;
+pub const DEFAULT_MAX_BACKLOG: Duration = Duration::from_secs(2);
+
+/// Mono samples captured for one pipeline. `generation` is bumped whenever
+/// the device is swapped so the pipeline knows to drop leftovers from the
+/// old stream and rebuild its resampler for `input_rate`.
+pub struct CaptureBuffer {
+    pub samples: VecDeque<f32>,
+    pub input_rate: usize,
+    pub generation: u64,
+    /// Seconds of input lost to a disconnect that the pipeline hasn't
+    /// reported yet
+    pub lost: f64,
+    /// When the device went away, while it is being reconnected
+    pub lost_since: Option<Instant>,
+    /// Seconds of audio the pipeline has taken from `samples` but not yet
+    /// run through the model
+    pub waiting: f64,
+}
+
+pub type SharedBuffer = Arc<Mutex<CaptureBuffer>>;
+
+impl CaptureBuffer {
+    pub fn shared(input_rate: usize) -> SharedBuffer {
+        Arc::new(Mutex::new(CaptureBuffer {
+            samples: VecDeque::new(),
+            input_rate,
+            generation: 0,
+            lost: 0.0,
+            lost_since: None,
+            waiting: 0.0,
+        }))
+    }
+
+    /// How far the pipeline is behind the audio captured so far, in seconds.
+    pub fn backlog_secs(&self) -> f64 {
+        self.samples.len() as f64 / self.input_rate as f64 + self.waiting
+    }
+}
+
+/// What a live pipeline does once more audio is waiting for the model than
+/// its `max_backlog`, i.e. the model runs slower than real time.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OverloadPolicy {
+    /// Say so and carry on; the transcript falls further behind
+    #[default]
+    Warn,
+    /// Drop the oldest waiting audio, reported as a `GapReason::Overloaded` gap
+    Drop,
+    /// Skip silent chunks without running the model
+    SkipSilence,
+}
+
+pub fn parse_overload(s: &str) -> Result<OverloadPolicy, String> {
+    match s {
+        "warn" => Ok(OverloadPolicy::Warn),
+        "drop" => Ok(OverloadPolicy::Drop),
+        "skip-silence" => Ok(OverloadPolicy::SkipSilence),
+        other => Err(format!(
+            "Unknown overload policy: {} (expected warn, drop or skip-silence)",
+            other
+        )),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Overload {
+    pub policy: OverloadPolicy,
+    pub max_backlog: Duration,
+}
+
+impl Default for Overload {
+    fn default() -> Self {
+        Self {
+            policy: OverloadPolicy::default(),
+            max_backlog: DEFAULT_MAX_BACKLOG,
+        }
+    }
+}
+
+/// A pipeline has fallen more than `max_backlog` behind live audio. Raised
+/// once, and again only after it has caught up.
+#[derive(Clone, Debug)]
+pub struct BacklogWarning {
+    pub source: Option<String>,
+    /// Seconds of audio waiting for the model
+    pub secs: f64,
+    pub policy: OverloadPolicy,
+}
+
+impl fmt::Display for BacklogWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.source {
+            Some(ref source) => write!(f, "Transcription of {}", source)?,
+            None => write!(f, "Transcription")?,
+        }
+        write!(f, " is {:.1} s behind live audio", self.secs)?;
+        match self.policy {
+            OverloadPolicy::Warn => Ok(()),
+            OverloadPolicy::Drop => write!(f, "; dropping the oldest audio"),
+            OverloadPolicy::SkipSilence => write!(f, "; skipping silence"),
+        }
+    }
+}
+
+/// How long one chunk took, for benchmarking. `start` and `end` are the
+/// session time the chunk's audio covers.
+pub struct ChunkTiming {
+    pub source: usize,
+    pub start: f64,
+    pub end: f64,
+    pub inference: Duration,
+    pub finished: Instant,
+}
+
+pub type ChunkTimings = Arc<Mutex<Vec<ChunkTiming>>>;
+
+/// Session-wide flags every pipeline follows.
+#[derive(Clone, Default)]
+pub struct Controls {
+    pub stop: Arc<AtomicBool>,
+    pub paused: Arc<AtomicBool>,
+    pub talking: Arc<AtomicBool>,
+    /// When capture started; each pipeline's clock starts from its first audio
+    /// relative to this, so sources from different devices line up
+    pub started: Arc<OnceLock<Instant>>,
+    /// Raised by pipelines, for the session to report
+    pub backlog_warnings: Arc<Mutex<Vec<BacklogWarning>>>,
+}
+
+/// Optional processing a pipeline applies before recognition.
+#[derive(Default)]
+pub struct Stages {
+    /// Noise suppression at the input rate, before level conditioning
+    pub denoise: Option<Denoiser>,
+    /// Level conditioning at the input rate, before resampling
+    pub dsp: Option<Dsp>,
+    /// Push-to-talk gate on the 16kHz audio
+    pub gate: Option<Gate>,
+}
+
+/// What a pipeline reports to the merger. `clock` is how far into the session
+/// the pipeline has got; `event` is `None` when only the clock moved.
+pub struct SourceEvent {
+    pub source: usize,
+    pub clock: f64,
+    pub event: Option<TranscriptEvent>,
+}
+
+/// Resampling, push-to-talk gating and recognition for one mono source,
+/// with its own model instance so sources don't share decoder state.
+pub struct Pipeline<M = Engine> {
+    source: usize,
+    label: Option<String>,
+    model: M,
+    buffer: SharedBuffer,
+    stages: Stages,
+    controls: Controls,
+    events: Sender<SourceEvent>,
+
+    resampler: Option<FftFixedIn<f32>>,
+    /// Rate the resampler and stages are set up for
+    input_rate: usize,
+    generation: Option<u64>,
+    /// Input-rate samples waiting for a full resampler chunk
+    pending: Vec<f32>,
+    /// 16kHz samples waiting for a full Nemotron chunk
+    asr_buffer: Vec<f32>,
+    /// Session time (seconds) at the end of the last chunk fed to the model
+    clock: f64,
+    /// When the current pause began, and how much buffered audio it discarded
+    paused_at: Option<(Instant, f64)>,
+    /// Clock when the input went away, while it is being reconnected
+    outage_start: Option<f64>,
+    /// Start time, text and tokens of the current push-to-talk utterance
+    utterance: Option<(f64, String, Vec<Token>)>,
+    /// Whether `clock` has been lined up with `Controls::started`
+    anchored: bool,
+    /// The first error from the model; live sessions carry on regardless
+    failure: Option<RustscriberError>,
+    /// Where to record each chunk's timing, when benchmarking
+    timings: Option<ChunkTimings>,
+    /// How to keep up with live audio; files are never behind
+    overload: Option<Overload>,
+    /// Over `max_backlog` and already said so
+    behind: bool,
+}
+
+impl<M: Recognizer> Pipeline<M> {
+    pub fn new(
+        source: usize,
+        label: Option<String>,
+        model: M,
+        buffer: SharedBuffer,
+        stages: Stages,
+        controls: Controls,
+        events: Sender<SourceEvent>,
+    ) -> Self {
+        Self {
+            source,
+            label,
+            model,
+            buffer,
+            stages,
+            controls,
+            events,
+            resampler: None,
+            input_rate: ASR_SAMPLE_RATE,
+            generation: None,
+            pending: Vec::new(),
+            asr_buffer: Vec::with_capacity(NEMOTRON_CHUNK_SIZE * 2),
+            clock: 0.0,
+            paused_at: None,
+            outage_start: None,
+            utterance: None,
+            anchored: false,
+            failure: None,
+            timings: None,
+            overload: None,
+            behind: false,
+        }
+    }
+
+    /// Watch how far behind live audio the model is, and apply `overload`'s
+    /// policy when it is too far.
+    pub fn overload(mut self, overload: Overload) -> Self {
+        self.overload = Some(overload);
+        self
+    }
+
+    /// Record how long each chunk takes in `timings`.
+    pub fn timed(mut self, timings: Option<ChunkTimings>) -> Self {
+        self.timings = timings;
+        self
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        thread::spawn(move || self.run())
+    }
+
+    /// Transcribe a whole recording at `input_rate`, as fast as the model
+    /// allows, flushing the decoder at the end. Hands the model back, so it
+    /// can be reused for the next file, along with the first error if any.
+    pub fn transcribe_all(
+        mut self,
+        samples: Vec<f32>,
+        input_rate: usize,
+    ) -> (M, Result<(), RustscriberError>) {
+        match self.set_input_rate(input_rate) {
+            Ok(()) => {
+                for block in samples.chunks(input_rate) {
+                    self.process(block.to_vec());
+                    if self.failure.is_some() {
+                        break;
+                    }
+                }
+                if self.failure.is_none() {
+                    self.flush();
+                }
+            }
+            Err(e) => self.failure = Some(e),
+        }
+        self.clock = f64::INFINITY;
+        self.send(None);
+        let result = self.failure.map_or(Ok(()), Err);
+        (self.model, result)
+    }
+
+    fn run(mut self) {
+        loop {
+            if self.controls.stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            // Drain available samples from the shared buffer
+            let (drained, input_rate, current_generation, lost, lost_since) = {
+                let mut guard = self.buffer.lock().unwrap();
+                let drained: Vec<f32> = guard.samples.drain(..).collect();
+                let lost = std::mem::take(&mut guard.lost);
+                (
+                    drained,
+                    guard.input_rate,
+                    guard.generation,
+                    lost,
+                    guard.lost_since,
+                )
+            };
+
+            // First pass, or the device was swapped: set up the resampler for
+            // the current input rate.
+            if self.generation != Some(current_generation) {
+                if let Err(e) = self.set_input_rate(input_rate) {
+                    eprintln!("{}", e);
+                    break;
+                }
+                self.generation = Some(current_generation);
+            }
+
+            self.follow_outage(lost_since, lost);
+
+            if !self.anchored
+                && !drained.is_empty()
+                && let Some(started) = self.controls.started.get()
+            {
+                let buffered = drained.len() as f64 / input_rate as f64;
+                self.clock = (started.elapsed().as_secs_f64() - buffered).max(0.0);
+                self.anchored = true;
+            }
+
+            if self.controls.paused.load(Ordering::Relaxed) {
+                if self.paused_at.is_none() {
+                    self.pause();
+                }
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            self.resume();
+
+            if drained.is_empty() {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            self.process(drained);
+        }
+
+        // Let the merger release everything still waiting on this source
+        self.clock = f64::INFINITY;
+        self.send(None);
+    }
+
+    /// Set up the resampler and rate-dependent stages for a new input rate,
+    /// dropping any input left over from the old one.
+    fn set_input_rate(&mut self, input_rate: usize) -> Result<(), RustscriberError> {
+        self.pending.clear();
+        self.input_rate = input_rate;
+        self.resampler = if input_rate != ASR_SAMPLE_RATE {
+            // Use a chunk size that divides nicely into our workflow.
+            // 1024 input frames is a reasonable FFT size.
+            let resampler = FftFixedIn::<f32>::new(input_rate, ASR_SAMPLE_RATE, 1024, 1, 1)
+                .map_err(|e| RustscriberError::Resampler(e.to_string()))?;
+            Some(resampler)
+        } else {
+            None
+        };
+        if let Some(ref mut denoise) = self.stages.denoise {
+            denoise.set_sample_rate(input_rate);
+        }
+        if let Some(ref mut dsp) = self.stages.dsp {
+            dsp.set_sample_rate(input_rate);
+        }
+        Ok(())
+    }
+
+    /// Run input-rate mono audio through the stages and the model.
+    fn process(&mut self, mut drained: Vec<f32>) {
+        let chunk_secs = NEMOTRON_CHUNK_SIZE as f64 / ASR_SAMPLE_RATE as f64;
+
+        if let Some(ref mut denoise) = self.stages.denoise {
+            drained = denoise.process(&drained);
+        }
+        if let Some(ref mut dsp) = self.stages.dsp {
+            dsp.process(&mut drained);
+        }
+
+        let samples_16k = self.resample(drained);
+
+        // Push-to-talk gate: only pass audio while talking
+        let (samples_16k, released) = match self.stages.gate {
+            Some(ref mut gate) => {
+                let talking = self.controls.talking.load(Ordering::Relaxed);
+                let out = gate.process(&samples_16k, talking);
+                self.clock += out.dropped as f64 / ASR_SAMPLE_RATE as f64;
+                if out.opened {
+                    self.utterance = Some((self.clock, String::new(), Vec::new()));
+                }
+                if out.dropped > 0 {
+                    self.send(None);
+                }
+                (out.samples, out.closed)
+            }
+            None => (samples_16k, false),
+        };
+
+        self.asr_buffer.extend_from_slice(&samples_16k);
+        self.check_backlog();
+
+        // Feed full chunks to Nemotron
+        while self.asr_buffer.len() >= NEMOTRON_CHUNK_SIZE {
+            let skip = self.skipping_silence();
+            let chunk: Vec<f32> = self.asr_buffer.drain(..NEMOTRON_CHUNK_SIZE).collect();
+            if skip && dsp::rms_dbfs(&chunk) < SILENCE_DBFS {
+                self.clock += chunk_secs;
+                self.send(None);
+            } else {
+                self.transcribe_chunk(&chunk, chunk_secs);
+            }
+            self.publish_waiting();
+        }
+        self.publish_waiting();
+
+        if released {
+            self.finish_utterance();
+        }
+    }
+
+    /// Seconds of audio taken from the capture buffer but not yet through
+    /// the model.
+    fn waiting_secs(&self) -> f64 {
+        self.asr_buffer.len() as f64 / ASR_SAMPLE_RATE as f64
+            + self.pending.len() as f64 / self.input_rate as f64
+    }
+
+    fn publish_waiting(&self) {
+        let waiting = self.waiting_secs();
+        if let Ok(mut guard) = self.buffer.lock() {
+            guard.waiting = waiting;
+        }
+    }
+
+    /// Warn once when the model falls more than `max_backlog` behind, and
+    /// with `OverloadPolicy::Drop` drop all but the newest chunk of the
+    /// waiting audio.
+    fn check_backlog(&mut self) {
+        let Some(overload) = self.overload else {
+            return;
+        };
+        let waiting = self.waiting_secs();
+        let max_backlog = overload.max_backlog.as_secs_f64();
+        if waiting <= max_backlog {
+            // Some slack, so a backlog hovering at the limit isn't reported
+            // over and over
+            if waiting < max_backlog / 2.0 {
+                self.behind = false;
+            }
+            return;
+        }
+
+        if !self.behind {
+            self.behind = true;
+            self.controls
+                .backlog_warnings
+                .lock()
+                .unwrap()
+                .push(BacklogWarning {
+                    source: self.label.clone(),
+                    secs: waiting,
+                    policy: overload.policy,
+                });
+        }
+
+        if overload.policy == OverloadPolicy::Drop {
+            let dropped = self.asr_buffer.len().saturating_sub(NEMOTRON_CHUNK_SIZE);
+            self.asr_buffer.drain(..dropped);
+            let start = self.clock;
+            self.clock += dropped as f64 / ASR_SAMPLE_RATE as f64;
+            self.send(Some(TranscriptEvent::Gap {
+                start,
+                end: self.clock,
+                reason: GapReason::Overloaded,
+                source: self.label.clone(),
+            }));
+        }
+    }
+
+    /// Whether silent chunks should be skipped rather than transcribed.
+    fn skipping_silence(&self) -> bool {
+        self.overload.is_some_and(|overload| {
+            overload.policy == OverloadPolicy::SkipSilence
+                && self.waiting_secs() > overload.max_backlog.as_secs_f64()
+        })
+    }
+
+    /// Resample drained input to 16kHz, or pass it through.
+    fn resample(&mut self, drained: Vec<f32>) -> Vec<f32> {
+        let Some(ref mut resampler) = self.resampler else {
+            return drained;
+        };
+
+        // rubato expects non-interleaved channel data: &[&[f32]]
+        // We already have mono, so it's one channel.
+        self.pending.extend_from_slice(&drained);
+        let chunk_size = resampler.input_frames_next();
+        let mut resampled = Vec::new();
+
+        // Feed pending samples through the resampler in chunk_size batches
+        let mut offset = 0;
+        while offset + chunk_size <= self.pending.len() {
+            let input_chunk = &self.pending[offset..offset + chunk_size];
+            match resampler.process(&[input_chunk], None) {
+                Ok(output) => {
+                    if !output.is_empty() {
+                        resampled.extend_from_slice(&output[0]);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Resampler error: {}", e);
+                }
+            }
+            offset += chunk_size;
+        }
+
+        // Keep leftover samples for the next iteration
+        self.pending.drain(..offset);
+
+        resampled
+    }
+
+    /// Pad out the last partial chunk and push silence through the decoder
+    /// so it emits the end of what was said.
+    fn flush(&mut self) {
+        // Input still waiting for a full resampler chunk
+        if let Some(ref mut resampler) = self.resampler
+            && !self.pending.is_empty()
+        {
+            let real = self.pending.len() * ASR_SAMPLE_RATE / self.input_rate;
+            let mut padded = std::mem::take(&mut self.pending);
+            padded.resize(resampler.input_frames_next(), 0.0);
+            if let Ok(output) = resampler.process(&[&padded], None) {
+                let real = real.min(output[0].len());
+                self.asr_buffer.extend_from_slice(&output[0][..real]);
+            }
+        }
+
+        // Only the real audio advances the clock
+        let mut remaining = self.asr_buffer.len();
+        let mut padded = std::mem::take(&mut self.asr_buffer);
+        let chunks = remaining.div_ceil(NEMOTRON_CHUNK_SIZE).max(1) + PTT_FLUSH_CHUNKS;
+        padded.resize(NEMOTRON_CHUNK_SIZE * chunks, 0.0);
+        for chunk in padded.chunks(NEMOTRON_CHUNK_SIZE) {
+            let real = remaining.min(NEMOTRON_CHUNK_SIZE);
+            remaining -= real;
+            self.transcribe_chunk(chunk, real as f64 / ASR_SAMPLE_RATE as f64);
+        }
+    }
+
+    /// Flush the decoder after a push-to-talk release and report the whole
+    /// press.
+    fn finish_utterance(&mut self) {
+        self.flush();
+
+        if let Some((start, text, tokens)) = self.utterance.take() {
+            let event = TranscriptEvent::Final {
+                text: text.trim().to_string(),
+                start,
+                end: self.clock,
+                words: engine::words(&tokens, self.label.as_deref()),
+                source: self.label.clone(),
+            };
+            self.send(Some(event));
+        }
+        // Start the next press from a clean decoder state
+        self.model.reset();
+    }
+
+    /// Run one chunk covering `secs` of session time through the model.
+    fn transcribe_chunk(&mut self, chunk: &[f32], secs: f64) {
+        let start = self.clock;
+        self.clock += secs;
+
+        let began = Instant::now();
+        let output = match self.model.transcribe_chunk(chunk) {
+            Ok(output) => output,
+            Err(error) => {
+                eprintln!("\n{}", error);
+                self.failure.get_or_insert(error);
+                ChunkOutput::default()
+            }
+        };
+        if let Some(ref timings) = self.timings {
+            timings.lock().unwrap().push(ChunkTiming {
+                source: self.source,
+                start,
+                end: self.clock,
+                inference: began.elapsed(),
+                finished: Instant::now(),
+            });
+        }
+        let text = output.text();
+        let tokens = output.timed(start, self.clock);
+        if let Some((_, ref mut utterance_text, ref mut utterance_tokens)) = self.utterance {
+            utterance_text.push_str(&text);
+            utterance_tokens.extend_from_slice(&tokens);
+        }
+
+        let event = (!text.is_empty()).then(|| TranscriptEvent::Text {
+            text,
+            start,
+            end: self.clock,
+            tokens,
+            source: self.label.clone(),
+        });
+        self.send(event);
+    }
+
+    /// While the input is gone, keep the clock moving with wall time so the
+    /// merger doesn't hold every other source back waiting on this one. Once
+    /// it is back (`lost` seconds later), report the whole outage as one gap;
+    /// its start has passed by then, so it follows the other sources' text
+    /// from the outage.
+    fn follow_outage(&mut self, lost_since: Option<Instant>, lost: f64) {
+        if let Some(since) = lost_since {
+            let start = *self.outage_start.get_or_insert(self.clock);
+            let clock = start + since.elapsed().as_secs_f64();
+            if clock > self.clock {
+                self.clock = clock;
+                self.send(None);
+            }
+        }
+        if lost > 0.0 {
+            let start = self.outage_start.take().unwrap_or(self.clock);
+            self.clock = self.clock.max(start + lost);
+            self.send(Some(TranscriptEvent::Gap {
+                start,
+                end: self.clock,
+                reason: GapReason::Disconnected,
+                source: self.label.clone(),
+            }));
+        }
+    }
+
+    fn pause(&mut self) {
+        // Drop partial chunks rather than splice them onto post-resume
+        // audio; the gap marker covers them.
+        let discarded = self.asr_buffer.len() as f64 / ASR_SAMPLE_RATE as f64
+            + self.pending.len() as f64 / self.input_rate as f64;
+        self.asr_buffer.clear();
+        self.pending.clear();
+        self.publish_waiting();
+        self.paused_at = Some((Instant::now(), discarded));
+    }
+
+    /// Move the clock past a pause that has just ended, if any.
+    fn resume(&mut self) {
+        if let Some((since, discarded)) = self.paused_at.take() {
+            let start = self.clock;
+            self.clock += discarded + since.elapsed().as_secs_f64();
+            // Pausing is session-wide, so the first source reports the
+            // gap for all of them.
+            let gap = (self.source == 0).then_some(TranscriptEvent::Gap {
+                start,
+                end: self.clock,
+                reason: GapReason::Paused,
+                source: None,
+            });
+            self.send(gap);
+        }
+    }
+
+    fn send(&self, event: Option<TranscriptEvent>) {
+        let _ = self.events.send(SourceEvent {
+            source: self.source,
+            clock: self.clock,
+            event,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::{self, Receiver};
+
+    /// Counts the chunks it's given instead of recognizing them.
+    #[derive(Default)]
+    struct Counter {
+        chunks: usize,
+    }
+
+    impl Recognizer for Counter {
+        fn transcribe_chunk(&mut self, _: &[f32]) -> Result<ChunkOutput, RustscriberError> {
+            self.chunks += 1;
+            Ok(ChunkOutput::default())
+        }
+
+        fn reset(&mut self) {}
+    }
+
+    fn pipeline(policy: OverloadPolicy) -> (Pipeline<Counter>, Receiver<SourceEvent>) {
+        let (tx, rx) = mpsc::channel();
+        let mut pipeline = Pipeline::new(
+            0,
+            None,
+            Counter::default(),
+            CaptureBuffer::shared(ASR_SAMPLE_RATE),
+            Stages::default(),
+            Controls::default(),
+            tx,
+        )
+        .overload(Overload {
+            policy,
+            max_backlog: Duration::from_secs(1),
+        });
+        pipeline.set_input_rate(ASR_SAMPLE_RATE).unwrap();
+        (pipeline, rx)
+    }
+
+    fn secs(secs: f64) -> usize {
+        (secs * ASR_SAMPLE_RATE as f64) as usize
+    }
+
+    fn warnings(pipeline: &Pipeline<Counter>) -> usize {
+        pipeline.controls.backlog_warnings.lock().unwrap().len()
+    }
+
+    #[test]
+    fn drop_keeps_only_the_newest_chunk() {
+        let (mut pipeline, events) = pipeline(OverloadPolicy::Drop);
+        pipeline.process(vec![0.1; secs(3.0)]);
+
+        let dropped = secs(3.0) - NEMOTRON_CHUNK_SIZE;
+        let dropped_secs = dropped as f64 / ASR_SAMPLE_RATE as f64;
+        assert_eq!(pipeline.model.chunks, 1);
+        assert!((pipeline.clock - 3.0).abs() < 1e-9);
+        assert_eq!(warnings(&pipeline), 1);
+
+        let gaps: Vec<(f64, f64)> = events
+            .try_iter()
+            .filter_map(|message| match message.event {
+                Some(TranscriptEvent::Gap {
+                    start,
+                    end,
+                    reason: GapReason::Overloaded,
+                    ..
+                }) => Some((start, end)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!(gaps[0].0, 0.0);
+        assert!((gaps[0].1 - dropped_secs).abs() < 1e-9);
+    }
+
+    #[test]
+    fn skips_silence_only_while_behind() {
+        let (mut pipeline, _events) = pipeline(OverloadPolicy::SkipSilence);
+        // Five chunks: the first four start more than 1 s behind
+        pipeline.process(vec![0.0; secs(3.0)]);
+        assert_eq!(pipeline.model.chunks, 1);
+        assert!((pipeline.clock - 5.0 * 0.56).abs() < 1e-9);
+    }
+
+    #[test]
+    fn never_skips_speech() {
+        let (mut pipeline, _events) = pipeline(OverloadPolicy::SkipSilence);
+        pipeline.process(vec![0.5; secs(3.0)]);
+        assert_eq!(pipeline.model.chunks, 5);
+    }
+
+    #[test]
+    fn warns_once_until_caught_up() {
+        let (mut pipeline, _events) = pipeline(OverloadPolicy::Warn);
+        pipeline.process(vec![0.1; secs(1.5)]);
+        assert_eq!(warnings(&pipeline), 1);
+        // Still over the limit
+        pipeline.process(vec![0.1; secs(0.7)]);
+        assert_eq!(warnings(&pipeline), 1);
+        // Under the limit, but not by enough to count as caught up
+        pipeline.process(vec![0.1; secs(0.1)]);
+        assert!(pipeline.behind);
+        // Under half the limit
+        pipeline.process(vec![0.1; secs(0.1)]);
+        assert!(!pipeline.behind);
+        pipeline.process(vec![0.1; secs(1.5)]);
+        assert_eq!(warnings(&pipeline), 2);
+    }
+
+    fn clocks(events: &Receiver<SourceEvent>) -> Vec<f64> {
+        events.try_iter().map(|message| message.clock).collect()
+    }
+
+    #[test]
+    fn clock_follows_wall_time_during_an_outage() {
+        let (mut pipeline, events) = pipeline(OverloadPolicy::Warn);
+        pipeline.clock = 10.0;
+        let since = Instant::now() - Duration::from_secs(2);
+
+        pipeline.follow_outage(Some(since), 0.0);
+        let moved = clocks(&events);
+        assert_eq!(moved.len(), 1);
+        assert!(moved[0] >= 12.0 && moved[0] < 12.5);
+
+        // Still gone: the clock keeps moving without any gap yet
+        pipeline.follow_outage(Some(since - Duration::from_secs(1)), 0.0);
+        assert!(pipeline.clock >= 13.0);
+
+        // Back after 3.5 s: one gap from where the input went away
+        pipeline.follow_outage(None, 3.5);
+        let message = events.try_iter().last().unwrap();
+        let Some(TranscriptEvent::Gap {
+            start,
+            end,
+            reason: GapReason::Disconnected,
+            ..
+        }) = message.event
+        else {
+            panic!("expected a disconnect gap");
+        };
+        assert_eq!(start, 10.0);
+        assert_eq!(end, 13.5);
+        assert_eq!(message.clock, 13.5);
+        assert!(pipeline.outage_start.is_none());
+    }
+
+    #[test]
+    fn resuming_skips_the_discarded_audio_and_the_pause() {
+        let (tx, rx) = mpsc::channel();
+        let mut sources: Vec<Pipeline<Counter>> = (0..2)
+            .map(|source| {
+                let mut pipeline = Pipeline::new(
+                    source,
+                    None,
+                    Counter::default(),
+                    CaptureBuffer::shared(ASR_SAMPLE_RATE),
+                    Stages::default(),
+                    Controls::default(),
+                    tx.clone(),
+                );
+                pipeline.set_input_rate(ASR_SAMPLE_RATE).unwrap();
+                pipeline
+            })
+            .collect();
+
+        for pipeline in &mut sources {
+            // Half a chunk waiting for the model
+            pipeline.process(vec![0.1; secs(0.25)]);
+            pipeline.pause();
+            assert!(pipeline.asr_buffer.is_empty());
+        }
+        thread::sleep(Duration::from_millis(50));
+        for pipeline in &mut sources {
+            pipeline.resume();
+            assert!(pipeline.clock >= 0.3 && pipeline.clock < 1.0);
+        }
+
+        let gaps: Vec<(usize, f64, f64)> = rx
+            .try_iter()
+            .filter_map(|message| match message.event {
+                Some(TranscriptEvent::Gap {
+                    start,
+                    end,
+                    reason: GapReason::Paused,
+                    ..
+                }) => Some((message.source, start, end)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(gaps.len(), 1);
+        assert_eq!((gaps[0].0, gaps[0].1), (0, 0.0));
+        assert_eq!(gaps[0].2, sources[0].clock);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::session::{ControlRequest, SessionCommand};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+/// Gates 16kHz audio in front of the ASR buffer. While closed it keeps the
+/// most recent `pre_roll_len` samples so the start of a press isn't clipped.
+pub struct Gate {
+    pre_roll: VecDeque<f32>,
+    pre_roll_len: usize,
+    open: bool,
+}
+
+#[derive(Default)]
+pub struct GateOutput {
+    /// Samples to pass on to the model, including pre-roll on opening
+    pub samples: Vec<f32>,
+    /// Samples discarded while the gate was closed
+    pub dropped: usize,
+    pub opened: bool,
+    pub closed: bool,
+}
+
+impl Gate {
+    pub fn new(pre_roll_len: usize) -> Self {
+        Self {
+            pre_roll: VecDeque::with_capacity(pre_roll_len),
+            pre_roll_len,
+            open: false,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32], talking: bool) -> GateOutput {
+        let mut out = GateOutput::default();
+
+        if talking && !self.open {
+            out.opened = true;
+            out.samples.extend(self.pre_roll.drain(..));
+        } else if !talking && self.open {
+            out.closed = true;
+        }
+        self.open = talking;
+
+        if talking {
+            out.samples.extend_from_slice(samples);
+        } else {
+            self.pre_roll.extend(samples);
+            let excess = self.pre_roll.len().saturating_sub(self.pre_roll_len);
+            self.pre_roll.drain(..excess);
+            out.dropped = excess;
+        }
+
+        out
+    }
+}
+
+/// Read push-to-talk commands from a named pipe (create it with `mkfifo`).
+/// Each line is `press`, `release` or `toggle`.
+pub fn spawn_pipe_listener(path: PathBuf, requests: Sender<ControlRequest>) {
+    thread::spawn(move || {
+        loop {
+            // Opening a FIFO blocks until a writer connects; reopen after
+            // each writer hangs up.
+            let file = match File::open(&path) {
+                Ok(f) => f,
+                Err(e) => {
+                    eprintln!("Failed to open push-to-talk pipe {}: {}", path.display(), e);
+                    return;
+                }
+            };
+
+            for line in BufReader::new(file).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                let command = match line.trim() {
+                    "press" => SessionCommand::Talk(true),
+                    "release" => SessionCommand::Talk(false),
+                    "toggle" => SessionCommand::ToggleTalk,
+                    "" => continue,
+                    other => {
+                        eprintln!("Unknown push-to-talk command: {}", other);
+                        continue;
+                    }
+                };
+                let (request, _) = ControlRequest::new(command);
+                if requests.send(request).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp(from: usize, len: usize) -> Vec<f32> {
+        (from..from + len).map(|i| i as f32).collect()
+    }
+
+    #[test]
+    fn pre_roll_keeps_the_newest_samples() {
+        let mut gate = Gate::new(4);
+        let out = gate.process(&ramp(0, 3), false);
+        assert!(out.samples.is_empty());
+        assert_eq!(out.dropped, 0);
+
+        let out = gate.process(&ramp(3, 3), false);
+        assert_eq!(out.dropped, 2);
+        assert_eq!(gate.pre_roll, ramp(2, 4));
+
+        let out = gate.process(&ramp(6, 10), false);
+        assert_eq!(out.dropped, 10);
+        assert_eq!(gate.pre_roll, ramp(12, 4));
+    }
+
+    #[test]
+    fn opening_flushes_the_pre_roll_first() {
+        let mut gate = Gate::new(4);
+        gate.process(&ramp(0, 6), false);
+        let out = gate.process(&ramp(6, 3), true);
+        assert!(out.opened);
+        assert!(!out.closed);
+        assert_eq!(out.dropped, 0);
+        assert_eq!(out.samples, ramp(2, 7));
+        assert!(gate.pre_roll.is_empty());
+
+        // While open everything passes straight through
+        let out = gate.process(&ramp(9, 2), true);
+        assert!(!out.opened);
+        assert_eq!(out.samples, ramp(9, 2));
+    }
+
+    #[test]
+    fn reports_closing_once() {
+        let mut gate = Gate::new(4);
+        gate.process(&ramp(0, 2), true);
+        let out = gate.process(&ramp(2, 2), false);
+        assert!(out.closed);
+        assert!(out.samples.is_empty());
+        assert_eq!(out.dropped, 0);
+        assert!(!gate.process(&ramp(4, 2), false).closed);
+        // Audio after the release starts the next pre-roll
+        assert_eq!(gate.pre_roll, ramp(2, 4));
+    }
+}
@@ -0,0 +1,265 @@
+//! Noticing that an input stream has died and finding a device to carry on
+//! with.
+
+use crate::audio_config::{self, ConfigOverrides};
+use crate::device_enumerator::device_name;
+use crate::device_selector;
+use crate::error::RustscriberError;
+use crate::transcriber::Input;
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::{Host, StreamError};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a stream may keep failing without delivering audio before it is
+/// given up on. Some backends (e.g. ALSA) report an unplugged device as a
+/// stream of generic errors rather than `DeviceNotAvailable`.
+const STALL_TIMEOUT: Duration = Duration::from_secs(1);
+const FIRST_RETRY: Duration = Duration::from_millis(500);
+const MAX_RETRY: Duration = Duration::from_secs(8);
+/// Failed attempts at the lost device before the default device is tried too
+const FALLBACK_ATTEMPTS: u32 = 3;
+
+#[derive(Default)]
+struct Health {
+    /// The backend said the device is gone
+    lost: bool,
+    /// When errors started arriving with no audio in between
+    failing_since: Option<Instant>,
+}
+
+/// What an input stream's callbacks have reported, shared with its owner.
+#[derive(Clone, Default)]
+pub struct StreamHealth(Arc<Mutex<Health>>);
+
+impl StreamHealth {
+    /// Called from the audio callback.
+    pub fn audio(&self) {
+        if let Ok(mut health) = self.0.lock() {
+            health.failing_since = None;
+        }
+    }
+
+    /// Called from the stream's error callback.
+    pub fn error(&self, error: StreamError) {
+        let Ok(mut health) = self.0.lock() else {
+            return;
+        };
+        match error {
+            StreamError::DeviceNotAvailable | StreamError::StreamInvalidated => {
+                health.lost = true;
+                health.failing_since.get_or_insert_with(Instant::now);
+            }
+            // Glitches, but the stream carries on
+            StreamError::BufferUnderrun => {}
+            other => {
+                // A dead device can report the same error many times a
+                // second; only the first of a run is worth printing.
+                if health.failing_since.is_none() {
+                    eprintln!("Stream error: {}", other);
+                    health.failing_since = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    /// When the stream stopped delivering audio, if it has died.
+    pub fn lost_since(&self) -> Option<Instant> {
+        let health = self.0.lock().ok()?;
+        let since = health.failing_since?;
+        (health.lost || since.elapsed() >= STALL_TIMEOUT).then_some(since)
+    }
+}
+
+/// Doubling delays between reconnection attempts, up to a limit.
+struct Backoff {
+    next: Duration,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self { next: FIRST_RETRY }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(MAX_RETRY);
+        delay
+    }
+}
+
+/// A replacement for a lost input.
+pub struct Replacement {
+    pub input: Input,
+    /// The lost device wasn't found, so this is the default device
+    pub fallback: bool,
+}
+
+/// A change in an input's stream, reported by the owner's `recover`.
+pub enum Recovery {
+    Lost {
+        device: String,
+    },
+    Reconnected {
+        lost: String,
+        device: String,
+        after: Duration,
+        fallback: bool,
+    },
+    Failed {
+        device: String,
+        error: RustscriberError,
+    },
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Recovery::Lost { device } => {
+                write!(f, "Lost input device {}; reconnecting", device)
+            }
+            Recovery::Reconnected {
+                device,
+                after,
+                fallback: false,
+                ..
+            } => write!(
+                f,
+                "Reconnected to {} after {:.1} s",
+                device,
+                after.as_secs_f64()
+            ),
+            Recovery::Reconnected {
+                lost,
+                device,
+                after,
+                fallback: true,
+            } => write!(
+                f,
+                "{} did not come back; switched to the default device {} after {:.1} s",
+                lost,
+                device,
+                after.as_secs_f64()
+            ),
+            Recovery::Failed { device, error } => {
+                write!(f, "Reconnecting {} failed: {}", device, error)
+            }
+        }
+    }
+}
+
+/// Tracks one lost input until a replacement is found.
+pub struct Reconnect {
+    lost: Input,
+    device_id: Option<String>,
+    since: Instant,
+    backoff: Backoff,
+    next_attempt: Instant,
+    attempts: u32,
+}
+
+impl Reconnect {
+    pub fn new(lost: Input, since: Instant) -> Self {
+        let device_id = lost.device.id().ok().map(|id| id.to_string());
+        Self {
+            lost,
+            device_id,
+            since,
+            backoff: Backoff::new(),
+            next_attempt: Instant::now(),
+            attempts: 0,
+        }
+    }
+
+    /// When audio stopped arriving from the lost input.
+    pub fn since(&self) -> Instant {
+        self.since
+    }
+
+    pub fn device_name(&self) -> String {
+        device_name(&self.lost.device)
+    }
+
+    /// If an attempt is due, look for the lost device, or after a few tries
+    /// the default one unless it is among `in_use` (device IDs). The old
+    /// stream config is kept where the device still offers it, so
+    /// recordings can carry on. `Ok(None)` means nothing turned up.
+    pub fn attempt(
+        &mut self,
+        host: &Host,
+        overrides: &ConfigOverrides,
+        in_use: &[String],
+    ) -> Result<Option<Replacement>, RustscriberError> {
+        if Instant::now() < self.next_attempt {
+            return Ok(None);
+        }
+        self.attempts += 1;
+        self.next_attempt = Instant::now() + self.backoff.delay();
+
+        let found = self
+            .device_id
+            .as_deref()
+            .and_then(|id| device_selector::find_by_id(host, id));
+        let (device, fallback) = match found {
+            Some(device) => (device, false),
+            None if self.attempts >= FALLBACK_ATTEMPTS => {
+                let default = host.default_input_device().filter(|device| {
+                    let id = device.id().ok().map(|id| id.to_string());
+                    !id.is_some_and(|id| in_use.contains(&id))
+                });
+                match default {
+                    Some(device) => (device, true),
+                    None => return Ok(None),
+                }
+            }
+            None => return Ok(None),
+        };
+
+        let same = ConfigOverrides {
+            sample_rate: Some(self.lost.config.sample_rate),
+            channels: Some(self.lost.config.channels),
+            sample_format: Some(self.lost.sample_format),
+            ..overrides.clone()
+        };
+        let selected = audio_config::select_input_config(&device, &same)
+            .or_else(|_| audio_config::select_input_config(&device, overrides))?;
+        Ok(Some(Replacement {
+            input: Input {
+                device,
+                config: selected.config,
+                sample_format: selected.sample_format,
+            },
+            fallback,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let mut backoff = Backoff::new();
+        let delays: Vec<u128> = (0..7).map(|_| backoff.delay().as_millis()).collect();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 8000, 8000]);
+    }
+
+    #[test]
+    fn only_a_gone_device_is_lost_at_once() {
+        let health = StreamHealth::default();
+        health.error(StreamError::BufferUnderrun);
+        assert!(health.lost_since().is_none());
+
+        health.error(StreamError::BackendSpecific {
+            err: cpal::BackendSpecificError {
+                description: "poll failed".to_string(),
+            },
+        });
+        assert!(health.lost_since().is_none());
+        health.audio();
+        health.error(StreamError::DeviceNotAvailable);
+        assert!(health.lost_since().is_some());
+    }
+}
@@ -0,0 +1,177 @@
+use crate::error::RustscriberError;
+use crate::reconnect::StreamHealth;
+use cpal::traits::DeviceTrait;
+use cpal::{Device, FromSample, I24, SampleFormat, SizedSample, Stream, StreamConfig, U24};
+
+/// Convert interleaved samples of any cpal sample type to f32 in -1.0..=1.0,
+/// replacing the contents of `out`. Integer formats are scaled so their full
+/// range maps onto -1.0..1.0 (unsigned formats are re-centred on zero); float
+/// input outside the range is clipped.
+pub fn to_f32<T>(data: &[T], out: &mut Vec<f32>)
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    out.clear();
+    out.extend(
+        data.iter()
+            .map(|&sample| f32::from_sample_(sample).clamp(-1.0, 1.0)),
+    );
+}
+
+/// Convert an f32 sample to 16-bit PCM, clipping. Uses the same 32768 scale
+/// as the input conversion so i16 audio round-trips exactly.
+pub fn f32_to_i16(sample: f32) -> i16 {
+    (sample * 32768.0)
+        .round()
+        .clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Open an input stream in `sample_format` and hand each callback's audio to
+/// `callback` as interleaved f32. Audio and errors are reported to `health`
+/// so the owner can tell when the stream has died.
+pub fn build_input_stream<F>(
+    device: &Device,
+    config: &StreamConfig,
+    sample_format: SampleFormat,
+    health: &StreamHealth,
+    callback: F,
+) -> Result<Stream, RustscriberError>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    match sample_format {
+        SampleFormat::I8 => build::<i8, F>(device, config, health, callback),
+        SampleFormat::I16 => build::<i16, F>(device, config, health, callback),
+        SampleFormat::I24 => build::<I24, F>(device, config, health, callback),
+        SampleFormat::I32 => build::<i32, F>(device, config, health, callback),
+        SampleFormat::I64 => build::<i64, F>(device, config, health, callback),
+        SampleFormat::U8 => build::<u8, F>(device, config, health, callback),
+        SampleFormat::U16 => build::<u16, F>(device, config, health, callback),
+        SampleFormat::U24 => build::<U24, F>(device, config, health, callback),
+        SampleFormat::U32 => build::<u32, F>(device, config, health, callback),
+        SampleFormat::U64 => build::<u64, F>(device, config, health, callback),
+        SampleFormat::F32 => build::<f32, F>(device, config, health, callback),
+        SampleFormat::F64 => build::<f64, F>(device, config, health, callback),
+        // SampleFormat is non-exhaustive
+        _ => Err(RustscriberError::Format(format!(
+            "Unsupported sample format: {:?}",
+            sample_format
+        ))),
+    }
+}
+
+fn build<T, F>(
+    device: &Device,
+    config: &StreamConfig,
+    health: &StreamHealth,
+    mut callback: F,
+) -> Result<Stream, RustscriberError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    let audio_health = health.clone();
+    let error_health = health.clone();
+    // Reused across callbacks to avoid allocating on the audio thread
+    let mut floats = Vec::new();
+
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            audio_health.audio();
+            to_f32(data, &mut floats);
+            callback(&floats);
+        },
+        move |err| error_health.error(err),
+        None,
+    )?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert<T>(data: &[T]) -> Vec<f32>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let mut out = Vec::new();
+        to_f32(data, &mut out);
+        out
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-6, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn signed_integers_span_full_range() {
+        assert_close(&convert(&[i8::MIN, 0, 64]), &[-1.0, 0.0, 0.5]);
+        assert_close(&convert(&[i16::MIN, 0, 16384]), &[-1.0, 0.0, 0.5]);
+        assert_close(&convert(&[i32::MIN, 0, 1 << 30]), &[-1.0, 0.0, 0.5]);
+        assert_close(&convert(&[i64::MIN, 0, 1 << 62]), &[-1.0, 0.0, 0.5]);
+        assert_close(
+            &convert(&[
+                I24::new(-(1 << 23)).unwrap(),
+                I24::new(0).unwrap(),
+                I24::new(1 << 22).unwrap(),
+            ]),
+            &[-1.0, 0.0, 0.5],
+        );
+    }
+
+    #[test]
+    fn unsigned_integers_are_centred() {
+        assert_close(&convert(&[0u8, 128, 192]), &[-1.0, 0.0, 0.5]);
+        assert_close(&convert(&[0u16, 32768, 49152]), &[-1.0, 0.0, 0.5]);
+        assert_close(&convert(&[0u32, 1 << 31, 3 << 30]), &[-1.0, 0.0, 0.5]);
+        assert_close(&convert(&[0u64, 1 << 63, 3 << 62]), &[-1.0, 0.0, 0.5]);
+        assert_close(
+            &convert(&[
+                U24::new(0).unwrap(),
+                U24::new(1 << 23).unwrap(),
+                U24::new(3 << 22).unwrap(),
+            ]),
+            &[-1.0, 0.0, 0.5],
+        );
+    }
+
+    #[test]
+    fn integer_maximum_stays_below_one() {
+        for sample in convert(&[i16::MAX]).into_iter().chain(convert(&[u8::MAX])) {
+            assert!(sample > 0.99 && sample < 1.0);
+        }
+    }
+
+    #[test]
+    fn floats_are_clipped() {
+        assert_close(
+            &convert(&[-1.5f32, -0.25, 0.25, 2.0]),
+            &[-1.0, -0.25, 0.25, 1.0],
+        );
+        assert_close(&convert(&[-3.0f64, 0.5, 1.0001]), &[-1.0, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn i16_round_trips_exactly() {
+        let original = [i16::MIN, -12345, -1, 0, 1, 12345, i16::MAX];
+        let roundtrip: Vec<i16> = convert(&original).into_iter().map(f32_to_i16).collect();
+        assert_eq!(roundtrip, original);
+    }
+
+    #[test]
+    fn f32_to_i16_clips() {
+        assert_eq!(f32_to_i16(1.5), i16::MAX);
+        assert_eq!(f32_to_i16(1.0), i16::MAX);
+        assert_eq!(f32_to_i16(-1.0), i16::MIN);
+        assert_eq!(f32_to_i16(-2.0), i16::MIN);
+        assert_eq!(f32_to_i16(0.0), 0);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::audio_config::{self, ConfigOverrides};
+use crate::device_enumerator;
+use crate::device_selector;
+use crate::level_meter::InputLevels;
+use crate::transcriber::{Input, SourceMeter, Transcriber, TranscriptEvent};
+use cpal::Host;
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+/// How often lost inputs are checked on while no requests arrive
+const RECOVERY_INTERVAL: Duration = Duration::from_millis(200);
+/// How often the host's default input is checked with `follow_default`
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Commands that can be issued to a running live session, from stdin or the
+/// control socket.
+pub enum SessionCommand {
+    Pause,
+    Resume,
+    TogglePause,
+    /// Open (`true`) or close the push-to-talk gate
+    Talk(bool),
+    ToggleTalk,
+    Stop,
+    Status,
+    /// Switch to the input device with this ID, or whose name matches it
+    SwitchDevice(String),
+    StartRecording(String),
+    StopRecording,
+    Subscribe(Sender<TranscriptEvent>),
+}
+
+pub type Reply = Result<Value, String>;
+
+pub struct ControlRequest {
+    pub command: SessionCommand,
+    pub reply: Sender<Reply>,
+}
+
+impl ControlRequest {
+    pub fn new(command: SessionCommand) -> (Self, Receiver<Reply>) {
+        let (reply, rx) = mpsc::channel();
+        (Self { command, reply }, rx)
+    }
+}
+
+#[derive(Serialize)]
+struct SessionStatus {
+    state: &'static str,
+    host: String,
+    #[serde(flatten)]
+    input: InputStatus,
+    /// Inputs after the first, when several are transcribed together
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    extra_inputs: Vec<InputStatus>,
+    recording: Option<String>,
+    /// Inputs that are gone and being reconnected
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reconnecting: Vec<String>,
+    /// Raw capture levels of each input
+    levels: Vec<InputLevels>,
+    /// Levels after each pipeline's DSP stage, when enabled
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    dsp: Vec<SourceMeter>,
+    push_to_talk: bool,
+    talking: bool,
+    /// How far transcription is behind live audio
+    backlog_secs: f64,
+    /// The ONNX Runtime execution provider the model runs on
+    execution_provider: &'static str,
+    uptime_secs: f64,
+}
+
+#[derive(Serialize)]
+struct InputStatus {
+    device: String,
+    device_id: Option<String>,
+    channels: u16,
+    sample_rate: u32,
+    sample_format: String,
+}
+
+impl InputStatus {
+    fn new(input: &Input) -> Self {
+        Self {
+            device: device_enumerator::device_name(&input.device),
+            device_id: input.device.id().ok().map(|id| id.to_string()),
+            channels: input.config.channels,
+            sample_rate: input.config.sample_rate,
+            sample_format: format!("{:?}", input.sample_format),
+        }
+    }
+}
+
+/// Owns the live transcriber and applies control commands to it. All access
+/// to the cpal stream happens on the thread that calls `run`.
+pub struct Session {
+    host: Host,
+    /// Applied again when switching devices
+    config_overrides: ConfigOverrides,
+    transcriber: Transcriber,
+    recording: Option<String>,
+    started: Instant,
+    /// Skip the notices printed below the transcript, for front ends that
+    /// report replies themselves
+    quiet: bool,
+    /// Move to the host's default input whenever it changes
+    follow_default: bool,
+    /// ID of the default input when it was last checked
+    default_id: Option<String>,
+    default_checked: Instant,
+}
+
+impl Session {
+    pub fn new(host: Host, config_overrides: ConfigOverrides, transcriber: Transcriber) -> Self {
+        Self {
+            host,
+            config_overrides,
+            transcriber,
+            recording: None,
+            started: Instant::now(),
+            quiet: false,
+            follow_default: false,
+            default_id: None,
+            default_checked: Instant::now(),
+        }
+    }
+
+    pub fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    /// With `follow` set, swap capture over whenever the host's default
+    /// input device changes.
+    pub fn follow_default(mut self, follow: bool) -> Self {
+        self.follow_default = follow;
+        self.default_id = default_input_id(&self.host);
+        self
+    }
+
+    /// Handle requests until a `Stop` arrives or every sender hangs up, then
+    /// shut the transcriber down. Inputs that die are reconnected meanwhile.
+    pub fn run(mut self, requests: Receiver<ControlRequest>) {
+        loop {
+            match requests.recv_timeout(RECOVERY_INTERVAL) {
+                Ok(request) => {
+                    let stop = matches!(request.command, SessionCommand::Stop);
+                    let reply = self.handle(request.command);
+                    let _ = request.reply.send(reply);
+                    if stop {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            self.recover();
+            if self.follow_default {
+                self.check_default();
+            }
+        }
+
+        self.transcriber.stop();
+    }
+
+    fn recover(&mut self) {
+        for change in self.transcriber.recover(&self.host, &self.config_overrides) {
+            self.notice(&format!("[{}]", change));
+        }
+        for warning in self.transcriber.backlog_warnings() {
+            self.notice(&format!("[{}]", warning));
+        }
+        // A replacement input in a different format ends the recording
+        if !self.transcriber.is_recording()
+            && let Some(filename) = self.recording.take()
+        {
+            self.notice(&format!(
+                "Recording saved to {} (the new input records in a different format)",
+                filename
+            ));
+        }
+    }
+
+    fn handle(&mut self, command: SessionCommand) -> Reply {
+        match command {
+            SessionCommand::Pause => {
+                self.set_paused(true)?;
+                Ok(self.status())
+            }
+            SessionCommand::Resume => {
+                self.set_paused(false)?;
+                Ok(self.status())
+            }
+            SessionCommand::TogglePause => {
+                self.set_paused(!self.transcriber.is_paused())?;
+                Ok(self.status())
+            }
+            SessionCommand::Talk(talking) => {
+                self.transcriber
+                    .set_talking(talking)
+                    .map_err(|e| e.to_string())?;
+                Ok(self.status())
+            }
+            SessionCommand::ToggleTalk => {
+                self.transcriber
+                    .set_talking(!self.transcriber.is_talking())
+                    .map_err(|e| e.to_string())?;
+                Ok(self.status())
+            }
+            SessionCommand::Stop | SessionCommand::Status => Ok(self.status()),
+            SessionCommand::SwitchDevice(query) => {
+                self.switch_device(&query)?;
+                Ok(self.status())
+            }
+            SessionCommand::StartRecording(filename) => {
+                self.transcriber
+                    .start_recording(&filename)
+                    .map_err(|e| e.to_string())?;
+                self.notice(&format!("Recording to {}", filename));
+                self.recording = Some(filename);
+                Ok(self.status())
+            }
+            SessionCommand::StopRecording => {
+                self.transcriber
+                    .stop_recording()
+                    .map_err(|e| e.to_string())?;
+                if let Some(filename) = self.recording.take() {
+                    self.notice(&format!("Recording saved to {}", filename));
+                }
+                Ok(self.status())
+            }
+            SessionCommand::Subscribe(tx) => {
+                self.transcriber.subscribe(tx);
+                Ok(self.status())
+            }
+        }
+    }
+
+    fn set_paused(&self, paused: bool) -> Result<(), String> {
+        if paused == self.transcriber.is_paused() {
+            return Ok(());
+        }
+        if paused {
+            self.transcriber.pause().map_err(|e| e.to_string())?;
+            self.notice("[paused]");
+        } else {
+            self.transcriber.resume().map_err(|e| e.to_string())?;
+            self.notice("[resumed]");
+        }
+        Ok(())
+    }
+
+    /// Move to the default input if it has changed since the last check.
+    fn check_default(&mut self) {
+        if self.default_checked.elapsed() < FOLLOW_INTERVAL {
+            return;
+        }
+        self.default_checked = Instant::now();
+        let id = default_input_id(&self.host);
+        if id.is_none() || id == self.default_id {
+            return;
+        }
+        self.default_id = id;
+        let Some(device) = self.host.default_input_device() else {
+            return;
+        };
+
+        let name = device_enumerator::device_name(&device);
+        let result = audio_config::select_input_config(&device, &self.config_overrides).and_then(
+            |selected| {
+                let input = Input {
+                    device,
+                    config: selected.config,
+                    sample_format: selected.sample_format,
+                };
+                let notice = format!(
+                    "[Default input changed; switched to {} ({} channels, {} Hz, {:?})]",
+                    name, input.config.channels, input.config.sample_rate, input.sample_format
+                );
+                self.transcriber.follow_device(input)?;
+                Ok(notice)
+            },
+        );
+        match result {
+            Ok(notice) => self.notice(&notice),
+            Err(e) => self.notice(&format!(
+                "[Default input changed to {}, but switching failed: {}]",
+                name, e
+            )),
+        }
+    }
+
+    fn switch_device(&mut self, query: &str) -> Result<(), String> {
+        let device =
+            device_selector::find_by_id_or_name(&self.host, query).map_err(|e| e.to_string())?;
+        let selected = audio_config::select_input_config(&device, &self.config_overrides)
+            .map_err(|e| e.to_string())?;
+        let sample_format = selected.sample_format;
+
+        let input = Input {
+            device,
+            config: selected.config,
+            sample_format,
+        };
+        let name = device_enumerator::device_name(&input.device);
+        let (channels, sample_rate) = (input.config.channels, input.config.sample_rate);
+
+        self.transcriber
+            .switch_device(input)
+            .map_err(|e| e.to_string())?;
+
+        self.notice(&format!(
+            "Switched to input device: {} ({} channels, {} Hz, {:?})",
+            name, channels, sample_rate, sample_format
+        ));
+        Ok(())
+    }
+
+    /// Print a status line below the running transcript. Uses "\r\n" so it
+    /// renders correctly while the terminal is in raw mode for keyboard
+    /// shortcuts.
+    fn notice(&self, message: &str) {
+        if self.quiet {
+            return;
+        }
+        print!("\r\n{}\r\n", message);
+        let _ = io::stdout().flush();
+    }
+
+    fn status(&self) -> Value {
+        let mut inputs = self.transcriber.inputs().map(InputStatus::new);
+        let reconnecting = self.transcriber.lost_inputs();
+        let status = SessionStatus {
+            state: if self.transcriber.is_paused() {
+                "paused"
+            } else if !reconnecting.is_empty() {
+                "reconnecting"
+            } else {
+                "listening"
+            },
+            host: self.host.id().to_string(),
+            input: inputs.next().expect("a session has at least one input"),
+            extra_inputs: inputs.collect(),
+            recording: self.recording.clone(),
+            reconnecting,
+            levels: self.transcriber.input_levels(),
+            dsp: self.transcriber.dsp_meters(),
+            push_to_talk: self.transcriber.push_to_talk(),
+            talking: self.transcriber.is_talking(),
+            backlog_secs: self.transcriber.backlog_secs(),
+            execution_provider: self.transcriber.provider(),
+            uptime_secs: self.started.elapsed().as_secs_f64(),
+        };
+        serde_json::to_value(status).unwrap_or(Value::Null)
+    }
+}
+
+fn default_input_id(host: &Host) -> Option<String> {
+    let device = host.default_input_device()?;
+    device.id().ok().map(|id| id.to_string())
+}
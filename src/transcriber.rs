@@ -1,263 +1,746 @@
+use crate::audio_config::ConfigOverrides;
+use crate::denoise::{ComparisonRecording, Denoiser};
+use crate::device_enumerator::device_name;
+use crate::downmix::{ChannelMix, Downmixer};
+use crate::dsp::{Dsp, DspMeter, DspOptions, SharedMeter};
+use crate::engine::{Engine, Token, Word};
+use crate::error::RustscriberError;
+use crate::execution::Runtime;
+use crate::level_meter::{self, InputLevels, LevelMeter, SharedLevels};
+use crate::merge;
+use crate::pipeline::{
+    ASR_SAMPLE_RATE, BacklogWarning, CaptureBuffer, Controls, Overload, Pipeline, SharedBuffer,
+    Stages,
+};
+use crate::push_to_talk::Gate;
+use crate::reconnect::{Reconnect, Recovery, StreamHealth};
+use crate::sample_convert;
+use crate::wav_recorder::{self, WavWriterHandle};
 use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{Device, SampleFormat, Stream, StreamConfig};
-use parakeet_rs::Nemotron;
-use rubato::{FftFixedIn, Resampler};
-use std::collections::VecDeque;
-use std::io::Write;
-use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
+use cpal::{Device, Host, SampleFormat, Stream, StreamConfig};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Sender};
 use std::sync::{Arc, Mutex};
-use std::thread::{self, JoinHandle};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-const ASR_SAMPLE_RATE: usize = 16000;
-/// 560ms at 16kHz — required chunk size for Nemotron
-const NEMOTRON_CHUNK_SIZE: usize = 8960;
+/// Transcript output delivered to subscribers. Times are seconds of audio
+/// since the session started. `source` labels the channel the text came
+/// from when several are transcribed separately.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TranscriptEvent {
+    Text {
+        text: String,
+        start: f64,
+        end: f64,
+        /// The decoded subword tokens, with their timings
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        tokens: Vec<Token>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+    },
+    /// Audio between `start` and `end` was not transcribed. A gap from a
+    /// lost device is reported by each of its sources.
+    Gap {
+        start: f64,
+        end: f64,
+        reason: GapReason,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+    },
+    /// The complete transcript of one push-to-talk press.
+    Final {
+        text: String,
+        start: f64,
+        end: f64,
+        /// The press's tokens joined into words
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        words: Vec<Word>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source: Option<String>,
+    },
+}
 
-pub struct Transcriber {
+/// Why a `TranscriptEvent::Gap` wasn't transcribed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapReason {
+    Paused,
+    /// The input device went away until it (or a replacement) was reopened
+    Disconnected,
+    /// The model fell behind live audio and the oldest audio was dropped
+    /// (`OverloadPolicy::Drop`)
+    Overloaded,
+}
+
+impl TranscriptEvent {
+    pub fn start(&self) -> f64 {
+        match *self {
+            TranscriptEvent::Text { start, .. }
+            | TranscriptEvent::Gap { start, .. }
+            | TranscriptEvent::Final { start, .. } => start,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TranscriberOptions {
+    /// Also stop the cpal stream while paused, releasing the device. By
+    /// default the stream keeps running and its audio is discarded, which
+    /// resumes instantly and keeps any active recording going.
+    pub pause_stream: bool,
+    /// Only feed audio to the model while talking is signalled (see
+    /// `set_talking`), emitting a `TranscriptEvent::Final` per press.
+    pub push_to_talk: bool,
+    /// Audio kept from just before each press so the first syllable isn't cut.
+    pub pre_roll: Duration,
+    /// How the device's channels are reduced to mono for the model.
+    pub channel_mix: ChannelMix,
+    /// Transcribe every channel separately, each with its own model, instead
+    /// of downmixing. `channel_mix` is ignored.
+    pub split_channels: bool,
+    /// Labels for split channels, in channel order (default "ch1", "ch2", ...)
+    pub channel_names: Vec<String>,
+    /// Level conditioning applied to each pipeline before resampling
+    pub dsp: DspOptions,
+    /// Spectral noise suppression before the level conditioning, limited to
+    /// this many dB of attenuation; off when `None`
+    pub denoise: Option<f32>,
+    /// Record each pipeline's audio before and after noise suppression to
+    /// `<stem>-raw.wav` and `<stem>-denoised.wav`
+    pub denoise_record: Option<PathBuf>,
+    /// What live pipelines do when the model falls behind
+    pub overload: Overload,
+    /// Where ONNX Runtime runs the model
+    pub runtime: Runtime,
+    /// Don't print the transcript to stdout; it still goes to subscribers
+    pub quiet: bool,
+}
+
+/// The latest DSP levels of one pipeline.
+#[derive(Serialize)]
+pub struct SourceMeter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(flatten)]
+    pub meter: DspMeter,
+}
+
+pub type Subscribers = Arc<Mutex<Vec<Sender<TranscriptEvent>>>>;
+type CaptureSink = Box<dyn FnMut(&[f32]) + Send>;
+
+/// An input device and the stream config chosen for it.
+#[derive(Clone)]
+pub struct Input {
+    pub device: Device,
+    pub config: StreamConfig,
+    pub sample_format: SampleFormat,
+}
+
+/// Capture from one input, feeding one or more pipelines.
+struct Capture {
+    input: Input,
     stream: Option<Stream>,
-    processing_thread: Option<JoinHandle<()>>,
-    stop_flag: Arc<AtomicBool>,
+    /// One per pipeline: a single downmixed buffer, or one per split channel
+    buffers: Vec<SharedBuffer>,
+    /// Input levels, measured in the capture callback
+    levels: SharedLevels,
+    health: StreamHealth,
+    /// Set while the device is gone
+    outage: Option<Reconnect>,
+}
+
+pub struct Transcriber {
+    captures: Vec<Capture>,
+    pipelines: Vec<JoinHandle<()>>,
+    merger: Option<JoinHandle<()>>,
+    controls: Controls,
+    /// DSP levels per pipeline, when DSP is enabled
+    meters: Vec<(Option<String>, SharedMeter)>,
+    /// Recording of the first input
+    recording: WavWriterHandle,
+    subscribers: Subscribers,
+    options: TranscriberOptions,
+    /// The execution provider the models registered with
+    provider: &'static str,
 }
 
 impl Transcriber {
+    /// Set up capture from each of `inputs`, with separate pipelines (and
+    /// model instances) per input and, with `split_channels`, per channel.
     pub fn new(
         model_path: &Path,
-        device: &Device,
-        config: &StreamConfig,
-        sample_format: SampleFormat,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let input_rate = config.sample_rate as usize;
-        let channels = config.channels as usize;
-        let needs_resample = input_rate != ASR_SAMPLE_RATE;
-
-        println!(
-            "ASR pipeline: {}Hz {}ch → 16kHz mono (resample: {})",
-            input_rate, channels, needs_resample
-        );
+        inputs: Vec<Input>,
+        options: TranscriberOptions,
+    ) -> Result<Self, RustscriberError> {
+        let recording: WavWriterHandle = Arc::new(Mutex::new(None));
+        let subscribers: Subscribers = Arc::new(Mutex::new(Vec::new()));
+        let controls = Controls::default();
+        let (events_tx, events_rx) = mpsc::channel();
+        let pre_roll_len = (options.pre_roll.as_secs_f64() * ASR_SAMPLE_RATE as f64) as usize;
+        let multiple_inputs = inputs.len() > 1;
 
-        println!("Loading Nemotron model from {}...", model_path.display());
-        let model = Nemotron::from_pretrained(model_path, None)?;
-        println!("Model loaded.");
+        let mut captures = Vec::new();
+        let mut pipelines = Vec::new();
+        let mut meters = Vec::new();
+        let mut provider = "cpu";
+        for (index, input) in inputs.into_iter().enumerate() {
+            let labels = source_labels(&input, &options, multiple_inputs);
+            let input_rate = input.config.sample_rate as usize;
+            let buffers: Vec<SharedBuffer> = labels
+                .iter()
+                .map(|_| CaptureBuffer::shared(input_rate))
+                .collect();
 
-        let buffer: Arc<Mutex<VecDeque<f32>>> = Arc::new(Mutex::new(VecDeque::new()));
-        let buffer_for_callback = Arc::clone(&buffer);
+            // Only the first input is recorded; the others get a handle
+            // that is never filled.
+            let recording = match index {
+                0 => Arc::clone(&recording),
+                _ => Arc::new(Mutex::new(None)),
+            };
+            let sink = capture_sink(&options, &input.config, &buffers)?;
+            let levels = level_meter::shared_levels(device_name(&input.device));
+            let health = StreamHealth::default();
+            let stream = Self::build_stream(&input, &health, &levels, sink, recording)?;
 
-        let stream = Self::build_stream(
-            device,
-            config,
-            sample_format,
-            channels,
-            buffer_for_callback,
-        )?;
+            for (label, buffer) in labels.into_iter().zip(&buffers) {
+                match label {
+                    Some(ref label) => println!(
+                        "Loading Nemotron model for {} from {}...",
+                        label,
+                        model_path.display()
+                    ),
+                    None => println!("Loading Nemotron model from {}...", model_path.display()),
+                }
+                let model = Engine::load(model_path, &options.runtime)?;
+                provider = model.provider();
+                let mut stages = build_stages(&options, label.as_deref(), input_rate)?;
+                stages.gate = options.push_to_talk.then(|| Gate::new(pre_roll_len));
+                if let Some(ref dsp) = stages.dsp {
+                    meters.push((label.clone(), dsp.meter()));
+                }
+                pipelines.push(
+                    Pipeline::new(
+                        pipelines.len(),
+                        label,
+                        model,
+                        Arc::clone(buffer),
+                        stages,
+                        controls.clone(),
+                        events_tx.clone(),
+                    )
+                    .overload(options.overload),
+                );
+            }
 
-        let stop_flag = Arc::new(AtomicBool::new(false));
-        let stop_flag_thread = Arc::clone(&stop_flag);
+            captures.push(Capture {
+                input,
+                stream: Some(stream),
+                buffers,
+                levels,
+                health,
+                outage: None,
+            });
+        }
+        println!("Model loaded ({}).", options.runtime);
+        if let Some(warning) = options.runtime.fallback_warning(provider) {
+            eprintln!("{}", warning);
+        }
 
-        let processing_thread = thread::spawn(move || {
-            Self::processing_loop(model, buffer, stop_flag_thread, input_rate, needs_resample);
-        });
+        let merger = merge::spawn_merger(
+            pipelines.len(),
+            events_rx,
+            Arc::clone(&subscribers),
+            !options.quiet,
+        );
+        let pipelines = pipelines.into_iter().map(Pipeline::spawn).collect();
 
         Ok(Self {
-            stream: Some(stream),
-            processing_thread: Some(processing_thread),
-            stop_flag,
+            captures,
+            pipelines,
+            merger: Some(merger),
+            controls,
+            meters,
+            recording,
+            subscribers,
+            options,
+            provider,
         })
     }
 
-    pub fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if let Some(ref stream) = self.stream {
+    pub fn start(&self) -> Result<(), RustscriberError> {
+        // Pipelines measure their first audio from here, which lines up
+        // inputs whose streams take different times to start.
+        let _ = self.controls.started.set(Instant::now());
+        for stream in self.streams() {
             stream.play()?;
         }
         Ok(())
     }
 
     pub fn stop(mut self) {
-        self.stop_flag.store(true, Ordering::Relaxed);
-        // Drop the stream to stop audio capture
-        self.stream.take();
-        if let Some(handle) = self.processing_thread.take() {
+        self.controls.stop.store(true, Ordering::Relaxed);
+        // Drop the streams to stop audio capture
+        for capture in &mut self.captures {
+            capture.stream.take();
+        }
+        if let Err(e) = self.stop_recording() {
+            eprintln!("Failed to finalize recording: {}", e);
+        }
+        for handle in self.pipelines.drain(..) {
+            let _ = handle.join();
+        }
+        // Exits once every pipeline has dropped its sender
+        if let Some(handle) = self.merger.take() {
             let _ = handle.join();
         }
     }
 
-    fn build_stream(
-        device: &Device,
-        config: &StreamConfig,
-        sample_format: SampleFormat,
-        channels: usize,
-        buffer: Arc<Mutex<VecDeque<f32>>>,
-    ) -> Result<Stream, Box<dyn std::error::Error>> {
-        let err_fn = |err| eprintln!("Stream error: {}", err);
-
-        let stream = match sample_format {
-            SampleFormat::F32 => {
-                let buf = Arc::clone(&buffer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                        push_mono(data, channels, &buf);
-                    },
-                    err_fn,
-                    None,
-                )?
-            }
-            SampleFormat::I16 => {
-                let buf = Arc::clone(&buffer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[i16], _: &cpal::InputCallbackInfo| {
-                        let floats: Vec<f32> =
-                            data.iter().map(|&s| s as f32 / 32768.0).collect();
-                        push_mono(&floats, channels, &buf);
-                    },
-                    err_fn,
-                    None,
-                )?
-            }
-            SampleFormat::U8 => {
-                let buf = Arc::clone(&buffer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[u8], _: &cpal::InputCallbackInfo| {
-                        let floats: Vec<f32> =
-                            data.iter().map(|&s| (s as f32 - 128.0) / 128.0).collect();
-                        push_mono(&floats, channels, &buf);
-                    },
-                    err_fn,
-                    None,
-                )?
+    /// Stop feeding audio to the model. The model and decoder state stay
+    /// loaded, so resuming continues the same transcript; the skipped span is
+    /// reported as a `TranscriptEvent::Gap`.
+    pub fn pause(&self) -> Result<(), RustscriberError> {
+        if self.options.pause_stream {
+            for stream in self.streams() {
+                stream.pause()?;
             }
-            SampleFormat::I32 => {
-                let buf = Arc::clone(&buffer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[i32], _: &cpal::InputCallbackInfo| {
-                        let floats: Vec<f32> =
-                            data.iter().map(|&s| s as f32 / 2147483648.0).collect();
-                        push_mono(&floats, channels, &buf);
-                    },
-                    err_fn,
-                    None,
-                )?
-            }
-            _ => return Err(format!("Unsupported sample format: {:?}", sample_format).into()),
-        };
+        }
+        self.controls.paused.store(true, Ordering::Relaxed);
+        Ok(())
+    }
 
-        Ok(stream)
-    }
-
-    fn processing_loop(
-        mut model: Nemotron,
-        buffer: Arc<Mutex<VecDeque<f32>>>,
-        stop_flag: Arc<AtomicBool>,
-        input_rate: usize,
-        needs_resample: bool,
-    ) {
-        // Set up resampler if needed
-        let mut resampler: Option<FftFixedIn<f32>> = if needs_resample {
-            // Use a chunk size that divides nicely into our workflow.
-            // 1024 input frames is a reasonable FFT size.
-            let r = FftFixedIn::<f32>::new(input_rate, ASR_SAMPLE_RATE, 1024, 1, 1);
-            match r {
-                Ok(r) => Some(r),
-                Err(e) => {
-                    eprintln!("Failed to create resampler: {}", e);
-                    return;
-                }
+    pub fn resume(&self) -> Result<(), RustscriberError> {
+        if self.options.pause_stream {
+            for stream in self.streams() {
+                stream.play()?;
             }
-        } else {
-            None
-        };
+        }
+        self.controls.paused.store(false, Ordering::Relaxed);
+        Ok(())
+    }
 
-        // Buffer to accumulate 16kHz samples until we have a full Nemotron chunk
-        let mut asr_buffer: Vec<f32> = Vec::with_capacity(NEMOTRON_CHUNK_SIZE * 2);
+    pub fn is_paused(&self) -> bool {
+        self.controls.paused.load(Ordering::Relaxed)
+    }
 
-        loop {
-            if stop_flag.load(Ordering::Relaxed) {
-                break;
-            }
+    /// Take the backlog warnings pipelines have raised since the last call.
+    pub fn backlog_warnings(&self) -> Vec<BacklogWarning> {
+        std::mem::take(&mut *self.controls.backlog_warnings.lock().unwrap())
+    }
 
-            // Drain available samples from the shared buffer
-            let drained: Vec<f32> = {
-                let mut guard = buffer.lock().unwrap();
-                guard.drain(..).collect()
-            };
+    /// The execution provider the models run on, which can be the CPU even
+    /// when the options ask for another.
+    pub fn provider(&self) -> &'static str {
+        self.provider
+    }
 
-            if drained.is_empty() {
-                thread::sleep(std::time::Duration::from_millis(10));
-                continue;
-            }
+    pub fn push_to_talk(&self) -> bool {
+        self.options.push_to_talk
+    }
 
-            // Resample or pass through
-            let samples_16k = if let Some(ref mut resampler) = resampler {
-                // rubato expects non-interleaved channel data: &[&[f32]]
-                // We already have mono, so it's one channel.
-                let chunk_size = resampler.input_frames_next();
-                let mut resampled = Vec::new();
-
-                // Feed drained samples through the resampler in chunk_size batches
-                let mut offset = 0;
-                while offset + chunk_size <= drained.len() {
-                    let input_chunk = &drained[offset..offset + chunk_size];
-                    match resampler.process(&[input_chunk], None) {
-                        Ok(output) => {
-                            if !output.is_empty() {
-                                resampled.extend_from_slice(&output[0]);
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Resampler error: {}", e);
-                        }
-                    }
-                    offset += chunk_size;
-                }
+    /// Open or close the push-to-talk gate. Closing it finishes the current
+    /// utterance.
+    pub fn set_talking(&self, talking: bool) -> Result<(), RustscriberError> {
+        if !self.options.push_to_talk {
+            return Err(RustscriberError::Failed(
+                "Push-to-talk is not enabled (start with --push-to-talk)".to_string(),
+            ));
+        }
+        self.controls.talking.store(talking, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn is_talking(&self) -> bool {
+        self.controls.talking.load(Ordering::Relaxed)
+    }
+
+    /// DSP levels for each pipeline; empty unless a DSP stage is enabled.
+    pub fn dsp_meters(&self) -> Vec<SourceMeter> {
+        self.meters
+            .iter()
+            .map(|(source, meter)| SourceMeter {
+                source: source.clone(),
+                meter: *meter.lock().unwrap(),
+            })
+            .collect()
+    }
+
+    /// How far the slowest pipeline is behind live audio, in seconds.
+    pub fn backlog_secs(&self) -> f64 {
+        self.captures
+            .iter()
+            .flat_map(|capture| &capture.buffers)
+            .map(|buffer| buffer.lock().unwrap().backlog_secs())
+            .fold(0.0, f64::max)
+    }
+
+    /// The latest levels of each input, in the order they were given.
+    pub fn input_levels(&self) -> Vec<InputLevels> {
+        self.captures
+            .iter()
+            .map(|capture| capture.levels.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Live handles to each input's levels, e.g. for a `LevelMonitor`. They
+    /// stay valid across device switches.
+    pub fn shared_levels(&self) -> Vec<SharedLevels> {
+        self.captures
+            .iter()
+            .map(|capture| Arc::clone(&capture.levels))
+            .collect()
+    }
+
+    /// The inputs being captured, in the order they were given.
+    pub fn inputs(&self) -> impl Iterator<Item = &Input> {
+        self.captures.iter().map(|capture| &capture.input)
+    }
+
+    fn streams(&self) -> impl Iterator<Item = &Stream> {
+        self.captures
+            .iter()
+            .filter_map(|capture| capture.stream.as_ref())
+    }
+
+    /// Names of the inputs that are gone and being reconnected.
+    pub fn lost_inputs(&self) -> Vec<String> {
+        self.captures
+            .iter()
+            .filter_map(|capture| capture.outage.as_ref())
+            .map(Reconnect::device_name)
+            .collect()
+    }
+
+    /// Notice inputs whose streams have died, and try to reopen lost ones
+    /// (or fall back to the default device) where an attempt is due. The
+    /// models stay loaded; the time an input was gone is reported as a
+    /// `GapReason::Disconnected` gap and recorded as silence. Call this
+    /// regularly from the thread that owns the transcriber.
+    pub fn recover(&mut self, host: &Host, overrides: &ConfigOverrides) -> Vec<Recovery> {
+        let in_use: Vec<String> = self
+            .inputs()
+            .filter_map(|input| input.device.id().ok())
+            .map(|id| id.to_string())
+            .collect();
+        let mut changes = Vec::new();
 
-                // Put leftover samples back into the shared buffer so they're
-                // picked up next iteration
-                if offset < drained.len() {
-                    let mut guard = buffer.lock().unwrap();
-                    for &s in &drained[offset..] {
-                        guard.push_front(s);
+        for index in 0..self.captures.len() {
+            let capture = &mut self.captures[index];
+            let Some(mut outage) = capture.outage.take() else {
+                if let Some(since) = capture.health.lost_since() {
+                    capture.stream.take();
+                    capture.outage = Some(Reconnect::new(capture.input.clone(), since));
+                    for buffer in &capture.buffers {
+                        buffer.lock().unwrap().lost_since = Some(since);
                     }
+                    changes.push(Recovery::Lost {
+                        device: device_name(&capture.input.device),
+                    });
                 }
-
-                resampled
-            } else {
-                drained
+                continue;
             };
 
-            asr_buffer.extend_from_slice(&samples_16k);
-
-            // Feed full chunks to Nemotron
-            while asr_buffer.len() >= NEMOTRON_CHUNK_SIZE {
-                let chunk: Vec<f32> = asr_buffer.drain(..NEMOTRON_CHUNK_SIZE).collect();
-                match model.transcribe_chunk(&chunk) {
-                    Ok(text) => {
-                        if !text.is_empty() {
-                            print!("{}", text);
-                            let _ = std::io::stdout().flush();
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("\nASR error: {}", e);
+            let result = outage
+                .attempt(host, overrides, &in_use)
+                .and_then(|replacement| match replacement {
+                    Some(replacement) => {
+                        let gap = outage.since().elapsed();
+                        self.replace_input(index, replacement.input, Some(gap))?;
+                        Ok(Some((gap, replacement.fallback)))
                     }
+                    None => Ok(None),
+                });
+            match result {
+                Ok(Some((after, fallback))) => changes.push(Recovery::Reconnected {
+                    lost: outage.device_name(),
+                    device: device_name(&self.captures[index].input.device),
+                    after,
+                    fallback,
+                }),
+                Ok(None) => self.captures[index].outage = Some(outage),
+                Err(error) => {
+                    changes.push(Recovery::Failed {
+                        device: outage.device_name(),
+                        error,
+                    });
+                    self.captures[index].outage = Some(outage);
                 }
             }
         }
+        changes
+    }
+
+    /// Swap capture over to a different input device without reloading the model.
+    pub fn switch_device(&mut self, input: Input) -> Result<(), RustscriberError> {
+        if self.captures.len() > 1 {
+            return Err(RustscriberError::Failed(
+                "Switching devices is only supported with a single input".to_string(),
+            ));
+        }
+        if self.is_recording() {
+            return Err(RustscriberError::Failed(
+                "Stop recording before switching devices".to_string(),
+            ));
+        }
+
+        self.replace_input(0, input, None)
+    }
+
+    /// Move capture to `input` because it has become the default device.
+    /// Unlike `switch_device`, an active recording carries on, unless the
+    /// new device records in a different format, which finalizes it.
+    pub fn follow_device(&mut self, input: Input) -> Result<(), RustscriberError> {
+        if self.captures.len() > 1 {
+            return Err(RustscriberError::Failed(
+                "Following the default device is only supported with a single input".to_string(),
+            ));
+        }
+        let gap = self.captures[0]
+            .outage
+            .as_ref()
+            .map_or(Duration::ZERO, |outage| outage.since().elapsed());
+        self.replace_input(0, input, Some(gap))
+    }
+
+    /// Move the capture at `index` onto `input`. With `gap` (how long the old
+    /// device had been gone, possibly nothing), an active recording carries
+    /// on across the swap.
+    fn replace_input(
+        &mut self,
+        index: usize,
+        input: Input,
+        gap: Option<Duration>,
+    ) -> Result<(), RustscriberError> {
+        let keep_paused = self.options.pause_stream && self.is_paused();
+        // Only the first input is recorded
+        let recording = match index {
+            0 => Arc::clone(&self.recording),
+            _ => Arc::new(Mutex::new(None)),
+        };
+        let capture = &mut self.captures[index];
+        let sink = capture_sink(&self.options, &input.config, &capture.buffers)?;
+        let health = StreamHealth::default();
+        let stream = Self::build_stream(
+            &input,
+            &health,
+            &capture.levels,
+            sink,
+            Arc::clone(&recording),
+        )?;
+
+        // Drop the old stream before resetting the buffer so none of its
+        // samples land after the rate change.
+        capture.stream.take();
+        if let Some(gap) = gap
+            && index == 0
+        {
+            wav_recorder::bridge_gap(&recording, &input.config, gap)?;
+        }
+        for buffer in &capture.buffers {
+            let mut guard = buffer.lock().unwrap();
+            guard.samples.clear();
+            guard.input_rate = input.config.sample_rate as usize;
+            guard.generation += 1;
+            guard.lost += gap.map_or(0.0, |gap| gap.as_secs_f64());
+            guard.lost_since = None;
+        }
+
+        if !keep_paused {
+            stream.play()?;
+        }
+        capture.stream = Some(stream);
+        capture.health = health;
+        capture.outage = None;
+        capture.levels.lock().unwrap().device = device_name(&input.device);
+        capture.input = input;
+        Ok(())
+    }
+
+    /// Start writing the captured audio (all channels, at the device rate) of
+    /// the first input to a WAV file.
+    pub fn start_recording(&self, filename: &str) -> Result<(), RustscriberError> {
+        let mut guard = self.recording.lock().unwrap();
+        if guard.is_some() {
+            return Err(RustscriberError::Failed("Already recording".to_string()));
+        }
+        let config = &self.captures[0].input.config;
+        *guard = Some(wav_recorder::create_writer(filename, config)?);
+        Ok(())
+    }
+
+    /// Finalize the active recording. Returns `false` if nothing was being recorded.
+    pub fn stop_recording(&self) -> Result<bool, RustscriberError> {
+        let writer = self.recording.lock().unwrap().take();
+        match writer {
+            Some(w) => {
+                wav_recorder::finalize(w)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.lock().unwrap().is_some()
+    }
+
+    /// Deliver transcript events to `tx` as they are produced. The sender is
+    /// dropped once its receiver goes away.
+    pub fn subscribe(&self, tx: Sender<TranscriptEvent>) {
+        self.subscribers.lock().unwrap().push(tx);
+    }
+
+    fn build_stream(
+        input: &Input,
+        health: &StreamHealth,
+        levels: &SharedLevels,
+        mut sink: CaptureSink,
+        recording: WavWriterHandle,
+    ) -> Result<Stream, RustscriberError> {
+        let mut meter = LevelMeter::new(
+            Arc::clone(levels),
+            input.config.sample_rate,
+            input.config.channels,
+        );
+        sample_convert::build_input_stream(
+            &input.device,
+            &input.config,
+            input.sample_format,
+            health,
+            move |data| {
+                meter.process(data);
+                wav_recorder::write_f32(&recording, data);
+                sink(data);
+            },
+        )
     }
 }
 
-/// Downmix interleaved multi-channel audio to mono and push into the shared buffer.
-fn push_mono(data: &[f32], channels: usize, buffer: &Arc<Mutex<VecDeque<f32>>>) {
-    let mono: Vec<f32> = if channels == 1 {
-        data.to_vec()
+/// Transcript labels for the pipelines of one input: `None` for a single
+/// downmixed input, otherwise the device name and/or channel name.
+fn source_labels(
+    input: &Input,
+    options: &TranscriberOptions,
+    multiple_inputs: bool,
+) -> Vec<Option<String>> {
+    let input_rate = input.config.sample_rate as usize;
+    let channels = input.config.channels as usize;
+    let needs_resample = input_rate != ASR_SAMPLE_RATE;
+    let device = multiple_inputs.then(|| device_name(&input.device));
+
+    if options.split_channels {
+        println!(
+            "ASR pipeline: {}Hz {}ch → one 16kHz pipeline per channel (resample: {})",
+            input_rate, channels, needs_resample
+        );
     } else {
-        data.chunks_exact(channels)
-            .map(|frame| frame.iter().sum::<f32>() / channels as f32)
-            .collect()
+        println!(
+            "ASR pipeline: {}Hz {}ch → 16kHz mono via {} (resample: {})",
+            input_rate, channels, options.channel_mix, needs_resample
+        );
+    }
+    pipeline_labels(channels, options, device)
+}
+
+/// Labels for the pipelines of a source with `channels` channels: one per
+/// channel when splitting (prefixed with `device`, if given), otherwise just
+/// `device`.
+pub fn pipeline_labels(
+    channels: usize,
+    options: &TranscriberOptions,
+    device: Option<String>,
+) -> Vec<Option<String>> {
+    if !options.split_channels {
+        return vec![device];
+    }
+    (0..channels)
+        .map(|c| {
+            let channel = options
+                .channel_names
+                .get(c)
+                .cloned()
+                .unwrap_or_else(|| format!("ch{}", c + 1));
+            Some(match device {
+                Some(ref device) => format!("{}/{}", device, channel),
+                None => channel,
+            })
+        })
+        .collect()
+}
+
+/// The noise suppression and level conditioning `options` ask for, for one
+/// pipeline fed at `input_rate`. Push-to-talk is left to the caller.
+pub fn build_stages(
+    options: &TranscriberOptions,
+    label: Option<&str>,
+    input_rate: usize,
+) -> Result<Stages, RustscriberError> {
+    let comparison = match options.denoise_record {
+        Some(ref stem) if options.denoise.is_some() => {
+            Some(ComparisonRecording::create(stem, label, input_rate as u32)?)
+        }
+        _ => None,
     };
+    Ok(Stages {
+        denoise: options
+            .denoise
+            .map(|db| Denoiser::new(db, input_rate, comparison)),
+        dsp: options
+            .dsp
+            .is_enabled()
+            .then(|| Dsp::new(options.dsp.clone(), input_rate)),
+        gate: None,
+    })
+}
+
+/// Route each interleaved callback buffer into the pipelines' capture
+/// buffers: one downmixed signal, or one buffer per channel when splitting.
+fn capture_sink(
+    options: &TranscriberOptions,
+    config: &StreamConfig,
+    buffers: &[SharedBuffer],
+) -> Result<CaptureSink, RustscriberError> {
+    let channels = config.channels as usize;
+
+    if options.split_channels {
+        if channels != buffers.len() {
+            return Err(RustscriberError::Failed(format!(
+                "Device has {} channel(s) but {} channel pipelines are running",
+                channels,
+                buffers.len()
+            )));
+        }
+        let buffers = buffers.to_vec();
+        return Ok(Box::new(move |data| push_split(data, &buffers)));
+    }
+
+    let mut downmixer = Downmixer::new(options.channel_mix.clone(), channels, config.sample_rate)
+        .map_err(RustscriberError::Settings)?;
+    let buffer = Arc::clone(&buffers[0]);
+    Ok(Box::new(move |data| {
+        push_mono(data, &mut downmixer, &buffer)
+    }))
+}
+
+/// Push each channel of interleaved audio into its own buffer.
+fn push_split(data: &[f32], buffers: &[SharedBuffer]) {
+    for (channel, buffer) in buffers.iter().enumerate() {
+        if let Ok(mut guard) = buffer.lock() {
+            guard
+                .samples
+                .extend(data.iter().skip(channel).step_by(buffers.len()));
+        }
+    }
+}
+
+/// Downmix interleaved multi-channel audio to mono and push into the shared buffer.
+fn push_mono(data: &[f32], downmixer: &mut Downmixer, buffer: &SharedBuffer) {
+    let mono = downmixer.process(data);
 
     if let Ok(mut guard) = buffer.lock() {
-        guard.extend(mono.iter());
+        guard.samples.extend(mono.iter());
     }
 }
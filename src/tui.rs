@@ -0,0 +1,719 @@
+use crate::device_selector;
+use crate::level_meter::InputLevels;
+use crate::session::{ControlRequest, Reply, SessionCommand};
+use crate::transcriber::{GapReason, TranscriptEvent};
+use cpal::HostId;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Clear, LineGauge, List, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the session status (levels, device, recording) is refreshed
+const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+/// A pause in speech at least this long starts a new transcript line
+const PARAGRAPH_GAP_SECS: f64 = 1.5;
+/// How long replies to key presses stay in the bottom line
+const MESSAGE_DURATION: Duration = Duration::from_secs(5);
+/// Range covered by the level meters
+const METER_MIN_DB: f32 = -60.0;
+
+pub struct TuiOptions {
+    /// Host whose devices are offered when switching
+    pub host: HostId,
+    pub model: String,
+    pub push_to_talk: bool,
+    /// Where `r` records to and `s` saves the transcript
+    pub recording_dir: PathBuf,
+    /// When the transcriber started, which transcript times count from
+    pub started: Instant,
+}
+
+/// Run the full-screen interface on its own thread. It drives the session
+/// through `requests` like any other front end, and returns once the user
+/// quits or the session ends.
+pub fn spawn(requests: Sender<ControlRequest>, options: TuiOptions) -> JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let mut terminal = ratatui::try_init()?;
+        let result = App::new(requests, options).run(&mut terminal);
+        ratatui::restore();
+        result
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EntryKind {
+    Speech,
+    Gap,
+    Bookmark,
+}
+
+/// One transcript line: a stretch of speech from one source, or a marker.
+struct Entry {
+    kind: EntryKind,
+    start: f64,
+    end: f64,
+    source: Option<String>,
+    text: String,
+    /// Set once a push-to-talk press ends, so the next press starts a line
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Transcript {
+    entries: Vec<Entry>,
+    bookmarks: usize,
+}
+
+impl Transcript {
+    fn push_event(&mut self, event: TranscriptEvent) {
+        match event {
+            TranscriptEvent::Text {
+                text,
+                start,
+                end,
+                source,
+                ..
+            } => {
+                if let Some(last) = self.entries.last_mut()
+                    && last.kind == EntryKind::Speech
+                    && !last.closed
+                    && last.source == source
+                    && start - last.end < PARAGRAPH_GAP_SECS
+                {
+                    last.text.push_str(&text);
+                    last.end = end;
+                    return;
+                }
+                self.entries.push(Entry {
+                    kind: EntryKind::Speech,
+                    start,
+                    end,
+                    source,
+                    text: text.trim_start().to_string(),
+                    closed: false,
+                });
+            }
+            TranscriptEvent::Gap {
+                start,
+                end,
+                reason,
+                source,
+            } => self.entries.push(Entry {
+                kind: EntryKind::Gap,
+                start,
+                end,
+                source,
+                text: match reason {
+                    GapReason::Paused => format!("paused for {:.1} s", end - start),
+                    GapReason::Disconnected => format!("input lost for {:.1} s", end - start),
+                    GapReason::Overloaded => {
+                        format!("{:.1} s dropped to catch up", end - start)
+                    }
+                },
+                closed: true,
+            }),
+            TranscriptEvent::Final { source, .. } => {
+                if let Some(entry) = self
+                    .entries
+                    .iter_mut()
+                    .rev()
+                    .find(|e| e.kind == EntryKind::Speech && e.source == source)
+                {
+                    entry.closed = true;
+                }
+            }
+        }
+    }
+
+    fn bookmark(&mut self, at: f64) -> usize {
+        self.bookmarks += 1;
+        self.entries.push(Entry {
+            kind: EntryKind::Bookmark,
+            start: at,
+            end: at,
+            source: None,
+            text: format!("bookmark {}", self.bookmarks),
+            closed: true,
+        });
+        self.bookmarks
+    }
+
+    /// Plain-text form, one timestamped line per entry.
+    fn to_text(&self) -> String {
+        let mut out = String::new();
+        for entry in &self.entries {
+            let text = match entry.kind {
+                EntryKind::Speech => entry.text.trim().to_string(),
+                EntryKind::Gap | EntryKind::Bookmark => format!("-- {} --", entry.text),
+            };
+            match entry.source {
+                Some(ref source) => out.push_str(&format!(
+                    "[{}] [{}] {}\n",
+                    timestamp(entry.start),
+                    source,
+                    text
+                )),
+                None => out.push_str(&format!("[{}] {}\n", timestamp(entry.start), text)),
+            }
+        }
+        out
+    }
+}
+
+/// Choosing a device to switch to.
+struct DevicePicker {
+    /// Names and IDs, as listed by the host
+    devices: Vec<(String, Option<String>)>,
+    state: ListState,
+}
+
+struct App {
+    requests: Sender<ControlRequest>,
+    options: TuiOptions,
+    transcript: Transcript,
+    /// Latest reply to `SessionCommand::Status`
+    status: Value,
+    /// How far the newest text trails live audio, in seconds
+    latency: Option<f64>,
+    /// Lines scrolled back from the end of the transcript; 0 follows it
+    scroll: usize,
+    picker: Option<DevicePicker>,
+    /// Reply to the last key press, and whether it was an error
+    message: Option<(String, bool, Instant)>,
+}
+
+impl App {
+    fn new(requests: Sender<ControlRequest>, options: TuiOptions) -> Self {
+        Self {
+            requests,
+            options,
+            transcript: Transcript::default(),
+            status: Value::Null,
+            latency: None,
+            scroll: 0,
+            picker: None,
+            message: None,
+        }
+    }
+
+    fn run(mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        let (tx, events) = mpsc::channel();
+        if self.request(SessionCommand::Subscribe(tx)).is_none() {
+            return Ok(());
+        }
+        let mut status_at: Option<Instant> = None;
+
+        loop {
+            for event in events.try_iter() {
+                if let TranscriptEvent::Text { end, .. } = event {
+                    self.latency = Some(self.elapsed() - end);
+                }
+                self.transcript.push_event(event);
+            }
+
+            if status_at.is_none_or(|at| at.elapsed() >= STATUS_INTERVAL) {
+                // No reply means the session has stopped, e.g. over the
+                // control socket
+                match self.request(SessionCommand::Status) {
+                    Some(Ok(status)) => self.status = status,
+                    Some(Err(e)) => self.show(e, true),
+                    None => break,
+                }
+                status_at = Some(Instant::now());
+            }
+
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if event::poll(Duration::from_millis(50))?
+                && let Event::Key(key) = event::read()?
+                && key.kind == KeyEventKind::Press
+                && !self.handle_key(key)
+            {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send a command to the session and wait for its reply; `None` once the
+    /// session has ended.
+    fn request(&self, command: SessionCommand) -> Option<Reply> {
+        let (request, reply) = ControlRequest::new(command);
+        self.requests.send(request).ok()?;
+        reply.recv().ok()
+    }
+
+    /// Run a command, keeping the status it replies with and reporting
+    /// `done` or the error.
+    fn command(&mut self, command: SessionCommand, done: impl Into<String>) {
+        match self.request(command) {
+            Some(Ok(status)) => {
+                self.status = status;
+                self.show(done.into(), false);
+            }
+            Some(Err(e)) => self.show(e, true),
+            None => {}
+        }
+    }
+
+    fn show(&mut self, message: String, error: bool) {
+        self.message = Some((message, error, Instant::now()));
+    }
+
+    fn elapsed(&self) -> f64 {
+        self.options.started.elapsed().as_secs_f64()
+    }
+
+    /// Handle a key press; returns `false` to quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if self.picker.is_some() {
+            self.handle_picker_key(key);
+            return true;
+        }
+
+        let ctrl_c =
+            key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            _ if ctrl_c => return self.quit(),
+            KeyCode::Char('q') | KeyCode::Esc => return self.quit(),
+            KeyCode::Char(' ') if self.options.push_to_talk => {
+                self.command(SessionCommand::ToggleTalk, "");
+                self.message = None;
+            }
+            KeyCode::Char('p') | KeyCode::Char(' ') => {
+                self.command(SessionCommand::TogglePause, "");
+                self.message = None;
+            }
+            KeyCode::Char('r') => self.toggle_recording(),
+            KeyCode::Char('d') => self.open_picker(),
+            KeyCode::Char('b') => {
+                let n = self.transcript.bookmark(self.elapsed());
+                self.show(
+                    format!("Bookmark {} at {}", n, timestamp(self.elapsed())),
+                    false,
+                );
+            }
+            KeyCode::Char('s') => self.save_transcript(),
+            KeyCode::Up => self.scroll += 1,
+            KeyCode::Down => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Home => self.scroll = usize::MAX,
+            KeyCode::End => self.scroll = 0,
+            _ => {}
+        }
+        true
+    }
+
+    fn quit(&mut self) -> bool {
+        let _ = self.request(SessionCommand::Stop);
+        false
+    }
+
+    fn toggle_recording(&mut self) {
+        match self.status.get("recording").and_then(Value::as_str) {
+            Some(path) => {
+                let done = format!("Recording saved to {}", path);
+                self.command(SessionCommand::StopRecording, done);
+            }
+            None => {
+                let path = self
+                    .options
+                    .recording_dir
+                    .join(format!("recording-{}.wav", unix_time()))
+                    .display()
+                    .to_string();
+                let done = format!("Recording to {}", path);
+                self.command(SessionCommand::StartRecording(path), done);
+            }
+        }
+    }
+
+    fn save_transcript(&mut self) {
+        let path = self
+            .options
+            .recording_dir
+            .join(format!("transcript-{}.txt", unix_time()));
+        match fs::write(&path, self.transcript.to_text()) {
+            Ok(()) => self.show(format!("Transcript saved to {}", path.display()), false),
+            Err(e) => self.show(format!("Failed to save {}: {}", path.display(), e), true),
+        }
+    }
+
+    fn open_picker(&mut self) {
+        let devices = cpal::host_from_id(self.options.host)
+            .map_err(|e| e.to_string())
+            .and_then(|host| device_selector::list_input_devices(&host).map_err(|e| e.to_string()));
+        match devices {
+            Ok(devices) if devices.is_empty() => self.show("No input devices found".into(), true),
+            Ok(devices) => {
+                let current = self.status.get("device").and_then(Value::as_str);
+                let selected = devices
+                    .iter()
+                    .position(|(name, _)| Some(name.as_str()) == current)
+                    .unwrap_or(0);
+                self.picker = Some(DevicePicker {
+                    devices,
+                    state: ListState::default().with_selected(Some(selected)),
+                });
+            }
+            Err(e) => self.show(e, true),
+        }
+    }
+
+    fn handle_picker_key(&mut self, key: KeyEvent) {
+        let Some(ref mut picker) = self.picker else {
+            return;
+        };
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => picker.state.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => picker.state.select_next(),
+            KeyCode::Enter => {
+                let chosen = picker
+                    .state
+                    .selected()
+                    .and_then(|i| picker.devices.get(i))
+                    .cloned();
+                self.picker = None;
+                if let Some((name, id)) = chosen {
+                    let done = format!("Switched to {}", name);
+                    self.command(SessionCommand::SwitchDevice(id.unwrap_or(name)), done);
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('d') => self.picker = None,
+            _ => {}
+        }
+    }
+
+    fn levels(&self) -> Vec<InputLevels> {
+        self.status
+            .get("levels")
+            .and_then(|levels| serde_json::from_value(levels.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let levels = self.levels();
+        let [status_area, transcript_area, meter_area, help_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(levels.len().max(1) as u16 + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(self.status_line(), status_area);
+        self.draw_transcript(frame, transcript_area);
+        draw_meters(frame, meter_area, &levels);
+        frame.render_widget(self.bottom_line(), help_area);
+
+        if let Some(ref mut picker) = self.picker {
+            draw_picker(frame, picker);
+        }
+    }
+
+    fn status_line(&self) -> Line<'static> {
+        let status = &self.status;
+        let separator = || Span::styled(" │ ", Style::new().fg(Color::DarkGray));
+        let mut spans = Vec::new();
+
+        let (state, color) = match status.get("state").and_then(Value::as_str) {
+            Some("paused") => (" PAUSED ", Color::Yellow),
+            Some("reconnecting") => (" RECONNECTING ", Color::Red),
+            _ if status.get("talking").and_then(Value::as_bool) == Some(true) => {
+                (" TALKING ", Color::Green)
+            }
+            _ if status.get("push_to_talk").and_then(Value::as_bool) == Some(true) => {
+                (" PUSH TO TALK ", Color::Blue)
+            }
+            _ => (" LISTENING ", Color::Green),
+        };
+        spans.push(Span::styled(
+            state,
+            Style::new().fg(Color::Black).bg(color).bold(),
+        ));
+        if status.get("recording").and_then(Value::as_str).is_some() {
+            spans.push(Span::styled(
+                " ● REC ",
+                Style::new().fg(Color::White).bg(Color::Red).bold(),
+            ));
+        }
+
+        spans.push(Span::raw(" "));
+        let device = status.get("device").and_then(Value::as_str).unwrap_or("?");
+        spans.push(Span::raw(device.to_string()));
+        if let (Some(rate), Some(channels)) = (
+            status.get("sample_rate").and_then(Value::as_u64),
+            status.get("channels").and_then(Value::as_u64),
+        ) {
+            spans.push(Span::styled(
+                format!(" {} Hz {}ch", rate, channels),
+                Style::new().fg(Color::DarkGray),
+            ));
+        }
+        if let Some(extra) = status.get("extra_inputs").and_then(Value::as_array) {
+            spans.push(Span::raw(format!(" +{} more", extra.len())));
+        }
+
+        spans.push(separator());
+        let model = Path::new(&self.options.model)
+            .file_name()
+            .map_or(self.options.model.clone(), |name| {
+                name.to_string_lossy().into_owned()
+            });
+        spans.push(Span::raw(format!("model {}", model)));
+        spans.push(separator());
+        spans.push(Span::raw(match self.latency {
+            Some(latency) => format!("latency {:.1} s", latency.max(0.0)),
+            None => "latency -".to_string(),
+        }));
+        // More than a chunk waiting means the model isn't keeping up
+        if let Some(backlog) = status.get("backlog_secs").and_then(Value::as_f64)
+            && backlog > 1.0
+        {
+            spans.push(Span::styled(
+                format!(" ({:.1} s behind)", backlog),
+                Style::new().fg(Color::Yellow),
+            ));
+        }
+        spans.push(separator());
+        spans.push(Span::raw(timestamp(self.elapsed())));
+
+        Line::from(spans)
+    }
+
+    fn draw_transcript(&mut self, frame: &mut Frame, area: Rect) {
+        let title = if self.scroll > 0 {
+            " Transcript (scrolled back, End to follow) "
+        } else {
+            " Transcript "
+        };
+        let block = Block::bordered().title(title);
+        let inner = block.inner(area);
+        frame.render_widget(block, area);
+
+        let lines = transcript_lines(&self.transcript, inner.width as usize);
+        let height = inner.height as usize;
+        // Clamp here so Home and over-scrolling stop at the first line
+        self.scroll = self.scroll.min(lines.len().saturating_sub(height));
+        let start = lines.len().saturating_sub(height + self.scroll);
+        let visible: Vec<Line> = lines.into_iter().skip(start).take(height).collect();
+        frame.render_widget(Paragraph::new(visible), inner);
+    }
+
+    fn bottom_line(&self) -> Line<'static> {
+        if let Some((ref message, error, at)) = self.message
+            && at.elapsed() < MESSAGE_DURATION
+        {
+            let style = if error {
+                Style::new().fg(Color::Red)
+            } else {
+                Style::new().fg(Color::Green)
+            };
+            return Line::styled(message.clone(), style);
+        }
+
+        let mut keys = vec![("p", "pause")];
+        if self.options.push_to_talk {
+            keys.push(("space", "talk"));
+        }
+        keys.extend([
+            ("r", "record"),
+            ("d", "device"),
+            ("b", "bookmark"),
+            ("s", "save"),
+            ("↑↓", "scroll"),
+            ("q", "quit"),
+        ]);
+        let spans = keys.into_iter().flat_map(|(key, action)| {
+            [
+                Span::styled(key, Style::new().bold()),
+                Span::styled(format!(" {}  ", action), Style::new().fg(Color::DarkGray)),
+            ]
+        });
+        Line::from(spans.collect::<Vec<_>>())
+    }
+}
+
+/// Wrap the transcript to `width` columns, styling each kind of entry.
+fn transcript_lines(transcript: &Transcript, width: usize) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    for entry in &transcript.entries {
+        let mut prefix = format!("{} ", timestamp(entry.start));
+        if let Some(ref source) = entry.source {
+            prefix.push_str(&format!("[{}] ", source));
+        }
+        let style = match entry.kind {
+            EntryKind::Speech => Style::new(),
+            EntryKind::Gap => Style::new()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::ITALIC),
+            EntryKind::Bookmark => Style::new().fg(Color::Magenta).bold(),
+        };
+        let text = match entry.kind {
+            EntryKind::Speech => entry.text.clone(),
+            _ => format!("── {} ──", entry.text),
+        };
+
+        let prefix_width = Span::raw(prefix.as_str()).width();
+        let wrapped = wrap(&text, width.saturating_sub(prefix_width).max(10));
+        for (i, row) in wrapped.into_iter().enumerate() {
+            let lead = if i == 0 {
+                prefix.clone()
+            } else {
+                " ".repeat(prefix_width)
+            };
+            lines.push(Line::from(vec![
+                Span::styled(lead, Style::new().fg(Color::DarkGray)),
+                Span::styled(row, style),
+            ]));
+        }
+    }
+    lines
+}
+
+/// Greedy word wrap; words longer than a line are split.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut row_width = 0;
+
+    for word in text.split_whitespace() {
+        let word_width = Span::raw(word).width();
+        if row_width > 0 && row_width + 1 + word_width > width {
+            rows.push(std::mem::take(&mut row));
+            row_width = 0;
+        }
+        if word_width > width {
+            for c in word.chars() {
+                if row_width >= width {
+                    rows.push(std::mem::take(&mut row));
+                    row_width = 0;
+                }
+                row.push(c);
+                row_width += 1;
+            }
+            continue;
+        }
+        if row_width > 0 {
+            row.push(' ');
+            row_width += 1;
+        }
+        row.push_str(word);
+        row_width += word_width;
+    }
+    if !row.is_empty() || rows.is_empty() {
+        rows.push(row);
+    }
+    rows
+}
+
+fn draw_meters(frame: &mut Frame, area: Rect, levels: &[InputLevels]) {
+    let block = Block::bordered().title(" Input ");
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let name_width = levels
+        .iter()
+        .map(|l| l.device.chars().count().min(24) as u16 + 1)
+        .max()
+        .unwrap_or(0);
+    for (i, level) in levels.iter().enumerate() {
+        if i as u16 >= inner.height {
+            break;
+        }
+        let row = Rect {
+            y: inner.y + i as u16,
+            height: 1,
+            ..inner
+        };
+        let [name_area, gauge_area, note_area] = Layout::horizontal([
+            Constraint::Length(name_width),
+            Constraint::Fill(3),
+            Constraint::Fill(2),
+        ])
+        .areas(row);
+
+        let name: String = level.device.chars().take(24).collect();
+        frame.render_widget(Paragraph::new(name), name_area);
+
+        let ratio = ((level.rms_dbfs - METER_MIN_DB) / -METER_MIN_DB).clamp(0.0, 1.0);
+        let color = if level.clipping {
+            Color::Red
+        } else if level.peak_dbfs > -6.0 {
+            Color::Yellow
+        } else {
+            Color::Green
+        };
+        let gauge = LineGauge::default()
+            .ratio(ratio as f64)
+            .label(format!("{:>4.0} dBFS", level.rms_dbfs))
+            .filled_style(Style::new().fg(color))
+            .unfilled_style(Style::new().fg(Color::DarkGray));
+        frame.render_widget(gauge, gauge_area);
+
+        let warnings: Vec<String> = level.warnings().iter().map(|w| w.to_string()).collect();
+        let note = if warnings.is_empty() {
+            Line::styled(
+                format!(" peak {:.0} dBFS", level.peak_hold_dbfs),
+                Style::new().fg(Color::DarkGray),
+            )
+        } else {
+            Line::styled(
+                format!(" {}", warnings.join("; ")),
+                Style::new().fg(Color::Red),
+            )
+        };
+        frame.render_widget(Paragraph::new(note), note_area);
+    }
+}
+
+fn draw_picker(frame: &mut Frame, picker: &mut DevicePicker) {
+    let outer = frame.area();
+    let width = (outer.width * 3 / 4).max(20).min(outer.width);
+    let height = (picker.devices.len() as u16 + 2).min(outer.height);
+    let area = Rect {
+        x: outer.x + (outer.width - width) / 2,
+        y: outer.y + (outer.height - height) / 2,
+        width,
+        height,
+    };
+
+    let items: Vec<String> = picker
+        .devices
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect();
+    let list = List::new(items)
+        .block(Block::bordered().title(" Switch input (Enter to pick, Esc to cancel) "))
+        .highlight_style(Style::new().reversed())
+        .highlight_symbol("> ");
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(list, area, &mut picker.state);
+}
+
+/// Session time as `m:ss`, or `h:mm:ss` past an hour.
+fn timestamp(secs: f64) -> String {
+    let secs = secs.max(0.0) as u64;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if h > 0 {
+        format!("{}:{:02}:{:02}", h, m, s)
+    } else {
+        format!("{}:{:02}", m, s)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -1,146 +1,313 @@
-use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{Device, SampleFormat, Stream, StreamConfig};
+use crate::audio_config::ConfigOverrides;
+use crate::device_enumerator::device_name;
+use crate::error::RustscriberError;
+use crate::level_meter::{self, LevelMeter, SharedLevels};
+use crate::reconnect::{Reconnect, Recovery, StreamHealth};
+use crate::sample_convert;
+use crate::transcriber::Input;
+use cpal::traits::StreamTrait;
+use cpal::{Host, Stream, StreamConfig};
 use hound::{WavSpec, WavWriter};
 use std::fs::File;
 use std::io::BufWriter;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-type WavWriterHandle = Arc<Mutex<Option<WavWriter<BufWriter<File>>>>>;
+pub type WavWriterHandle = Arc<Mutex<Option<WavWriter<BufWriter<File>>>>>;
 
 pub struct WavRecorder {
     writer: WavWriterHandle,
+    input: Input,
     stream: Option<Stream>,
+    health: StreamHealth,
+    /// Set while the device is gone
+    outage: Option<Reconnect>,
+    levels: SharedLevels,
 }
 
 impl WavRecorder {
-    pub fn new(
-        filename: &str,
-        device: &Device,
-        config: &StreamConfig,
-        sample_format: SampleFormat,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
-        let spec = WavSpec {
-            channels: config.channels as u16,
-            sample_rate: config.sample_rate,
-            bits_per_sample: 16,
-            sample_format: hound::SampleFormat::Int,
-        };
-
-        let writer = WavWriter::create(filename, spec)?;
+    pub fn new(filename: &str, input: Input) -> Result<Self, RustscriberError> {
+        let writer = create_writer(filename, &input.config)?;
         let writer: WavWriterHandle = Arc::new(Mutex::new(Some(writer)));
 
-        let stream = Self::build_stream(device, config, sample_format, Arc::clone(&writer))?;
+        let levels = level_meter::shared_levels(device_name(&input.device));
+        let health = StreamHealth::default();
+        let stream = Self::build_stream(&input, &health, &levels, Arc::clone(&writer))?;
 
         Ok(Self {
             writer,
+            input,
             stream: Some(stream),
+            health,
+            outage: None,
+            levels,
         })
     }
 
     fn build_stream(
-        device: &Device,
-        config: &StreamConfig,
-        sample_format: SampleFormat,
+        input: &Input,
+        health: &StreamHealth,
+        levels: &SharedLevels,
         writer: WavWriterHandle,
-    ) -> Result<Stream, Box<dyn std::error::Error>> {
-        let err_fn = |err| eprintln!("Stream error: {}", err);
-
-        let stream = match sample_format {
-            SampleFormat::U8 => {
-                let writer_clone = Arc::clone(&writer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[u8], _: &cpal::InputCallbackInfo| {
-                        if let Ok(mut guard) = writer_clone.lock() {
-                            if let Some(ref mut w) = *guard {
-                                for &sample in data {
-                                    let sample_i16 = ((sample as i16) - 128) * 256;
-                                    let _ = w.write_sample(sample_i16);
-                                }
-                            }
-                        }
-                    },
-                    err_fn,
-                    None,
-                )?
-            }
-            SampleFormat::I16 => {
-                let writer_clone = Arc::clone(&writer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[i16], _: &cpal::InputCallbackInfo| {
-                        if let Ok(mut guard) = writer_clone.lock() {
-                            if let Some(ref mut w) = *guard {
-                                for &sample in data {
-                                    let _ = w.write_sample(sample);
-                                }
-                            }
-                        }
-                    },
-                    err_fn,
-                    None,
-                )?
-            }
-            SampleFormat::F32 => {
-                let writer_clone = Arc::clone(&writer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                        if let Ok(mut guard) = writer_clone.lock() {
-                            if let Some(ref mut w) = *guard {
-                                for &sample in data {
-                                    let sample_i16 = (sample * 32767.0) as i16;
-                                    let _ = w.write_sample(sample_i16);
-                                }
-                            }
-                        }
-                    },
-                    err_fn,
-                    None,
-                )?
-            }
-            SampleFormat::I32 => {
-                let writer_clone = Arc::clone(&writer);
-                device.build_input_stream(
-                    config,
-                    move |data: &[i32], _: &cpal::InputCallbackInfo| {
-                        if let Ok(mut guard) = writer_clone.lock() {
-                            if let Some(ref mut w) = *guard {
-                                for &sample in data {
-                                    let sample_i16 = (sample >> 16) as i16;
-                                    let _ = w.write_sample(sample_i16);
-                                }
-                            }
-                        }
-                    },
-                    err_fn,
-                    None,
-                )?
-            }
-            _ => return Err(format!("Unsupported sample format: {:?}", sample_format).into()),
-        };
-
-        Ok(stream)
+    ) -> Result<Stream, RustscriberError> {
+        let mut meter = LevelMeter::new(
+            Arc::clone(levels),
+            input.config.sample_rate,
+            input.config.channels,
+        );
+        sample_convert::build_input_stream(
+            &input.device,
+            &input.config,
+            input.sample_format,
+            health,
+            move |data| {
+                meter.process(data);
+                write_f32(&writer, data);
+            },
+        )
     }
 
-    pub fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn start(&self) -> Result<(), RustscriberError> {
         if let Some(ref stream) = self.stream {
             stream.play()?;
         }
         Ok(())
     }
 
-    pub fn stop_and_finalize(mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// A live handle to the input levels, e.g. for a `LevelMonitor`.
+    pub fn shared_levels(&self) -> SharedLevels {
+        Arc::clone(&self.levels)
+    }
+
+    /// Notice a dead stream, or try to reopen the device (or the default
+    /// one) if an attempt is due. Only a device that records in the same
+    /// format is taken, and the time it was gone is filled with silence.
+    /// Call this regularly while recording.
+    pub fn recover(&mut self, host: &Host, overrides: &ConfigOverrides) -> Option<Recovery> {
+        let Some(mut outage) = self.outage.take() else {
+            let since = self.health.lost_since()?;
+            self.stream.take();
+            self.outage = Some(Reconnect::new(self.input.clone(), since));
+            return Some(Recovery::Lost {
+                device: device_name(&self.input.device),
+            });
+        };
+
+        let change = match outage.attempt(host, overrides, &[]) {
+            Ok(None) => None,
+            Ok(Some(replacement)) => match self.reopen(replacement.input, &outage) {
+                Ok(()) => {
+                    return Some(Recovery::Reconnected {
+                        lost: outage.device_name(),
+                        device: device_name(&self.input.device),
+                        after: outage.since().elapsed(),
+                        fallback: replacement.fallback,
+                    });
+                }
+                Err(error) => Some(error),
+            },
+            Err(error) => Some(error),
+        };
+        let change = change.map(|error| Recovery::Failed {
+            device: outage.device_name(),
+            error,
+        });
+        self.outage = Some(outage);
+        change
+    }
+
+    fn reopen(&mut self, input: Input, outage: &Reconnect) -> Result<(), RustscriberError> {
+        check_format(
+            &self.input.config,
+            &input.config,
+            &device_name(&input.device),
+        )?;
+        let health = StreamHealth::default();
+        let stream = Self::build_stream(&input, &health, &self.levels, Arc::clone(&self.writer))?;
+        bridge_gap(&self.writer, &input.config, outage.since().elapsed())?;
+        stream.play()?;
+
+        self.levels.lock().unwrap().device = device_name(&input.device);
+        self.stream = Some(stream);
+        self.health = health;
+        self.input = input;
+        Ok(())
+    }
+
+    pub fn stop_and_finalize(mut self) -> Result<(), RustscriberError> {
         // Drop the stream first to stop recording
         self.stream.take();
 
         // Finalize the WAV file
-        if let Ok(mut guard) = self.writer.lock() {
-            if let Some(w) = guard.take() {
-                w.finalize()?;
-            }
+        if let Ok(mut guard) = self.writer.lock()
+            && let Some(w) = guard.take()
+        {
+            finalize(w)?;
         }
 
         Ok(())
     }
 }
+
+/// Refuse a replacement `device` whose stream (`config`) can't carry on a
+/// file recorded with `recorded`.
+fn check_format(
+    recorded: &StreamConfig,
+    config: &StreamConfig,
+    device: &str,
+) -> Result<(), RustscriberError> {
+    if (config.channels, config.sample_rate) != (recorded.channels, recorded.sample_rate) {
+        return Err(RustscriberError::ConfigNegotiation(format!(
+            "{} can't record {} channel(s) at {} Hz like the rest of the file",
+            device, recorded.channels, recorded.sample_rate
+        )));
+    }
+    Ok(())
+}
+
+/// Create a 16-bit PCM WAV writer matching the stream's channel count and rate.
+pub fn create_writer(
+    filename: &str,
+    config: &StreamConfig,
+) -> Result<WavWriter<BufWriter<File>>, RustscriberError> {
+    let spec = WavSpec {
+        channels: config.channels,
+        sample_rate: config.sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    WavWriter::create(filename, spec)
+        .map_err(|e| RustscriberError::wav(format!("Failed to create {}", filename), e))
+}
+
+/// Write out the WAV header and any buffered samples.
+pub fn finalize(writer: WavWriter<BufWriter<File>>) -> Result<(), RustscriberError> {
+    writer
+        .finalize()
+        .map_err(|e| RustscriberError::wav("Failed to finish the recording", e))
+}
+
+/// Fill `gap` in the recording with silence before the audio of a stream
+/// with `config` follows. If that stream's channel count or rate differs
+/// from the recording's, the recording is finalized instead. Returns whether
+/// a recording is still going.
+pub fn bridge_gap(
+    writer: &WavWriterHandle,
+    config: &StreamConfig,
+    gap: Duration,
+) -> Result<bool, RustscriberError> {
+    let mut guard = writer.lock().unwrap();
+    let Some(ref mut w) = *guard else {
+        return Ok(false);
+    };
+    let spec = w.spec();
+    if (spec.channels, spec.sample_rate) != (config.channels, config.sample_rate) {
+        if let Some(w) = guard.take() {
+            finalize(w)?;
+        }
+        return Ok(false);
+    }
+
+    let samples = (gap.as_secs_f64() * spec.sample_rate as f64) as u64 * spec.channels as u64;
+    for _ in 0..samples {
+        w.write_sample(0i16)
+            .map_err(|e| RustscriberError::wav("Failed to write the recording", e))?;
+    }
+    Ok(true)
+}
+
+/// Append interleaved f32 samples to the writer, if one is open.
+pub fn write_f32(writer: &WavWriterHandle, data: &[f32]) {
+    if let Ok(mut guard) = writer.lock()
+        && let Some(ref mut w) = *guard
+    {
+        for &sample in data {
+            let _ = w.write_sample(sample_convert::f32_to_i16(sample));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpal::BufferSize;
+    use std::path::{Path, PathBuf};
+
+    fn config(channels: u16, sample_rate: u32) -> StreamConfig {
+        StreamConfig {
+            channels,
+            sample_rate,
+            buffer_size: BufferSize::Default,
+        }
+    }
+
+    fn temp_wav(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rustscriber-{}-{}.wav", name, std::process::id()))
+    }
+
+    fn recording(path: &Path, config: &StreamConfig) -> WavWriterHandle {
+        let writer = create_writer(&path.to_string_lossy(), config).unwrap();
+        Arc::new(Mutex::new(Some(writer)))
+    }
+
+    fn finish(writer: &WavWriterHandle) {
+        if let Some(w) = writer.lock().unwrap().take() {
+            finalize(w).unwrap();
+        }
+    }
+
+    #[test]
+    fn bridges_a_gap_with_silence() {
+        let path = temp_wav("bridge");
+        let stereo = config(2, 8000);
+        let writer = recording(&path, &stereo);
+        write_f32(&writer, &[0.5; 20]);
+        assert!(bridge_gap(&writer, &stereo, Duration::from_millis(250)).unwrap());
+        write_f32(&writer, &[0.5; 20]);
+        finish(&writer);
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        // 10 frames, 2000 frames of silence, 10 frames
+        assert_eq!(reader.duration(), 2020);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert!(samples[20..4020].iter().all(|&s| s == 0));
+        assert!(samples[4020..].iter().all(|&s| s > 0));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn finalizes_instead_of_changing_format() {
+        for (name, next) in [("rate", config(1, 16000)), ("channels", config(2, 8000))] {
+            let path = temp_wav(name);
+            let writer = recording(&path, &config(1, 8000));
+            write_f32(&writer, &[0.5; 80]);
+            assert!(!bridge_gap(&writer, &next, Duration::from_secs(1)).unwrap());
+            assert!(writer.lock().unwrap().is_none());
+            // Nothing more is written, and the file is complete
+            write_f32(&writer, &[0.5; 80]);
+            assert_eq!(hound::WavReader::open(&path).unwrap().duration(), 80);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_finished_recording_has_no_gap_to_bridge() {
+        let writer: WavWriterHandle = Arc::new(Mutex::new(None));
+        assert!(!bridge_gap(&writer, &config(1, 8000), Duration::from_secs(1)).unwrap());
+    }
+
+    #[test]
+    fn reopening_needs_the_same_format() {
+        let recorded = config(2, 48000);
+        assert!(check_format(&recorded, &config(2, 48000), "mic").is_ok());
+        let error = check_format(&recorded, &config(1, 48000), "mic").unwrap_err();
+        assert_eq!(error.exit_code(), 4);
+        assert!(
+            error
+                .to_string()
+                .starts_with("mic can't record 2 channel(s) at 48000 Hz")
+        );
+        assert!(check_format(&recorded, &config(2, 44100), "mic").is_err());
+    }
+}